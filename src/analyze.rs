@@ -0,0 +1,264 @@
+use std::cmp::Reverse;
+
+use clap::ValueEnum;
+
+use crate::arena;
+use crate::config::{CornerRule, Deposit, DepositAt, Dynamics, Material, OnCycle, Reduction, TrailFull, TrailSides};
+use crate::dump::Dump;
+use crate::symmetry;
+
+// The most walls the balls ended against that are listed, the others are counted
+const MAX_LISTED_WALLS: usize = 6;
+
+
+// Print summary statistics of the accumulated canvas of a dump
+pub fn print_summary(dump: &Dump)
+{
+    let header = &dump.header;
+    let canvas = &symmetry::image_canvas(&dump.canvas, &header.config);
+
+    let mut hit_values: Vec<f64> = canvas.iter().copied().filter(|v| *v != 0.0).collect();
+    hit_values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    // So an empty canvas has 0 in total, not the -0 the sum of nothing is
+    let total: f64 = hit_values.iter().sum::<f64>() + 0.0;
+    let percentile = |p: f64| -> f64 {
+        if hit_values.is_empty() {
+            return 0.0;
+        }
+        hit_values[((hit_values.len() - 1) as f64 * p).round() as usize]
+    };
+
+    // Weighted center of the accumulated values, in canvas coordinates
+    let mut center_x = 0.0;
+    let mut center_y = 0.0;
+    for (i, v) in canvas.iter().enumerate() {
+        center_x += v * ((i % canvas.width) as f64 + 0.5) / canvas.width as f64;
+        center_y += v * ((i / canvas.width) as f64 + 0.5) / canvas.height as f64;
+    }
+
+    println!("canvas:             {} x {}", header.width, header.height);
+    println!("arena:              {}", arena::describe(&header.config.arena));
+    match (&header.config.shader_script, &header.config.shader_expr) {
+        (Some(path), _) => println!("shader:             script {}", path.display()),
+        (None, Some(expr)) => println!("shader:             {}", expr),
+        (None, None) => println!("shader:             {}", header.config.shader),
+    }
+    if header.config.shader_script.is_none() && header.config.shader_expr.is_none() && header.config.shader == "path-power" {
+        println!("shader exponent:    {}", header.config.shader_exponent);
+    }
+    if !header.config.extra_shaders.is_empty() {
+        println!("extra shaders:      {}", header.config.extra_shaders.join(", "));
+    }
+    match header.config.deposit {
+        Deposit::End => {}
+        Deposit::Trail => println!("deposit:            along the trail"),
+        Deposit::Bounces => println!("deposit:            at the bounces, weighted with bounce index^{}", header.config.bounce_exponent),
+    }
+    match header.config.deposit_at {
+        DepositAt::Termination => {}
+        DepositAt::Start => println!("deposit at:         where the balls started"),
+        DepositAt::Both => println!("deposit at:         where the balls started and ended"),
+    }
+    match header.config.reduction {
+        Reduction::Sum => {}
+        Reduction::Min => println!("reduction:          the smallest weight in every pixel"),
+        Reduction::Max => println!("reduction:          the largest weight in every pixel"),
+        Reduction::Mean => println!("reduction:          the mean weight in every pixel"),
+    }
+    if let Some(color) = &header.config.color {
+        println!("color:              {}", color);
+    }
+    if let Some(bins) = header.config.direction_bins {
+        println!("direction bins:     {}", bins);
+    }
+    if !header.config.bounce_bands.is_empty() {
+        println!("bounce bands:       {}", band_names(&header.config.bounce_bands).join(", "));
+    }
+    if header.config.output.hit_counts {
+        println!("hit counts:         in the -hits dump");
+    }
+    if header.config.output.exit_map {
+        println!("exit map:           in the -exits dump");
+    }
+    if header.config.output.vector_field {
+        println!("vector field:       in the -vectors tiff");
+    }
+    if let Some(arrival) = header.config.first_arrival {
+        println!("first arrival:      smallest {} in every pixel, in the -arrival dump", format!("{:?}", arrival).to_lowercase());
+    }
+    if let Some(wavenumber) = header.config.wavenumber {
+        let image = format!("{:?}", header.config.output.phase_image).to_lowercase();
+        println!("phase:              wavenumber {}, the image shows the {}", wavenumber, image);
+    }
+    if header.config.dynamics == Dynamics::Outer {
+        println!("outer billiards:    table scale {}", header.config.table_scale);
+    }
+    if header.config.dynamics == Dynamics::Solid {
+        let camera = &header.config.solid.camera;
+        let distance = camera.distance.map_or("orthographic".to_string(), |d| format!("from {}", d));
+        let polyhedron = header.config.solid.polyhedron.to_possible_value().unwrap();
+        println!("solid:              {}, camera at {} x {} degrees, {}", polyhedron.get_name(), camera.yaw, camera.pitch, distance);
+    }
+    if header.config.reflection_jitter > 0.0 {
+        println!("reflection jitter:  {} degrees", header.config.reflection_jitter);
+    }
+    if let Some(tumble) = &header.config.tumble {
+        println!("tumbles:            {}", tumble.describe());
+    }
+    if header.config.gravity != [0.0, 0.0] {
+        println!("gravity:            {} x {}, steps of {}", header.config.gravity[0], header.config.gravity[1], header.config.flight_step);
+    }
+    if let Some(radius) = header.config.larmor_radius {
+        println!("larmor radius:      {}, steps of {}", radius, header.config.flight_step);
+    }
+    if header.config.frame_rotation != 0.0 {
+        println!("frame rotation:     {} turns around ({}, {}), steps of {}", header.config.frame_rotation,
+                 header.config.frame_center[0], header.config.frame_center[1], header.config.flight_step);
+    }
+    if header.config.restitution < 1.0 {
+        println!("restitution:        {}, stops below {}", header.config.restitution, header.config.min_energy);
+    }
+    if let Some(restitution) = header.config.trail_restitution {
+        println!("trail restitution:  {}, stops below {}", restitution, header.config.min_energy);
+    }
+    if header.config.deceleration > 0.0 {
+        println!("deceleration:       {}", header.config.deceleration);
+    }
+    if header.config.bounce_speedup != 1.0 {
+        println!("bounce speedup:     {}", header.config.bounce_speedup);
+    }
+    if header.config.ball_radius > 0.0 {
+        println!("ball radius:        {}", header.config.ball_radius);
+    }
+    if header.config.balls > 1 {
+        println!("balls:              {} at the same time", header.config.balls);
+    }
+    if header.config.branching > 0.0 {
+        println!("branching:          {}% of the bounces, at most {} times", header.config.branching * 100.0, header.config.max_branches);
+    }
+    match header.config.trail_full {
+        TrailFull::Trap => {}
+        TrailFull::Forget => println!("full trail:         forgets its oldest segments"),
+        TrailFull::Grow => println!("full trail:         grows without a limit"),
+    }
+    if let Some(bounces) = header.config.max_bounces {
+        println!("max. bounces:       {}", bounces);
+    }
+    if let Some(length) = header.config.max_path_length {
+        println!("max. path length:   {}", length);
+    }
+    if let Some(radius) = header.config.return_radius {
+        println!("return radius:      {}", radius);
+    }
+    if let Some(memory) = header.config.trail_memory {
+        println!("trail memory:       {} bounces", memory);
+    }
+    if let Some(window) = header.config.trail_window {
+        println!("trail window:       {} segments", window);
+    }
+    match header.config.trail_material {
+        Material::Reflect => {}
+        Material::Absorb => println!("trail:              absorbs"),
+        Material::Transmit(p) => println!("trail:              transmits {}%", p * 100.0),
+        Material::Rough(r) => println!("trail:              {}% diffuse", r * 100.0),
+        Material::Sticky(d) => println!("trail:              sticky for {}", d),
+    }
+    match header.config.corner_rule {
+        CornerRule::Nearest => {}
+        CornerRule::Reverse => println!("corners:            reverse the ball, within {}", header.config.corner_tolerance),
+        CornerRule::Bisector => println!("corners:            reflect across the bisector, within {}", header.config.corner_tolerance),
+    }
+    if header.config.reflection_offset != 0.0001 {
+        println!("reflection offset:  {}", header.config.reflection_offset);
+    }
+    if header.config.robust_predicates {
+        println!("robust predicates:  yes");
+    }
+    match header.config.on_cycle {
+        None => {}
+        Some(OnCycle::Stop) => println!("periodic orbits:    stop, within {} over {} bounces",
+                                        header.config.cycle_tolerance, header.config.cycle_window),
+        Some(OnCycle::Perturb(degrees)) => println!("periodic orbits:    perturbed by {} degrees, within {} over {} bounces",
+                                                    degrees, header.config.cycle_tolerance, header.config.cycle_window),
+    }
+    match header.config.trail_sides {
+        TrailSides::Both => {}
+        TrailSides::Left => println!("trail blocks from:  the left only"),
+        TrailSides::Right => println!("trail blocks from:  the right only"),
+    }
+    if header.config.trail_width > 0.0 {
+        println!("trail width:        {}", header.config.trail_width);
+    }
+    if let Some(wind) = &header.config.wind {
+        println!("wind:               {}, steps of {}", wind.describe(), header.config.flight_step);
+    }
+    if let Some(potential) = &header.config.potential {
+        println!("potential:          {}, steps of {}", potential.describe(), header.config.flight_step);
+    }
+    println!("simulations:        {}", header.simulations);
+    if let Some(tally) = &header.tally {
+        let share = |n: usize| 100.0 * n as f64 / tally.balls().max(1) as f64;
+        let endings = [("trapped:           ", tally.trapped), ("escaped:           ", tally.escaped),
+                       ("absorbed:          ", tally.absorbed), ("stopped:           ", tally.stopped),
+                       ("max. bounces hit:  ", tally.bounce_limit), ("max. path reached: ", tally.path_limit),
+                       ("returned:          ", tally.returned), ("periodic:          ", tally.periodic)];
+        for (label, n) in endings {
+            if n > 0 {
+                println!("{} {} ({:.2}%)", label, n, share(n));
+            }
+        }
+        println!("mean bounces:       {:.3}", tally.bounces as f64 / tally.balls().max(1) as f64);
+        // The walls most balls ended against, by index, those of a solid are its faces
+        let wall = if header.config.dynamics == Dynamics::Solid { "face" } else { "wall" };
+        let mut walls: Vec<(usize, usize)> = tally.wall_ends.iter().copied().enumerate().filter(|(_, n)| *n > 0).collect();
+        walls.sort_by_key(|(_, n)| Reverse(*n));
+        let mut contacts: Vec<String> = walls.iter().take(MAX_LISTED_WALLS)
+            .map(|(i, n)| format!("{} {} {:.2}%", wall, i, share(*n)))
+            .collect();
+        if walls.len() > MAX_LISTED_WALLS {
+            contacts.push(format!("{} more {}s", walls.len() - MAX_LISTED_WALLS, wall));
+        }
+        if tally.trail_ends > 0 {
+            contacts.push(format!("trail {:.2}%", share(tally.trail_ends)));
+        }
+        if !contacts.is_empty() {
+            println!("ended against:      {}", contacts.join(", "));
+        }
+    }
+    println!("pixels hit:         {} ({:.2}%)", hit_values.len(), 100.0 * hit_values.len() as f64 / canvas.data.len() as f64);
+    println!("total value:        {}", total);
+    println!("value / simulation: {}", total / header.simulations as f64);
+    println!("min. hit pixel:     {}", percentile(0.0));
+    println!("median hit pixel:   {}", percentile(0.5));
+    println!("99th pct. hit px.:  {}", percentile(0.99));
+    println!("max. pixel:         {}", percentile(1.0));
+    if total != 0.0 {
+        println!("center of mass:     ({:.4}, {:.4})", center_x / total, center_y / total);
+    }
+    // How the weight is shared by the directions, the first bin starts at the x axis, or
+    // by the bounce bands
+    if let (Some(bins), Some(n), true) = (&dump.bins, header.config.bins(), total != 0.0) {
+        let shares: Vec<String> = (0..n)
+            .map(|k| format!("{:.1}%", 100.0 * bins.data.iter().map(|v| v[k]).sum::<f64>() / total))
+            .collect();
+        match header.config.direction_bins {
+            Some(_) => println!("directions:         {}", shares.join(" ")),
+            None => println!("bands:              {}", shares.join(" ")),
+        }
+    }
+}
+
+
+// The bounce counts of every band, like 6-20, the last band has all bounce counts above
+fn band_names(bands: &[usize]) -> Vec<String>
+{
+    let mut least = 0;
+    let mut names: Vec<String> = bands.iter().map(|most| {
+        let name = format!("{}-{}", least, most);
+        least = most + 1;
+        name
+    }).collect();
+    names.push(format!("{}+", least));
+    names
+}
@@ -0,0 +1,44 @@
+use crate::config::{Morph, RunConfig};
+
+
+// The values the last frame of an animation goes to, the ones that are not set stay
+// as they are in the first frame
+pub struct LastFrame {
+    pub edges: Option<usize>,
+    pub size: Option<f64>,
+    pub rotation: Option<f64>,
+}
+
+
+// The configs of all frames, interpolated from `first` to `last`. Fractional edge
+// counts morph between the polygons with the edge counts around them.
+pub fn frame_configs(first: &RunConfig, last: &LastFrame, frames: usize) -> Result<Vec<RunConfig>, String>
+{
+    if frames == 0 {
+        return Err("an animation needs at least one frame".to_string());
+    }
+    let lerp = |from: f64, to: f64, t: f64| from + (to - from) * t;
+
+    (0..frames)
+        .map(|i| {
+            let t = if frames == 1 { 0.0 } else { i as f64 / (frames - 1) as f64 };
+            let mut config = first.clone();
+
+            if let Some(edges) = last.edges {
+                let edges = lerp(first.arena.edges as f64, edges as f64, t);
+                let fraction = edges - edges.floor();
+                config.arena.edges = edges.floor() as usize;
+                config.arena.morph = (fraction > 0.0).then(|| Morph { edges: config.arena.edges + 1, t: fraction });
+            }
+            if let Some(size) = last.size {
+                config.arena.size = lerp(first.arena.size, size, t);
+            }
+            if let Some(rotation) = last.rotation {
+                config.arena.transform.rotation = lerp(first.arena.transform.rotation, rotation, t);
+            }
+
+            config.validate().map_err(|e| format!("frame {}: {}", i, e))?;
+            Ok(config)
+        })
+        .collect()
+}
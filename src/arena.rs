@@ -0,0 +1,874 @@
+use std::f64::consts::PI;
+
+use geo::{Coord, coord, EuclideanDistance, Line, Vector2DOps};
+use rand::prelude::*;
+
+use crate::config::{ArenaConfig, ArenaKind, ArenaVariation, Material, ObstacleShape, Transform};
+use crate::scene::{Boundary, rotate, Scene, Shape, Wall};
+use crate::parametric::curve_outline;
+use crate::shape::outline_edges;
+use crate::simulation::angle;
+
+
+pub const MAX_MAZE_SIZE: usize = 256;
+
+pub const MAX_VIEWPORT_SIZE: f64 = 5.0;
+
+pub const MAX_COMPOSITE: usize = 16;
+
+
+// The walls a generator makes: the outlines of the arena, and loose walls that do not
+// close an outline, like those inside mazes
+#[derive(Default)]
+pub struct Generated {
+    pub walls: Vec<Shape>,
+    pub loose_walls: Vec<Shape>,
+}
+
+
+// Makes the walls of an arena from its config, and those of random arenas from `rng`.
+// Every kind of arena has its generator, see generator(), a new kind of random arena
+// only needs one of its own.
+pub trait ArenaGenerator {
+    fn generate(&self, config: &ArenaConfig, rng: &mut StdRng) -> Generated;
+}
+
+
+// The outline of the arena, see boundary()
+struct Outline;
+
+
+impl ArenaGenerator for Outline {
+    fn generate(&self, config: &ArenaConfig, rng: &mut StdRng) -> Generated
+    {
+        Generated { walls: boundary(config, rng), loose_walls: Vec::new() }
+    }
+}
+
+
+// A convex polygon with random corners, and random scatterers inside it
+struct RandomPolygon;
+
+
+impl ArenaGenerator for RandomPolygon {
+    fn generate(&self, config: &ArenaConfig, rng: &mut StdRng) -> Generated
+    {
+        let (center, radius) = (center_of(config), config.size / 2.0);
+        let outline = random_polygon(center, radius, config.edges, rng);
+        let mut walls: Vec<Shape> = outline_edges(&outline).map(Shape::Segment).collect();
+        walls.extend(random_scatterers(&walls, center, radius, config.random_scatterers, rng));
+        Generated { walls, loose_walls: Vec::new() }
+    }
+}
+
+
+// A random maze in a square
+struct Maze;
+
+
+impl ArenaGenerator for Maze {
+    fn generate(&self, config: &ArenaConfig, rng: &mut StdRng) -> Generated
+    {
+        let walls = boundary(config, rng);
+        Generated { walls, loose_walls: maze(center_of(config), config.size, config.maze_size, rng) }
+    }
+}
+
+
+// Random segments in the viewport of an open arena
+struct RandomSegments;
+
+
+impl ArenaGenerator for RandomSegments {
+    fn generate(&self, config: &ArenaConfig, rng: &mut StdRng) -> Generated
+    {
+        Generated { walls: Vec::new(), loose_walls: random_segments(config, rng) }
+    }
+}
+
+
+// The arenas of a generator laid over each other, each generated where the random
+// numbers of the one before left off
+struct Composite {
+    generator: Box<dyn ArenaGenerator>,
+    count: usize,
+}
+
+
+impl ArenaGenerator for Composite {
+    fn generate(&self, config: &ArenaConfig, rng: &mut StdRng) -> Generated
+    {
+        let mut composite = Generated::default();
+        for _ in 0..self.count {
+            let generated = self.generator.generate(config, rng);
+            composite.walls.extend(generated.walls);
+            composite.loose_walls.extend(generated.loose_walls);
+        }
+        composite
+    }
+}
+
+
+// The generator of the kind of arena, composited when the config asks for it
+fn generator(config: &ArenaConfig) -> Box<dyn ArenaGenerator>
+{
+    let generator: Box<dyn ArenaGenerator> = match config.kind {
+        ArenaKind::Random => Box::new(RandomPolygon),
+        ArenaKind::Maze => Box::new(Maze),
+        ArenaKind::Open => Box::new(RandomSegments),
+        _ => Box::new(Outline),
+    };
+    match config.composite {
+        0 | 1 => generator,
+        count => Box::new(Composite { generator, count }),
+    }
+}
+
+
+// The walls of the arena, with the scatterers and obstacles inside it, and the
+// openings in them. Random arenas are generated from `seed`, see arena_seed().
+pub fn initial_arena(config: &ArenaConfig, seed: u64) -> Scene
+{
+    // A saved scene already has everything, as it was generated
+    if let (ArenaKind::Scene, Some(scene)) = (config.kind, &config.scene) {
+        return scene.to_scene();
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let with_material = |material: Material| move |shape: Shape| Wall::new(shape, material);
+    let arena_wall = |shape: Shape| Wall::new(transform(shape, config), config.material);
+    let generator = generator(config);
+    let generated = generator.generate(config, &mut rng);
+    let mut walls: Vec<Wall> = generated.walls.into_iter().map(arena_wall).collect();
+    for scale in &config.shells {
+        walls.extend(generator.generate(&shell_config(config, *scale), &mut rng).walls.into_iter().map(arena_wall));
+    }
+    let mut loose_walls: Vec<Wall> = generated.loose_walls.into_iter().map(arena_wall).collect();
+
+    for scatterer in &config.scatterers {
+        let center = coord! {x: scatterer.center[0], y: scatterer.center[1]};
+        walls.push(Wall::new(Shape::Circle { center, radius: scatterer.radius }, scatterer.material));
+    }
+    for obstacle in &config.obstacles {
+        match &obstacle.shape {
+            ObstacleShape::Segment([start, end]) => {
+                let line = Line::new(coord! {x: start[0], y: start[1]}, coord! {x: end[0], y: end[1]});
+                loose_walls.push(Wall::new(Shape::Segment(line), obstacle.material));
+            }
+            ObstacleShape::Polygon(vertices) => {
+                let coords: Vec<Coord> = vertices.iter().map(|v| coord! {x: v[0], y: v[1]}).collect();
+                walls.extend(outline_edges(&coords).map(Shape::Segment).map(with_material(obstacle.material)));
+            }
+            ObstacleShape::Arc { center, radius, start, sweep } => {
+                let arc = Shape::Arc { center: coord! {x: center[0], y: center[1]}, radii: coord! {x: *radius, y: *radius},
+                                       angle: 0.0, start: start.to_radians(), sweep: sweep.to_radians() };
+                loose_walls.push(Wall::new(arc, obstacle.material));
+            }
+        }
+    }
+
+    let openings = config.openings.iter()
+        .map(|opening| (coord! {x: opening.center[0], y: opening.center[1]}, opening.radius))
+        .collect();
+
+    let mut scene = Scene::new(walls, loose_walls, openings);
+    scene.regions = config.regions.iter()
+        .map(|region| (region.polygon.iter().map(|v| coord! {x: v[0], y: v[1]}).collect(), region.index))
+        .collect();
+    scene.moving_walls = config.moving_walls.clone();
+    scene.boundary = match config.kind {
+        ArenaKind::Torus => Boundary::Periodic,
+        ArenaKind::Open => {
+            let [lo, hi] = config.viewport;
+            Boundary::Viewport(coord! {x: lo[0], y: lo[1]}, coord! {x: hi[0], y: hi[1]})
+        }
+        _ => Boundary::Walls
+    };
+    scene
+}
+
+
+// The seed to generate the arena of a worker with. With variation, every run, or
+// every worker of a run, generates a different random arena.
+pub fn arena_seed(config: &ArenaConfig, run_seed: u64, worker: u64) -> u64
+{
+    match config.variation {
+        ArenaVariation::None => config.seed,
+        ArenaVariation::Run => config.seed.wrapping_add(run_seed),
+        ArenaVariation::Worker => config.seed.wrapping_add(run_seed).wrapping_add(worker),
+    }
+}
+
+
+fn center_of(config: &ArenaConfig) -> Coord
+{
+    coord! {x: config.center[0], y: config.center[1]}
+}
+
+
+// The config of a smaller copy of the arena, with the same center
+fn shell_config(config: &ArenaConfig, scale: f64) -> ArenaConfig
+{
+    ArenaConfig {
+        size: config.size * scale,
+        corner_radius: config.corner_radius * scale,
+        shells: Vec::new(),
+        ..config.clone()
+    }
+}
+
+
+// The first two outlines that cross, of the boundary, at index 0, and the shells
+// after it. Curves are checked along segments between points on them.
+pub fn crossing_shells(config: &ArenaConfig) -> Option<(usize, usize)>
+{
+    const CURVE_POINTS: usize = 256;
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let generator = generator(config);
+    let outlines: Vec<Vec<Shape>> = [1.0].iter().chain(&config.shells)
+        .map(|scale| generator.generate(&shell_config(config, *scale), &mut rng).walls)
+        .collect();
+
+    for (j, outline) in outlines.iter().enumerate().skip(1) {
+        let walls: Vec<Wall> = outline.iter().map(|shape| Wall::new(*shape, Material::Reflect)).collect();
+        let scene = Scene::new(walls, Vec::new(), Vec::new());
+        for (i, other) in outlines[..j].iter().enumerate() {
+            let crosses = other.iter().any(|shape| {
+                let points = shape.points(CURVE_POINTS);
+                points.windows(2).any(|p| scene.test_ball(Line::new(p[0], p[1])).is_some())
+            });
+            if crosses {
+                return Some((i, j));
+            }
+        }
+    }
+    None
+}
+
+
+// Map a generated shape through the transform of the arena. Curved walls are all
+// still axis aligned here, so stretching them only changes their radii.
+fn transform(shape: Shape, config: &ArenaConfig) -> Shape
+{
+    let t = &config.transform;
+    if *t == Transform::default() {
+        return shape;
+    }
+
+    let center = center_of(config);
+    let rotation = t.rotation.to_radians();
+    let stretch = |v: Coord| coord! {x: v.x * t.anisotropy[0], y: v.y * t.anisotropy[1]};
+    let point = |p: Coord| center + coord! {x: t.offset[0], y: t.offset[1]} + rotate(stretch(p - center), rotation) * t.scale;
+
+    match shape {
+        Shape::Segment(line) => Shape::Segment(Line::new(point(line.start), point(line.end))),
+        Shape::Circle { center, radius } if t.anisotropy[0] == t.anisotropy[1] => {
+            Shape::Circle { center: point(center), radius: radius * t.anisotropy[0] * t.scale }
+        }
+        Shape::Circle { center, radius } => {
+            Shape::Ellipse { center: point(center), radii: stretch(coord! {x: radius, y: radius}) * t.scale, angle: rotation }
+        }
+        Shape::Ellipse { center, radii, angle } => {
+            Shape::Ellipse { center: point(center), radii: stretch(radii) * t.scale, angle: angle + rotation }
+        }
+        Shape::Arc { center, radii, angle, start, sweep } => {
+            Shape::Arc { center: point(center), radii: stretch(radii) * t.scale, angle: angle + rotation, start, sweep }
+        }
+    }
+}
+
+
+fn boundary(config: &ArenaConfig, rng: &mut StdRng) -> Vec<Shape>
+{
+    let center = center_of(config);
+    let radius = config.size / 2.0;
+
+    match config.kind {
+        ArenaKind::Polygon => {
+            if config.corner_radius > 0.0 {
+                return round_corners(&polygon_outline(config), config.corner_radius);
+            }
+            if let Some(morph) = &config.morph {
+                let from = regular_polygon(center, radius, config.edges);
+                let to = regular_polygon(center, radius, morph.edges);
+                return outline_edges(&morph_outline(&from, &to, morph.t)).map(Shape::Segment).collect();
+            }
+
+            let mut walls: Vec<Shape> = Vec::new();
+
+            for i in 0..config.edges {
+                let angle0 = (i as f64)         * 2.0 * PI / (config.edges as f64);
+                let angle1 = ((i as f64) + 1.0) * 2.0 * PI / (config.edges as f64);
+
+                walls.push(Shape::Segment(Line::new(center + angle(angle0) * radius,
+                                                    center + angle(angle1) * radius)));
+            }
+
+            walls
+        }
+        ArenaKind::Star => outline_edges(&star(center, radius, config.edges, config.step)).map(Shape::Segment).collect(),
+        ArenaKind::Circle => {
+            vec![Shape::Circle { center, radius }]
+        }
+        ArenaKind::Ellipse => {
+            let minor = radius * (1.0 - config.eccentricity * config.eccentricity).sqrt();
+            vec![Shape::Ellipse { center, radii: coord! {x: radius, y: minor}, angle: 0.0 }]
+        }
+        ArenaKind::Stadium => stadium(center, config.size, config.aspect_ratio),
+        ArenaKind::Mushroom => mushroom(center, config.size, config.stem_width, config.stem_height),
+        ArenaKind::Lens => lens(center, config.size, config.disc_radii, config.disc_separation, false),
+        ArenaKind::Lune => lens(center, config.size, config.disc_radii, config.disc_separation, true),
+        ArenaKind::Custom => {
+            let outline = flip_y(&config.vertices);
+            fit(center, config.size, &[outline]).iter().flat_map(|o| outline_edges(o)).map(Shape::Segment).collect()
+        }
+        ArenaKind::Parametric => match &config.curve {
+            Some(curve) => {
+                let outline = curve_outline(curve, config.curve_resolution);
+                fit(center, config.size, &[outline]).iter().flat_map(|o| outline_edges(o)).map(Shape::Segment).collect()
+            }
+            None => Vec::new(),
+        },
+        ArenaKind::Mask => {
+            fit(center, config.size, &config.contours).iter().flat_map(|o| outline_edges(o)).map(Shape::Segment).collect()
+        }
+        ArenaKind::Koch | ArenaKind::Cesaro | ArenaKind::Rough => {
+            outline_edges(&fractal_outline(config, rng)).map(Shape::Segment).collect()
+        }
+        ArenaKind::Maze => {
+            let corners = [coord! {x: -radius, y: -radius}, coord! {x: radius, y: -radius},
+                           coord! {x: radius, y: radius}, coord! {x: -radius, y: radius}];
+            outline_edges(&corners.map(|c| center + c)).map(Shape::Segment).collect()
+        }
+        // random arenas have generators of their own, the others no outline
+        ArenaKind::Random | ArenaKind::Torus | ArenaKind::Open | ArenaKind::Scene => Vec::new(),
+    }
+}
+
+
+// The number of random segments in the viewport of an open arena
+pub fn open_segment_count(config: &ArenaConfig) -> usize
+{
+    let [lo, hi] = config.viewport;
+    (config.segment_density * (hi[0] - lo[0]) * (hi[1] - lo[1])).round() as usize
+}
+
+
+// Segments with random centers in the viewport, and random directions
+fn random_segments(config: &ArenaConfig, rng: &mut StdRng) -> Vec<Shape>
+{
+    let [lo, hi] = config.viewport;
+    let half = config.segment_length / 2.0;
+
+    (0..open_segment_count(config))
+        .map(|_| {
+            let center = coord! {x: rng.gen_range(lo[0] .. hi[0]), y: rng.gen_range(lo[1] .. hi[1])};
+            let dir = angle(rng.gen_range(0.0 .. PI));
+            Shape::Segment(Line::new(center - dir * half, center + dir * half))
+        })
+        .collect()
+}
+
+
+// The ranges of consecutive indices below n for which `within` is true, the ends excluded
+fn runs(n: usize, within: impl Fn(usize) -> bool) -> Vec<(usize, usize)>
+{
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < n {
+        if within(i) {
+            let start = i;
+            while i < n && within(i) {
+                i += 1;
+            }
+            ranges.push((start, i));
+        } else {
+            i += 1;
+        }
+    }
+    ranges
+}
+
+
+// The walls inside a square maze of n by n cells, carved by a randomized depth
+// first search, so there is exactly one path between any two cells. Walls in a
+// line are merged into one.
+fn maze(center: Coord, size: f64, n: usize, rng: &mut StdRng) -> Vec<Shape>
+{
+    // right[x + n * y]: wall between cell (x, y) and (x + 1, y), down: between (x, y) and (x, y + 1)
+    let mut right = vec![true; n * n];
+    let mut down = vec![true; n * n];
+    let mut visited = vec![false; n * n];
+
+    let mut stack: Vec<(usize, usize)> = vec![(0, 0)];
+    visited[0] = true;
+    while let Some(&(x, y)) = stack.last() {
+        let mut neighbours: Vec<(usize, usize)> = Vec::new();
+        if x > 0 { neighbours.push((x - 1, y)); }
+        if x + 1 < n { neighbours.push((x + 1, y)); }
+        if y > 0 { neighbours.push((x, y - 1)); }
+        if y + 1 < n { neighbours.push((x, y + 1)); }
+        neighbours.retain(|(nx, ny)| !visited[nx + n * ny]);
+
+        match neighbours.choose(rng) {
+            Some(&(nx, ny)) => {
+                if nx > x {
+                    right[x + n * y] = false;
+                } else if nx < x {
+                    right[nx + n * ny] = false;
+                } else if ny > y {
+                    down[x + n * y] = false;
+                } else {
+                    down[nx + n * ny] = false;
+                }
+                visited[nx + n * ny] = true;
+                stack.push((nx, ny));
+            }
+            None => {
+                stack.pop();
+            }
+        }
+    }
+
+    let cell = size / n as f64;
+    let corner = |x: usize, y: usize| center + coord! {x: (x as f64 - n as f64 / 2.0) * cell, y: (y as f64 - n as f64 / 2.0) * cell};
+    let mut walls: Vec<Shape> = Vec::new();
+
+    // the horizontal walls below every row, and the vertical walls right of every column
+    for line in 0..n.saturating_sub(1) {
+        for (start, end) in runs(n, |i| down[i + n * line]) {
+            walls.push(Shape::Segment(Line::new(corner(start, line + 1), corner(end, line + 1))));
+        }
+        for (start, end) in runs(n, |i| right[line + n * i]) {
+            walls.push(Shape::Segment(Line::new(corner(line + 1, start), corner(line + 1, end))));
+        }
+    }
+
+    walls
+}
+
+
+// A convex polygon, with its corners at random angles on a circle, one in each of
+// `edges` equal sectors, so they do not bunch up into a sliver. They are drawn again
+// until no two corners are half the circle apart, so the center is inside.
+fn random_polygon(center: Coord, radius: f64, edges: usize, rng: &mut StdRng) -> Vec<Coord>
+{
+    let sector = 2.0 * PI / (edges as f64);
+    loop {
+        let angles: Vec<f64> = (0..edges).map(|i| (i as f64 + rng.gen::<f64>()) * sector).collect();
+        let widest = angles.windows(2).map(|a| a[1] - a[0]).fold(angles[0] + 2.0 * PI - angles[edges - 1], f64::max);
+        if widest < PI {
+            return angles.iter().map(|a| center + angle(*a) * radius).collect();
+        }
+    }
+}
+
+
+// Circles that fit inside the polygon `walls` and do not overlap each other. Gives
+// up on a circle after a number of failed attempts, so there can be fewer.
+fn random_scatterers(walls: &[Shape], center: Coord, radius: f64, count: usize, rng: &mut StdRng) -> Vec<Shape>
+{
+    const ATTEMPTS: usize = 100;
+
+    let outline_walls = walls.iter().map(|shape| Wall::new(*shape, Material::Reflect)).collect();
+    let outline = Scene::new(outline_walls, Vec::new(), Vec::new());
+    let mut circles: Vec<(Coord, f64)> = Vec::new();
+
+    for _ in 0..count {
+        for _ in 0..ATTEMPTS {
+            let c = center + coord! {x: rng.gen_range(-radius .. radius), y: rng.gen_range(-radius .. radius)};
+            let r = radius * rng.gen_range(0.05 .. 0.2);
+
+            let fits = outline.contains(c)
+                && walls.iter().all(|shape| !matches!(shape, Shape::Segment(line) if c.euclidean_distance(line) < r))
+                && circles.iter().all(|(other, other_r)| (c - *other).magnitude() > r + other_r);
+            if fits {
+                circles.push((c, r));
+                break;
+            }
+        }
+    }
+
+    circles.into_iter().map(|(center, radius)| Shape::Circle { center, radius }).collect()
+}
+
+
+// The corners of a regular polygon, counter clockwise
+fn regular_polygon(center: Coord, radius: f64, edges: usize) -> Vec<Coord>
+{
+    (0..edges).map(|i| center + angle((i as f64) * 2.0 * PI / (edges as f64)) * radius).collect()
+}
+
+
+// The vertices of a polygon arena, morphed when asked for
+pub fn polygon_outline(config: &ArenaConfig) -> Vec<Coord>
+{
+    let (center, radius) = (center_of(config), config.size / 2.0);
+    let outline = regular_polygon(center, radius, config.edges);
+    match &config.morph {
+        Some(morph) => morph_outline(&outline, &regular_polygon(center, radius, morph.edges), morph.t),
+        None => outline,
+    }
+}
+
+
+// How far from a corner an arc with radius 1 touches its edges. Straight corners,
+// like the extra vertices of morphed polygons, have no corner to round.
+fn corner_tangent(prev: Coord, corner: Coord, next: Coord) -> Option<f64>
+{
+    let (u1, u2) = ((prev - corner).try_normalize()?, (next - corner).try_normalize()?);
+    let half_angle = u1.dot_product(u2).clamp(-1.0, 1.0).acos() / 2.0;
+    (half_angle < PI / 2.0 - 1e-9).then(|| 1.0 / half_angle.tan())
+}
+
+
+// The largest radius the corners of an outline can be rounded with, before the
+// arcs of two corners overlap on the edge between them
+pub fn max_corner_radius(outline: &[Coord]) -> f64
+{
+    let n = outline.len();
+    let tangents: Vec<Option<f64>> = (0..n).map(|i| corner_tangent(outline[(i + n - 1) % n], outline[i], outline[(i + 1) % n])).collect();
+
+    (0..n)
+        .map(|i| {
+            let length = outline[i].euclidean_distance(&outline[(i + 1) % n]);
+            match (tangents[i], tangents[(i + 1) % n]) {
+                (None, None) => f64::INFINITY,
+                (a, b) => length / (a.unwrap_or(0.0) + b.unwrap_or(0.0)),
+            }
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+
+// Replace every corner of a closed outline by an arc with `radius`, that touches
+// both edges of the corner
+fn round_corners(outline: &[Coord], radius: f64) -> Vec<Shape>
+{
+    let n = outline.len();
+    // Where the arc of every corner starts and ends, on the edge before and after it
+    let mut ends: Vec<(Coord, Coord)> = Vec::new();
+    let mut walls: Vec<Shape> = Vec::new();
+
+    for i in 0..n {
+        let (prev, corner, next) = (outline[(i + n - 1) % n], outline[i], outline[(i + 1) % n]);
+        let Some(tangent) = corner_tangent(prev, corner, next) else {
+            ends.push((corner, corner));
+            continue;
+        };
+
+        let (u1, u2) = ((prev - corner).try_normalize().unwrap(), (next - corner).try_normalize().unwrap());
+        let (t1, t2) = (corner + u1 * radius * tangent, corner + u2 * radius * tangent);
+        // The center lies on the bisector, radius away from both edges
+        let center = corner + (u1 + u2).try_normalize().unwrap() * radius.hypot(radius * tangent);
+
+        let (a1, a2) = ((t1 - center).y.atan2((t1 - center).x), (t2 - center).y.atan2((t2 - center).x));
+        let sweep = (a2 - a1).rem_euclid(2.0 * PI);
+        let (start, sweep) = if sweep <= PI { (a1, sweep) } else { (a2, 2.0 * PI - sweep) };
+
+        walls.push(Shape::Arc { center, radii: coord! {x: radius, y: radius}, angle: 0.0, start, sweep });
+        ends.push((t1, t2));
+    }
+
+    for i in 0..n {
+        let (from, to) = (ends[i].1, ends[(i + 1) % n].0);
+        if from != to {
+            walls.push(Shape::Segment(Line::new(from, to)));
+        }
+    }
+    walls
+}
+
+
+// Interpolate between two closed outlines, at t = 0 `from` and at t = 1 `to`. Both
+// are first resampled to the same number of points, evenly spread over their vertices.
+fn morph_outline(from: &[Coord], to: &[Coord], t: f64) -> Vec<Coord>
+{
+    let n = from.len().max(to.len());
+    // The point at index `s` of the outline, between its vertices when s is fractional
+    let at = |outline: &[Coord], s: f64| -> Coord {
+        let i = s.floor() as usize;
+        let (a, b) = (outline[i % outline.len()], outline[(i + 1) % outline.len()]);
+        a + (b - a) * (s - i as f64)
+    };
+
+    (0..n)
+        .map(|i| {
+            let a = at(from, (i * from.len()) as f64 / n as f64);
+            let b = at(to, (i * to.len()) as f64 / n as f64);
+            a + (b - a) * t
+        })
+        .collect()
+}
+
+
+// The outline of the star polygon {points/step}, that connects every step-th corner
+// of a regular polygon. Its inner corners are where those lines cross.
+fn star(center: Coord, radius: f64, points: usize, step: usize) -> Vec<Coord>
+{
+    let n = points as f64;
+    let inner_radius = radius * (PI * step as f64 / n).cos() / (PI * (step as f64 - 1.0) / n).cos();
+
+    (0..points)
+        .flat_map(|i| [center + angle((i as f64) * 2.0 * PI / n) * radius,
+                       center + angle((i as f64 + 0.5) * 2.0 * PI / n) * inner_radius])
+        .collect()
+}
+
+
+// The edges of the regular polygon, replaced `depth` times by the fractal generator
+pub fn fractal_outline(config: &ArenaConfig, rng: &mut StdRng) -> Vec<Coord>
+{
+    let center = coord! {x: config.center[0], y: config.center[1]};
+    let mut outline = regular_polygon(center, config.size / 2.0, config.edges);
+
+    for _ in 0..config.depth {
+        let mut refined: Vec<Coord> = Vec::new();
+        for edge in outline_edges(&outline) {
+            let d = edge.delta();
+            // the polygon is counter clockwise, so this points outwards
+            let normal = coord! {x: d.y, y: -d.x};
+
+            refined.push(edge.start);
+            match config.kind {
+                ArenaKind::Koch => bump(&mut refined, edge, normal, PI / 3.0),
+                // with an 85 degree tip
+                ArenaKind::Cesaro => bump(&mut refined, edge, -normal, (180.0 - 85.0) / 2.0 * PI / 180.0),
+                _ => refined.push(edge.start + d * 0.5 + normal * (config.roughness * rng.gen_range(-0.5 .. 0.5))),
+            }
+        }
+        outline = refined;
+    }
+
+    outline
+}
+
+
+// Replace the middle of the edge by two sides of equal length, that make `base_angle`
+// with the edge. Pushes the points after edge.start.
+fn bump(points: &mut Vec<Coord>, edge: Line, normal: Coord, base_angle: f64)
+{
+    let d = edge.delta();
+    let side = 1.0 / (2.0 * (1.0 + base_angle.cos()));    // as ratio of the edge length
+
+    points.push(edge.start + d * side);
+    points.push(edge.start + d * 0.5 + normal * (side * base_angle.sin()));
+    points.push(edge.end - d * side);
+}
+
+
+fn stadium(center: Coord, width: f64, aspect_ratio: f64) -> Vec<Shape>
+{
+    let r = width / 2.0 / aspect_ratio;
+    let h = width / 2.0 - r;    // half of the length of the straight walls
+    let left = center - coord! {x: h, y: 0.0};
+    let right = center + coord! {x: h, y: 0.0};
+
+    let mut walls = vec![
+        Shape::Arc { center: right, radii: coord! {x: r, y: r}, angle: 0.0, start: -PI / 2.0, sweep: PI },
+        Shape::Arc { center: left, radii: coord! {x: r, y: r}, angle: 0.0, start: PI / 2.0, sweep: PI },
+    ];
+    if h > 0.0 {
+        walls.push(Shape::Segment(Line::new(left - coord! {x: 0.0, y: r}, right - coord! {x: 0.0, y: r})));
+        walls.push(Shape::Segment(Line::new(left + coord! {x: 0.0, y: r}, right + coord! {x: 0.0, y: r})));
+    }
+    walls
+}
+
+
+// A half circle cap on top of a rectangular stem. The stem width and height are
+// ratios of the diameter of the cap, the whole mushroom fits in a `size` square.
+fn mushroom(center: Coord, size: f64, stem_width: f64, stem_height: f64) -> Vec<Shape>
+{
+    let r = size / (1.0 + 2.0 * stem_height).max(2.0);
+    let w = r * stem_width;         // half of the width of the stem
+    let h = 2.0 * r * stem_height;
+
+    // the y axis points down, so the cap is above its base line
+    let base = center.y - (h - r) / 2.0;
+    let point = |x: f64, y: f64| coord! {x: center.x + x, y: base + y};
+
+    let mut walls = vec![
+        Shape::Arc { center: point(0.0, 0.0), radii: coord! {x: r, y: r}, angle: 0.0, start: PI, sweep: PI },
+        Shape::Segment(Line::new(point(-w, 0.0), point(-w, h))),
+        Shape::Segment(Line::new(point(-w, h), point(w, h))),
+        Shape::Segment(Line::new(point(w, h), point(w, 0.0))),
+    ];
+    if w < r {
+        walls.push(Shape::Segment(Line::new(point(-r, 0.0), point(-w, 0.0))));
+        walls.push(Shape::Segment(Line::new(point(w, 0.0), point(r, 0.0))));
+    }
+    walls
+}
+
+
+// The intersection of two discs, whose centers are `separation` apart on a horizontal
+// line, or for lunes the part of the first disc the second does not cover. Their
+// circles have to cross. The shape is scaled to fit in a `size` square.
+fn lens(center: Coord, size: f64, radii: [f64; 2], separation: f64, lune: bool) -> Vec<Shape>
+{
+    const SAMPLES: usize = 1024;
+
+    // With the discs at x = 0 and x = separation, the circles cross at x = a
+    let [r1, r2] = radii;
+    let a = (separation * separation + r1 * r1 - r2 * r2) / (2.0 * separation);
+    let h = (r1 * r1 - a * a).sqrt();
+    // half of the angle each circle spans inside the other disc
+    let (inside1, inside2) = (h.atan2(a), h.atan2(separation - a));
+
+    // center x, radius, start and sweep of both arcs
+    let disc1_arc = if lune { (0.0, r1, inside1, 2.0 * (PI - inside1)) } else { (0.0, r1, -inside1, 2.0 * inside1) };
+    let arcs = [disc1_arc, (separation, r2, PI - inside2, 2.0 * inside2)];
+
+    // The extent of the arcs, from points along them
+    let points: Vec<Coord> = arcs.iter()
+        .flat_map(|&(x, r, start, sweep)| (0..=SAMPLES).map(move |i| {
+            angle(start + sweep * i as f64 / SAMPLES as f64) * r + coord! {x: x, y: 0.0}
+        }))
+        .collect();
+    let (min_x, max_x) = points.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| (lo.min(p.x), hi.max(p.x)));
+    let (min_y, max_y) = points.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| (lo.min(p.y), hi.max(p.y)));
+    let scale = size / (max_x - min_x).max(max_y - min_y);
+    let middle = coord! {x: (min_x + max_x) / 2.0, y: (min_y + max_y) / 2.0};
+
+    arcs.iter()
+        .map(|&(x, r, start, sweep)| Shape::Arc {
+            center: center + (coord! {x: x, y: 0.0} - middle) * scale,
+            radii: coord! {x: r * scale, y: r * scale},
+            angle: 0.0,
+            start,
+            sweep,
+        })
+        .collect()
+}
+
+
+// The y axis of shape files points up, and that of the image down
+fn flip_y(vertices: &[[f64; 2]]) -> Vec<[f64; 2]>
+{
+    vertices.iter().map(|v| [v[0], -v[1]]).collect()
+}
+
+
+// Scales the outlines together, to fit in a `size` square around `center`
+fn fit(center: Coord, size: f64, outlines: &[Vec<[f64; 2]>]) -> Vec<Vec<Coord>>
+{
+    let points = || outlines.iter().flatten();
+    let (min_x, max_x) = points().fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v[0]), hi.max(v[0])));
+    let (min_y, max_y) = points().fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v[1]), hi.max(v[1])));
+    let scale = size / (max_x - min_x).max(max_y - min_y);
+
+    outlines.iter()
+        .map(|outline| outline.iter()
+            .map(|v| coord! {x: center.x + (v[0] - (min_x + max_x) / 2.0) * scale,
+                             y: center.y + (v[1] - (min_y + max_y) / 2.0) * scale})
+            .collect())
+        .collect()
+}
+
+
+// Short human readable description, for summaries
+pub fn describe(config: &ArenaConfig) -> String
+{
+    let shape = match config.kind {
+        ArenaKind::Polygon => match &config.morph {
+            Some(morph) => format!("{} edges, morphed {} of the way to {} edges, size {}", config.edges, morph.t, morph.edges, config.size),
+            None => format!("{} edges, size {}", config.edges, config.size),
+        },
+        ArenaKind::Star => format!("star {{{}/{}}}, size {}", config.edges, config.step, config.size),
+        ArenaKind::Circle => format!("circle, size {}", config.size),
+        ArenaKind::Ellipse => format!("ellipse, eccentricity {}, size {}", config.eccentricity, config.size),
+        ArenaKind::Stadium => format!("stadium, aspect ratio {}, size {}", config.aspect_ratio, config.size),
+        ArenaKind::Mushroom => format!("mushroom, stem {} x {}, size {}", config.stem_width, config.stem_height, config.size),
+        ArenaKind::Lens | ArenaKind::Lune => format!("{}, discs of radius {} and {}, {} apart, size {}",
+                                                     if config.kind == ArenaKind::Lens { "lens" } else { "lune" },
+                                                     config.disc_radii[0], config.disc_radii[1], config.disc_separation, config.size),
+        ArenaKind::Custom => match &config.shape_file {
+            Some(path) => format!("{} ({} vertices), size {}", path.display(), config.vertices.len(), config.size),
+            None => format!("custom, {} vertices, size {}", config.vertices.len(), config.size),
+        },
+        ArenaKind::Parametric => match &config.curve {
+            Some(curve) => format!("{}, {} segments, size {}", curve.describe(), config.curve_resolution, config.size),
+            None => "parametric, without a curve".to_string(),
+        },
+        ArenaKind::Koch => format!("koch, {} edges, depth {}, size {}", config.edges, config.depth, config.size),
+        ArenaKind::Cesaro => format!("cesaro, {} edges, depth {}, size {}", config.edges, config.depth, config.size),
+        ArenaKind::Rough => format!("rough, {} edges, depth {}, roughness {}, seed {}, size {}",
+                                    config.edges, config.depth, config.roughness, config.seed, config.size),
+        ArenaKind::Torus => "torus".to_string(),
+        ArenaKind::Open => format!("open, {} segments of length {}, seed {}, viewport {:?}",
+                                   open_segment_count(config), config.segment_length, config.seed, config.viewport),
+        ArenaKind::Maze => format!("maze, {} x {} cells, seed {}, size {}", config.maze_size, config.maze_size, config.seed, config.size),
+        ArenaKind::Random => format!("random, {} edges, {} scatterers, seed {}, size {}",
+                                     config.edges, config.random_scatterers, config.seed, config.size),
+        ArenaKind::Scene => match (&config.scene_file, &config.scene) {
+            (Some(path), Some(scene)) => format!("{} ({} walls): {}", path.display(), scene.no_walls(), scene.arena),
+            (None, Some(scene)) => format!("scene, {} walls: {}", scene.no_walls(), scene.arena),
+            (_, None) => "scene".to_string(),
+        },
+        ArenaKind::Mask => match &config.mask_file {
+            Some(path) => format!("{} ({} contours), size {}", path.display(), config.contours.len(), config.size),
+            None => format!("mask, {} contours, size {}", config.contours.len(), config.size),
+        },
+    };
+
+    // The other settings are not used for saved scenes, those have the walls already
+    if config.kind == ArenaKind::Scene {
+        return shape;
+    }
+
+    let mut description = shape;
+    if config.corner_radius > 0.0 {
+        description += &format!(", corners rounded with radius {}", config.corner_radius);
+    }
+    if !config.shells.is_empty() {
+        let scales: Vec<String> = config.shells.iter().map(|s| s.to_string()).collect();
+        description += &format!(", shells at {}", scales.join(", "));
+    }
+    if !config.scatterers.is_empty() {
+        description += &format!(", {} scatterers", config.scatterers.len());
+    }
+    if !config.obstacles.is_empty() {
+        description += &format!(", {} obstacles", config.obstacles.len());
+    }
+    if !config.regions.is_empty() {
+        description += &format!(", {} refracting regions", config.regions.len());
+    }
+    if !config.moving_walls.is_empty() {
+        let walls: Vec<String> = config.moving_walls.iter().map(|wall| wall.describe()).collect();
+        description += &format!(", moving walls: {}", walls.join(", "));
+    }
+    let (t, identity) = (&config.transform, Transform::default());
+    if t.rotation != identity.rotation {
+        description += &format!(", rotated {}°", t.rotation);
+    }
+    if t.offset != identity.offset {
+        description += &format!(", offset ({}, {})", t.offset[0], t.offset[1]);
+    }
+    if t.scale != identity.scale {
+        description += &format!(", scale {}", t.scale);
+    }
+    if t.anisotropy != identity.anisotropy {
+        description += &format!(", anisotropy {} x {}", t.anisotropy[0], t.anisotropy[1]);
+    }
+    match config.material {
+        Material::Reflect => {}
+        Material::Absorb => description += ", absorbing walls",
+        Material::Transmit(p) => description += &format!(", walls transmit {}%", p * 100.0),
+        Material::Rough(r) => description += &format!(", walls {}% diffuse", r * 100.0),
+        Material::Sticky(d) => description += &format!(", walls sticky for {}", d),
+    }
+    if !config.openings.is_empty() {
+        description += &format!(", {} openings", config.openings.len());
+    }
+    if config.composite > 1 {
+        description += &format!(", {} arenas laid over each other", config.composite);
+    }
+    match config.variation {
+        ArenaVariation::None => {}
+        ArenaVariation::Run => description += ", varies per run",
+        ArenaVariation::Worker => description += ", varies per worker",
+    }
+    description
+}
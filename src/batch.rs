@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::RunConfig;
+use crate::presets::preset_by_name;
+
+
+// A queue of independent runs, for example for an overnight render. The batch
+// file looks like:
+//
+//   threads = 16            # size of the worker pool, shared by all runs
+//   parallel = 2            # number of runs that are simulated at the same time
+//
+//   [defaults]              # optional, applied to every run
+//   sims = 100_000_000
+//
+//   [[run]]
+//   name = "hexagon"        # optional, part of the file names
+//   preset = "dense-hexagon"    # optional, applied on top of the defaults
+//   arena = { edges = 5 }   # anything a config file can contain
+//
+// The threads setting of the individual runs is not used, all runs share the pool.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct BatchFile {
+    threads: Option<usize>,
+    parallel: Option<usize>,
+    #[serde(default)]
+    defaults: toml::Table,
+    run: Vec<toml::Table>,
+}
+
+
+pub struct Batch {
+    pub threads: Option<usize>,
+    pub parallel: usize,
+    pub runs: Vec<BatchRun>,
+}
+
+
+pub struct BatchRun {
+    pub name: String,
+    pub config: RunConfig,
+}
+
+
+pub fn load_batch(path: &Path) -> Result<Batch, String>
+{
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read batch file {}: {}", path.display(), e))?;
+    let file: BatchFile = toml::from_str(&text)
+        .map_err(|e| format!("Could not parse batch file {}: {}", path.display(), e))?;
+
+    let mut runs: Vec<BatchRun> = Vec::new();
+    for (i, mut values) in file.run.into_iter().enumerate() {
+        let invalid = |e: String| format!("Run {} in batch file {}: {}", i + 1, path.display(), e);
+
+        let name = match values.remove("name") {
+            Some(toml::Value::String(name)) => name,
+            Some(_) => return Err(invalid("name must be a string".to_string())),
+            None => format!("run{}", i + 1)
+        };
+        let mut config = RunConfig::default().with_overrides(file.defaults.clone()).map_err(invalid)?;
+        match values.remove("preset") {
+            Some(toml::Value::String(preset)) => preset_by_name(&preset).map_err(invalid)?.apply_to(&mut config),
+            Some(_) => return Err(invalid("preset must be a string".to_string())),
+            None => {}
+        }
+
+        let mut config = config.with_overrides(values).map_err(invalid)?;
+        config.load_files().map_err(invalid)?;
+        config.validate().map_err(invalid)?;
+
+        runs.push(BatchRun { name, config });
+    }
+
+    Ok(Batch {
+        threads: file.threads,
+        parallel: file.parallel.unwrap_or(1),
+        runs,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    // Loads the text as a batch file, of a name of its own, the tests run at the same time
+    fn load(name: &str, text: &str) -> Result<Batch, String>
+    {
+        let path = env::temp_dir().join(format!("batch-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        let batch = load_batch(&path);
+        fs::remove_file(&path).unwrap();
+        batch.map_err(|e| e.replace(&path.display().to_string(), "batch.toml"))
+    }
+
+    #[test]
+    fn runs_get_the_defaults_then_the_preset_then_their_own_values()
+    {
+        let batch = load("defaults", r#"
+            threads = 3
+            parallel = 2
+
+            [defaults]
+            sims = 1000
+            width = 64
+
+            [[run]]
+            name = "hexagon"
+            preset = "dense-hexagon"
+            arena = { edges = 7 }
+
+            [[run]]
+            height = 32
+        "#).unwrap();
+        assert_eq!((batch.threads, batch.parallel), (Some(3), 2));
+        let [hexagon, second] = &batch.runs[..] else { panic!("expected 2 runs") };
+        assert_eq!(hexagon.name, "hexagon");
+        assert_eq!((hexagon.config.sims, hexagon.config.width), (200_000_000, 2048));
+        assert_eq!((hexagon.config.arena.edges, hexagon.config.shader.as_str()), (7, "hit-count"));
+        assert_eq!(second.name, "run2");
+        assert_eq!((second.config.sims, second.config.width, second.config.height), (1000, 64, 32));
+    }
+
+    #[test]
+    fn run_config_survives_toml()
+    {
+        let batch = load("round-trip", "[[run]]\nsims = 1234\nseed = 5\narena = { kind = \"random\", composite = 2 }\n").unwrap();
+        let config = &batch.runs[0].config;
+        let text = toml::to_string(config).unwrap();
+        let reloaded: RunConfig = toml::from_str(&text).unwrap();
+        assert_eq!(toml::Table::try_from(&reloaded).unwrap(), toml::Table::try_from(config).unwrap());
+        assert_eq!((reloaded.sims, reloaded.seed, reloaded.arena.composite), (1234, Some(5), 2));
+    }
+
+    #[test]
+    fn invalid_runs_are_refused_by_number()
+    {
+        assert_eq!(load("name", "[[run]]\nname = 3\n").err().unwrap(), "Run 1 in batch file batch.toml: name must be a string");
+        assert!(load("second", "[[run]]\n[[run]]\nsims = \"many\"\n").err().unwrap().starts_with("Run 2 in batch file batch.toml:"));
+        assert!(load("no-runs", "parallel = 2\n").err().unwrap().starts_with("Could not parse batch file batch.toml"));
+    }
+}
@@ -0,0 +1,1210 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::arena::{crossing_shells, fractal_outline, initial_arena, max_corner_radius, MAX_COMPOSITE, MAX_MAZE_SIZE, MAX_VIEWPORT_SIZE, open_segment_count,
+                   polygon_outline};
+use crate::flight::{Potential, Wind};
+use crate::geodesic;
+use crate::mask;
+use crate::simulation::{MAX_BINS, Tumble};
+use crate::moving::MovingWall;
+use crate::outer;
+use crate::parametric::{Curve, curve_outline};
+use crate::scene_file::{self, SceneFile};
+use crate::scene::MAX_NO_WALLS;
+use crate::shaders::{build_color_shader, build_layered_shader, build_shader};
+use crate::symmetry::arena_symmetry;
+use crate::shape;
+use crate::solid::{Solid, SolidConfig};
+use crate::template;
+
+// A ball this large, or a trail this wide, would not fit in any arena inside the image
+const MAX_BALL_RADIUS: f64 = 0.5;
+
+
+// All parameters of a single run. Every field has a default, so a config file
+// only has to mention the values it wants to change.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunConfig {
+    pub width: usize,                   // width of the image in pixels
+    pub height: usize,                  // height of the image in pixels
+    pub sims: usize,                    // number of simulations to do
+    #[serde(with = "humantime_serde")]
+    pub duration: Option<Duration>,     // when set, simulate for this long instead of a number of sims
+    pub threads: Option<usize>,         // None means: use all available cores
+    pub seed: Option<u64>,              // None means: pick a random one
+    pub resample_starts: bool,          // pick a new start when it is outside the arena, instead of skipping it
+    pub trail_length: Option<usize>,    // the ball is trapped when its trail is this long. None means: what
+                                        // the walls leave of 200 segments, but at least 100
+    pub balls: usize,                   // flying at the same time, avoiding each others trails
+    pub branching: f64,                 // chance that a bounce splits the ball in a reflected and a passing one
+    pub max_branches: usize,            // in one simulation
+    pub trail_full: TrailFull,          // what happens when the trail has reached its length
+    pub max_bounces: Option<usize>,     // the ball stops after this many bounces. None means: no limit
+    pub max_path_length: Option<f64>,   // the ball stops when it flew this far, in image widths
+    pub return_radius: Option<f64>,     // the ball stops when it comes back this close to its start
+    pub trail_memory: Option<usize>,    // trail segments are forgotten this many bounces after they were laid,
+                                        // they still count for the trail length. None means: never
+    pub trail_window: Option<usize>,    // only this many of the most recent trail segments block the ball.
+                                        // None means: all of them
+    pub trail_material: Material,       // what the trail does to the ball when it hits it
+    pub trail_sides: TrailSides,        // the ball passes through the trail from the other side
+    pub corner_rule: CornerRule,        // how the ball reflects where it hits two walls at once, in a corner
+    pub corner_tolerance: f64,          // it hits the corner when it gets this close to it
+    pub reflection_offset: f64,         // the ball starts this far from where it bounced, the surface it left is left out anyway
+    pub robust_predicates: bool,        // thin straight walls and trail segments are hit by exact tests, also when it grazes them
+    pub on_cycle: Option<OnCycle>,      // what happens to a ball caught in a periodic orbit. None means: it is not looked for
+    pub cycle_tolerance: f64,           // bounces at points and in directions this close are the same
+    pub cycle_window: usize,            // number of recent bounces a bounce is compared with
+    pub tiles: [usize; 2],              // columns and rows of copies of the arena, each simulated on its own
+    pub fold_symmetry: bool,            // accumulate in one wedge of the symmetry of the arena, the images are unfolded
+    pub reflection_jitter: f64,         // standard deviation of the random turn of the ball at every reflection, in degrees
+    pub tumble: Option<Tumble>,         // the ball turns into a random direction on its own, after random free paths
+    pub gravity: [f64; 2],              // acceleration of the ball, it flies in parabolas. The ball starts with speed 1
+    pub larmor_radius: Option<f64>,     // of the circles a magnetic field bends the ball around, negative the other way
+    pub wind: Option<Wind>,             // carries the ball along
+    pub potential: Option<Potential>,   // a force towards or away from a center, that depends on where the ball is
+    pub frame_rotation: f64,            // the ball flies in a frame turning clockwise with the arena, in turns per unit of time
+    pub frame_center: [f64; 2],         // that the frame turns around
+    pub restitution: f64,               // the energy of the ball, that starts at 1, is multiplied with this at every bounce
+    pub trail_restitution: Option<f64>, // instead, at bounces off its trail. None means: the same as off the walls
+    pub min_energy: f64,                // the ball stops where its energy gets below this
+    pub deceleration: f64,              // the ball slows down at this rate from speed 1, and stops where it got to 0
+    pub bounce_speedup: f64,            // the speed of the ball is multiplied with this at every bounce
+    pub ball_radius: f64,               // the ball hits walls and its trail when its center gets this close
+    pub trail_width: f64,               // the trail is a band of this width around the path of the ball
+    pub flight_step: f64,               // curved flights are followed in straight steps of this length
+    pub dynamics: Dynamics,             // billiards inside the arena, or outer billiards around it
+    pub sphere_projection: SphereProjection,    // of the image of spherical billiards
+    pub table_scale: f64,               // of outer billiards, the table is the arena shrunk by this around its center
+    pub solid: SolidConfig,             // the polyhedron of solid billiards, and the camera it is seen through
+    pub shader: String,
+    pub shader_exponent: f64,           // of the path-power shader
+    pub shader_expr: Option<String>,    // a shader written as an expression, instead of the shader above
+    pub shader_script: Option<PathBuf>, // a shader script file, instead of both
+    pub color: Option<String>,          // a color shader, tinting the weights of the shader, for a color image
+    pub wavenumber: Option<f64>,        // of phase runs, the weight of a ball is turned by this times its path length
+    pub direction_bins: Option<usize>,  // of direction runs, the weight of a ball goes in one of this many bins, by the direction it ended in
+    pub bounce_bands: Vec<usize>,       // of band runs, the most bounces of every band but the last, it goes in the band of its bounce count
+    pub extra_shaders: Vec<String>,     // built-in shaders that weigh the same balls, each in a canvas of its own
+    pub deposit: Deposit,               // where the weight of a ball goes in the canvas
+    pub deposit_at: DepositAt,          // of end deposits, whether that is where the ball ended, started, or both
+    pub bounce_exponent: f64,           // the weight at the k-th bounce, of bounce deposits, is multiplied with k to this power
+    pub reduction: Reduction,           // how the weights that go in the same pixel are combined
+    pub first_arrival: Option<Arrival>, // also keep the smallest of this of the balls that ended in every pixel
+    pub arena: ArenaConfig,
+    pub output: OutputConfig,
+}
+
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArenaConfig {
+    pub kind: ArenaKind,
+    pub edges: usize,                   // for polygons and stars, and the polygon fractals start with
+    pub morph: Option<Morph>,           // of polygons, part of the way to a polygon with other edges
+    pub corner_radius: f64,             // of polygons, the corners are rounded with arcs of this radius
+    pub step: usize,                    // of stars, every how many corners the points are connected
+    pub depth: usize,                   // recursion depth of fractals
+    pub roughness: f64,                 // of rough arenas, how far midpoints are displaced, as ratio of the edge length
+    pub seed: u64,                      // for randomly generated arenas
+    pub variation: ArenaVariation,      // whether random arenas differ between runs or workers
+    pub composite: usize,               // number of random arenas laid over each other
+    pub random_scatterers: usize,       // number of scatterers in random arenas
+    pub maze_size: usize,               // number of cells along each side of mazes
+    pub viewport: [[f64; 2]; 2],        // of open arenas, lower and upper corner, in image coordinates
+    pub segment_density: f64,           // of open arenas, number of random segments per image area
+    pub segment_length: f64,            // of the random segments, as ratio of the image width
+    pub eccentricity: f64,              // for ellipses, the major axis is horizontal
+    pub aspect_ratio: f64,              // width / height of stadiums
+    pub stem_width: f64,                // of mushrooms, as ratio of the diameter of the cap
+    pub stem_height: f64,               // of mushrooms, as ratio of the diameter of the cap
+    pub disc_radii: [f64; 2],           // of the two discs lenses and lunes are made of
+    pub disc_separation: f64,           // distance between the centers of the discs, in the same units
+    pub shape_file: Option<PathBuf>,    // WKT or GeoJSON file with the outline of custom arenas
+    pub vertices: Vec<[f64; 2]>,        // outline of custom arenas, read from the shape file when it is set
+    pub curve: Option<Curve>,           // of parametric arenas
+    pub curve_resolution: usize,        // number of segments the curve is sampled into
+    pub mask_file: Option<PathBuf>,     // black and white image, the dark parts are the arena
+    pub mask_invert: bool,              // use the light parts of the mask instead
+    pub mask_tolerance: f64,            // how far, in pixels, the simplified contours may be off
+    pub contours: Vec<Vec<[f64; 2]>>,   // outlines traced from the mask file, in pixels
+    pub scene_file: Option<PathBuf>,    // scene saved with --save-scene, for scene arenas
+    pub scene: Option<SceneFile>,       // the walls of scene arenas, read from the scene file when it is set
+    pub size: f64,                      // size of arena, as ratio of the whole image
+    pub center: [f64; 2],               // center of the arena, in image coordinates (0..1)
+    pub shells: Vec<f64>,               // smaller copies of the boundary inside it, as ratio of its size
+    pub transform: Transform,           // of the generated walls, around the center
+    pub scatterers: Vec<Scatterer>,     // circular obstacles inside the arena, for Sinai billiards
+    pub obstacles: Vec<Obstacle>,       // straight obstacles inside the arena
+    pub openings: Vec<Opening>,         // holes in the walls, the ball escapes through them
+    pub regions: Vec<Region>,           // of other media, the ball refracts at their edges
+    pub moving_walls: Vec<MovingWall>,  // segments that move with the time of the ball
+    pub material: Material,             // of the walls of the arena itself
+}
+
+
+// A polygon between the one with the edges of the arena, at t = 0, and the one with
+// `edges`, at t = 1. Both get the same number of vertices, the polygon with fewer
+// edges some extra ones on its edges, and those are interpolated.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Morph {
+    pub edges: usize,
+    pub t: f64,
+}
+
+
+// Applied to the generated walls of an arena, around its center: first the anisotropy,
+// then the rotation and the scale, and last the offset. Scatterers, obstacles and
+// openings are placed in image coordinates, and are not transformed.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transform {
+    pub rotation: f64,                  // in degrees, clockwise on the image
+    pub offset: [f64; 2],               // in image coordinates
+    pub scale: f64,
+    pub anisotropy: [f64; 2],           // stretch along the x and y axis
+}
+
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Scatterer {
+    pub center: [f64; 2],               // in image coordinates
+    pub radius: f64,
+    #[serde(default)]
+    pub material: Material,
+}
+
+
+// The walls within `radius` of `center` are removed, in image coordinates
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Opening {
+    pub center: [f64; 2],
+    pub radius: f64,
+}
+
+
+// A region of another medium, written as { polygon = [[x0, y0], ...], index = 1.5 }, in
+// image coordinates. Outside all regions the refractive index is 1, where regions
+// overlap the later one counts.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub polygon: Vec<[f64; 2]>,
+    pub index: f64,
+}
+
+
+// Written as { segment = [[x0, y0], [x1, y1]] }, { polygon = [[x0, y0], ...] } or
+// { arc = { center = [x, y], radius = r, start = 0, sweep = 90 } }, in image coordinates,
+// optionally with a material
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Obstacle {
+    #[serde(flatten)]
+    pub shape: ObstacleShape,
+    #[serde(default)]
+    pub material: Material,
+}
+
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum ObstacleShape {
+    Segment([[f64; 2]; 2]),
+    Polygon(Vec<[f64; 2]>),
+    Arc { center: [f64; 2], radius: f64, start: f64, sweep: f64 },  // angles in degrees, clockwise
+                                                                    // on the image, 0 points right
+}
+
+
+// What a wall does to the balls that hit it. Written as "reflect", "absorb", { transmit = 0.3 }
+// { rough = 0.2 } or { sticky = 0.05 }
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Material {
+    #[default]
+    Reflect,
+    Absorb,             // the ball ends where it hits the wall
+    Transmit(f64),      // the ball passes through with this probability, and reflects otherwise
+    Rough(f64),         // reflects between the mirror direction, at 0, and a random diffuse one, at 1
+    Sticky(f64),        // the ball slides this far along the wall, and then detaches in a random diffuse direction
+}
+
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub directory: PathBuf,
+    pub name: String,                   // file name template, without extension
+    pub tone_map: ToneMap,
+    pub image_format: ImageFormat,
+    pub npy: bool,                      // also write the values of the dumps as NumPy arrays
+    pub escape_map: bool,               // also write where the balls escaped through openings
+    pub hit_counts: bool,               // also write how many balls ended in every pixel, to divide the canvas by
+    pub vector_field: bool,             // also write the sum of the directions the balls ended in, in every pixel
+    pub exit_map: bool,                 // also write where and in which direction the balls escaped through openings
+    pub phase_image: PhaseImage,        // what the image of a phase run shows of the sums in its pixels
+    pub bin_hues: Vec<f64>,             // the hue of every bin of direction and band runs, in turns, spread evenly when empty
+}
+
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArenaKind {
+    Polygon,    // regular polygon
+    Star,       // outline of the star polygon {edges/step}
+    Circle,
+    Ellipse,
+    Stadium,    // two half circles, joined by straight walls
+    Mushroom,   // half circle cap on a rectangular stem
+    Lens,       // the part two discs have in common
+    Lune,       // the part of a disc another disc does not cover
+    Custom,     // polygon with the given vertices, scaled to fit
+    Parametric, // outline sampled from a curve, scaled to fit
+    Mask,       // contours of an image, scaled to fit
+    Koch,       // Koch curves on the edges of a polygon, a snowflake with 3 edges
+    Cesaro,     // Cesàro curves, pointing inwards, on the edges of a polygon
+    Rough,      // polygon with randomly displaced midpoints
+    Random,     // convex polygon with random corners, and random scatterers
+    Maze,       // square maze, with a random path through every cell
+    Torus,      // no walls, the ball wraps around the edges of the image
+    Open,       // no walls, but random segments, the ball escapes when it leaves the viewport
+    Scene,      // walls saved from an earlier run, as they were
+}
+
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArenaVariation {
+    None,       // always the arena of the arena seed
+    Run,        // a different arena for every run seed
+    Worker,     // every worker thread simulates its own arena, the image combines them
+}
+
+
+// The rules the ball moves by
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dynamics {
+    Inner,      // the ball bounces off the walls, inside the arena
+    Outer,      // outer billiards: a point outside the arena jumps through the vertex it sees on its right
+    Hyperbolic, // the ball flies along geodesics of the Poincaré disk, that fills the image
+    Spherical,  // the ball flies along great circles of a sphere, shown in the sphere projection
+    Solid,      // the ball bounces inside a polyhedron in space, seen through a camera
+}
+
+
+// How the sphere of spherical billiards is shown in the image
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SphereProjection {
+    Orthographic,       // the hemisphere facing the viewer, seen from far away, in the disk that fills the image
+    Equirectangular,    // longitude along x and latitude along y, the facing hemisphere is the middle half
+}
+
+
+// Where the weight of a ball goes in the canvas: the pixel where it ended, every pixel
+// along its trail, for filaments instead of the density of the endings, or the pixels
+// of its bounces, where it spends its collisions
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Deposit {
+    End,
+    Trail,
+    Bounces,    // every bounce point, weighted by the bounce exponent
+}
+
+
+// Which point of a ball an end deposit goes to: where it ended, where it started, to
+// see where the balls that end up trapped come from, or both of them
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DepositAt {
+    Termination,
+    Start,
+    Both,
+}
+
+
+// How the weights of the balls that end in the same pixel are combined: summed, the
+// smallest or the largest of them, or their mean
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reduction {
+    Sum,
+    Min,
+    Max,
+    Mean,
+}
+
+
+// What the first arrival canvas keeps the smallest of, of the balls that ended in
+// every pixel
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Arrival {
+    PathLength,
+    Bounces,
+    TravelTime,
+}
+
+
+// What happens when the ball has laid as many trail segments as the trail length
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrailFull {
+    Trap,       // the ball is trapped where it is
+    Forget,     // the oldest segment is forgotten, the ball goes on
+    Grow,       // nothing, the trail grows without a limit
+}
+
+
+// From which sides the trail blocks the ball, seen in the direction it was laid in
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrailSides {
+    Both,
+    Left,       // balls coming from the right pass through
+    Right,
+}
+
+
+// How the ball reflects where it hits a corner between two straight walls
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CornerRule {
+    Nearest,    // off the wall that is hit first, the other one may be crossed
+    Reverse,    // it goes back the way it came
+    Bisector,   // off the wall across the bisector of the corner, that cuts it off
+}
+
+
+// What happens to a ball that leaves a bounce point again in the same direction, that
+// is caught in a (near) periodic orbit. Written as "stop" or { perturb = 1.0 }
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnCycle {
+    Stop,           // it ends there, as periodic
+    Perturb(f64),   // it turns by a random angle, normally distributed with this standard deviation in degrees
+}
+
+
+// How accumulated values are mapped to the gray values of the output image
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToneMap {
+    Log,        // log10 of the value, relative to the log10 of the maximum
+    Linear,
+    Sqrt,
+}
+
+
+// The file format of the images: 32bit tiffs, or 8 or 16 bit pngs, that more viewers
+// can open, in less precision
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageFormat {
+    Tiff,
+    Png8,
+    Png16,
+}
+
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str
+    {
+        match self {
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Png8 | ImageFormat::Png16 => "png",
+        }
+    }
+}
+
+
+// What the image of a phase run shows of the complex sum of the weights in every pixel
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PhaseImage {
+    Magnitude,
+    Intensity,  // the magnitude squared
+    Phase,      // from 0 to 1, pixels without balls are 0
+}
+
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        RunConfig {
+            width: 512,
+            height: 512,
+            sims: 10_000_000,
+            duration: None,
+            threads: None,
+            seed: None,
+            resample_starts: false,
+            trail_length: None,
+            balls: 1,
+            branching: 0.0,
+            max_branches: 32,
+            trail_full: TrailFull::Trap,
+            max_bounces: None,
+            max_path_length: None,
+            return_radius: None,
+            trail_memory: None,
+            trail_window: None,
+            trail_material: Material::Reflect,
+            trail_sides: TrailSides::Both,
+            corner_rule: CornerRule::Nearest,
+            corner_tolerance: 1e-6,
+            reflection_offset: 0.0001,
+            robust_predicates: false,
+            on_cycle: None,
+            cycle_tolerance: 1e-6,
+            cycle_window: 64,
+            tiles: [1, 1],
+            fold_symmetry: false,
+            reflection_jitter: 0.0,
+            tumble: None,
+            gravity: [0.0, 0.0],
+            larmor_radius: None,
+            wind: None,
+            potential: None,
+            frame_rotation: 0.0,
+            frame_center: [0.5, 0.5],
+            restitution: 1.0,
+            trail_restitution: None,
+            min_energy: 0.0,
+            deceleration: 0.0,
+            bounce_speedup: 1.0,
+            ball_radius: 0.0,
+            trail_width: 0.0,
+            flight_step: 0.005,
+            dynamics: Dynamics::Inner,
+            sphere_projection: SphereProjection::Orthographic,
+            table_scale: 0.3,
+            solid: SolidConfig::default(),
+            shader: "path-length".to_string(),
+            shader_exponent: 1.0,
+            shader_expr: None,
+            shader_script: None,
+            color: None,
+            wavenumber: None,
+            direction_bins: None,
+            bounce_bands: Vec::new(),
+            extra_shaders: Vec::new(),
+            deposit: Deposit::End,
+            deposit_at: DepositAt::Termination,
+            reduction: Reduction::Sum,
+            first_arrival: None,
+            bounce_exponent: 0.0,
+            arena: ArenaConfig::default(),
+            output: OutputConfig::default(),
+        }
+    }
+}
+
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        ArenaConfig {
+            kind: ArenaKind::Polygon,
+            edges: 5,
+            morph: None,
+            corner_radius: 0.0,
+            step: 2,
+            depth: 2,
+            roughness: 0.3,
+            seed: 0,
+            variation: ArenaVariation::None,
+            composite: 1,
+            random_scatterers: 0,
+            maze_size: 8,
+            viewport: [[0.0, 0.0], [1.0, 1.0]],
+            segment_density: 100.0,
+            segment_length: 0.05,
+            eccentricity: 0.6,
+            aspect_ratio: 2.0,
+            stem_width: 0.5,
+            stem_height: 0.5,
+            disc_radii: [1.0, 1.0],
+            disc_separation: 1.0,
+            shape_file: None,
+            vertices: Vec::new(),
+            curve: None,
+            curve_resolution: 256,
+            mask_file: None,
+            mask_invert: false,
+            mask_tolerance: 1.0,
+            contours: Vec::new(),
+            scene_file: None,
+            scene: None,
+            size: 0.98,
+            center: [0.5, 0.5],
+            shells: Vec::new(),
+            transform: Transform::default(),
+            scatterers: Vec::new(),
+            obstacles: Vec::new(),
+            regions: Vec::new(),
+            moving_walls: Vec::new(),
+            openings: Vec::new(),
+            material: Material::Reflect,
+        }
+    }
+}
+
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            rotation: 0.0,
+            offset: [0.0, 0.0],
+            scale: 1.0,
+            anisotropy: [1.0, 1.0],
+        }
+    }
+}
+
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            directory: PathBuf::from("."),
+            name: "raw-{date}".to_string(),
+            tone_map: ToneMap::Log,
+            escape_map: false,
+            image_format: ImageFormat::Tiff,
+            npy: false,
+            hit_counts: false,
+            vector_field: false,
+            exit_map: false,
+            phase_image: PhaseImage::Magnitude,
+            bin_hues: Vec::new(),
+        }
+    }
+}
+
+
+impl RunConfig {
+    // Pick a seed now if there is none yet, so it can be recorded with the results
+    pub fn fix_seed(&mut self)
+    {
+        if self.seed.is_none() {
+            self.seed = Some(rand::random());
+        }
+    }
+
+    // Whether the ball flies in curves, instead of straight lines
+    pub fn has_forces(&self) -> bool
+    {
+        self.gravity != [0.0, 0.0] || self.larmor_radius.is_some() || self.wind.is_some() || self.potential.is_some()
+            || self.frame_rotation != 0.0
+    }
+
+    // How many bins the weights go in, of direction and band runs
+    pub fn bins(&self) -> Option<usize>
+    {
+        match self.direction_bins {
+            Some(bins) => Some(bins),
+            None => (!self.bounce_bands.is_empty()).then(|| self.bounce_bands.len() + 1),
+        }
+    }
+
+    // Whether more than one ball flies at the same time, from the start or by branching
+    pub fn has_several_balls(&self) -> bool
+    {
+        self.balls > 1 || self.branching > 0.0
+    }
+
+    // Load a config file, everything the file does not mention is taken from `base`
+    pub fn load(path: &Path, base: &RunConfig) -> Result<RunConfig, String>
+    {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+        let file_values: toml::Table = toml::from_str(&text)
+            .map_err(|e| format!("Could not parse config file {}: {}", path.display(), e))?;
+
+        base.with_overrides(file_values)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    // A copy of this config, with all values mentioned in `overrides` replaced
+    pub fn with_overrides(&self, overrides: toml::Table) -> Result<RunConfig, String>
+    {
+        let mut values = toml::Table::try_from(self).unwrap();
+        merge_tables(&mut values, overrides);
+
+        values.try_into().map_err(|e: toml::de::Error| e.message().to_string())
+    }
+
+    // Read the files the config refers to, so the config is complete on its own
+    // and can be recorded in the dump
+    pub fn load_files(&mut self) -> Result<(), String>
+    {
+        if let (ArenaKind::Custom, Some(path)) = (self.arena.kind, &self.arena.shape_file) {
+            self.arena.vertices = shape::load_shape(path)?;
+        }
+        if let (ArenaKind::Mask, Some(path)) = (self.arena.kind, &self.arena.mask_file) {
+            self.arena.contours = mask::load_mask(path, self.arena.mask_invert, self.arena.mask_tolerance)?;
+        }
+        if let (ArenaKind::Scene, Some(path)) = (self.arena.kind, &self.arena.scene_file) {
+            self.arena.scene = Some(scene_file::load_scene(path)?);
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.width == 0 || self.height == 0 {
+            return Err("width and height must be at least 1 pixel".to_string());
+        }
+        if self.duration == Some(Duration::ZERO) {
+            return Err("duration must be longer than 0s".to_string());
+        }
+        if self.threads == Some(0) {
+            return Err("threads must be at least 1".to_string());
+        }
+        let fractal = matches!(self.arena.kind, ArenaKind::Koch | ArenaKind::Cesaro | ArenaKind::Rough);
+        if (matches!(self.arena.kind, ArenaKind::Polygon | ArenaKind::Star | ArenaKind::Random) || fractal) && self.arena.edges < 3 {
+            return Err(format!("an arena needs at least 3 edges, got {}", self.arena.edges));
+        }
+        if let Some(morph) = &self.arena.morph {
+            if self.arena.kind != ArenaKind::Polygon {
+                return Err("only polygon arenas can morph".to_string());
+            }
+            if morph.edges < 3 {
+                return Err(format!("a polygon needs at least 3 edges, got {} to morph to", morph.edges));
+            }
+            if !(0.0..=1.0).contains(&morph.t) {
+                return Err(format!("morph t must be from 0 to 1, got {}", morph.t));
+            }
+            if morph.edges.max(self.arena.edges) > MAX_NO_WALLS {
+                return Err(format!("an arena can have at most {} walls, got {} edges to morph to", MAX_NO_WALLS, morph.edges));
+            }
+        }
+        if self.arena.corner_radius != 0.0 {
+            if self.arena.kind != ArenaKind::Polygon {
+                return Err("only polygon arenas can have rounded corners".to_string());
+            }
+            if !(self.arena.corner_radius > 0.0 && self.arena.corner_radius.is_finite()) {
+                return Err(format!("corner radius can not be negative, got {}", self.arena.corner_radius));
+            }
+            let max_radius = max_corner_radius(&polygon_outline(&self.arena));
+            if self.arena.corner_radius > max_radius {
+                return Err(format!("corner radius can be at most {:.4} for this polygon, got {}", max_radius, self.arena.corner_radius));
+            }
+        }
+        if self.arena.kind == ArenaKind::Ellipse && !(0.0..1.0).contains(&self.arena.eccentricity) {
+            return Err(format!("eccentricity must be in [0, 1), got {}", self.arena.eccentricity));
+        }
+        if self.arena.kind == ArenaKind::Stadium && !(self.arena.aspect_ratio >= 1.0 && self.arena.aspect_ratio.is_finite()) {
+            return Err(format!("aspect ratio must be at least 1, got {}", self.arena.aspect_ratio));
+        }
+        if self.arena.kind == ArenaKind::Mushroom {
+            if !(self.arena.stem_width > 0.0 && self.arena.stem_width <= 1.0) {
+                return Err(format!("stem width must be in (0, 1], got {}", self.arena.stem_width));
+            }
+            if !(self.arena.stem_height > 0.0 && self.arena.stem_height.is_finite()) {
+                return Err(format!("stem height must be larger than 0, got {}", self.arena.stem_height));
+            }
+        }
+        if matches!(self.arena.kind, ArenaKind::Lens | ArenaKind::Lune) {
+            let ([r1, r2], separation) = (self.arena.disc_radii, self.arena.disc_separation);
+            if !(r1 > 0.0 && r2 > 0.0 && r1.is_finite() && r2.is_finite()) {
+                return Err(format!("disc radii must be larger than 0, got {:?}", self.arena.disc_radii));
+            }
+            // otherwise the circles do not cross, and one disc is inside the other, or they are apart
+            if !((r1 - r2).abs() < separation && separation < r1 + r2) {
+                return Err(format!("the discs have to overlap partly, their separation must be between {} and {}, got {}",
+                                   (r1 - r2).abs(), r1 + r2, separation));
+            }
+        }
+        if self.arena.kind == ArenaKind::Custom {
+            shape::validate_outline(&self.arena.vertices)?;
+        }
+        if self.arena.kind == ArenaKind::Parametric {
+            if !(3..=MAX_NO_WALLS).contains(&self.arena.curve_resolution) {
+                return Err(format!("curve resolution must be from 3 to {}, got {}", MAX_NO_WALLS, self.arena.curve_resolution));
+            }
+            let Some(curve) = &self.arena.curve else {
+                return Err("a parametric arena needs a curve".to_string());
+            };
+            curve.validate(self.arena.curve_resolution)?;
+            shape::validate_outline(&curve_outline(curve, self.arena.curve_resolution))?;
+        }
+        if fractal {
+            // every level replaces each edge by 4, or 2 for rough arenas
+            let factor: usize = if self.arena.kind == ArenaKind::Rough { 2 } else { 4 };
+            let no_walls = factor.checked_pow(self.arena.depth as u32).and_then(|f| f.checked_mul(self.arena.edges));
+            if no_walls.is_none_or(|n| n > MAX_NO_WALLS) {
+                return Err(format!("an arena can have at most {} walls, depth {} is too deep", MAX_NO_WALLS, self.arena.depth));
+            }
+            if !(self.arena.roughness >= 0.0 && self.arena.roughness.is_finite()) {
+                return Err(format!("roughness must be at least 0, got {}", self.arena.roughness));
+            }
+            // only the arena of the arena seed can be checked, variations are made during the run
+            let mut rng = StdRng::seed_from_u64(self.arena.seed);
+            let outline: Vec<[f64; 2]> = fractal_outline(&self.arena, &mut rng).iter().map(|c| [c.x, c.y]).collect();
+            shape::validate_outline(&outline)?;
+        }
+        if self.arena.kind == ArenaKind::Mask {
+            if !(self.arena.mask_tolerance >= 0.0 && self.arena.mask_tolerance.is_finite()) {
+                return Err(format!("mask tolerance must be at least 0, got {}", self.arena.mask_tolerance));
+            }
+            if self.arena.contours.is_empty() {
+                return Err("a mask arena needs a mask file, or contours".to_string());
+            }
+            for contour in &self.arena.contours {
+                shape::validate_outline(contour)?;
+            }
+        }
+        if self.arena.kind == ArenaKind::Scene {
+            let Some(scene) = &self.arena.scene else {
+                return Err("a scene arena needs a scene file, or a scene".to_string());
+            };
+            scene.validate()?;
+        }
+        if self.arena.kind == ArenaKind::Star && !(self.arena.step >= 1 && 2 * self.arena.step < self.arena.edges) {
+            return Err(format!("a star with {} points needs a step from 1 to {}, got {}",
+                               self.arena.edges, (self.arena.edges - 1) / 2, self.arena.step));
+        }
+        if !self.arena.center.iter().all(|c| c.is_finite()) {
+            return Err("arena center must be finite".to_string());
+        }
+        if !(self.arena.size > 0.0 && self.arena.size <= 1.0) {
+            return Err(format!("arena size must be in (0, 1], got {}", self.arena.size));
+        }
+        if !self.arena.shells.is_empty() {
+            if matches!(self.arena.kind, ArenaKind::Rough | ArenaKind::Random | ArenaKind::Maze | ArenaKind::Torus
+                                         | ArenaKind::Open | ArenaKind::Scene) {
+                return Err(format!("{:?} arenas can not have shells", self.arena.kind));
+            }
+            if let Some(scale) = self.arena.shells.iter().find(|s| !(**s > 0.0 && **s < 1.0)) {
+                return Err(format!("shells must be in (0, 1), got {}", scale));
+            }
+            if let Some((i, j)) = crossing_shells(&self.arena) {
+                let name = |i: usize| if i == 0 { "the boundary".to_string() } else { format!("shell {}", self.arena.shells[i - 1]) };
+                return Err(format!("{} and {} cross, every shell has to fit inside the others", name(i), name(j)));
+            }
+        }
+        let transform = &self.arena.transform;
+        if !(transform.rotation.is_finite() && transform.offset.iter().all(|c| c.is_finite())) {
+            return Err("arena rotation and offset must be finite".to_string());
+        }
+        if !(transform.scale > 0.0 && transform.scale.is_finite()) {
+            return Err(format!("arena scale must be larger than 0, got {}", transform.scale));
+        }
+        if !transform.anisotropy.iter().all(|a| *a > 0.0 && a.is_finite()) {
+            return Err(format!("arena anisotropy must be larger than 0, got {:?}", transform.anisotropy));
+        }
+        for scatterer in &self.arena.scatterers {
+            if !scatterer.center.iter().all(|c| c.is_finite()) {
+                return Err("scatterer center must be finite".to_string());
+            }
+            if !(scatterer.radius > 0.0 && scatterer.radius.is_finite()) {
+                return Err(format!("scatterer radius must be larger than 0, got {}", scatterer.radius));
+            }
+        }
+        for obstacle in &self.arena.obstacles {
+            match &obstacle.shape {
+                ObstacleShape::Segment(ends) => {
+                    if !ends.iter().flatten().all(|c| c.is_finite()) {
+                        return Err("obstacle segments must be finite".to_string());
+                    }
+                    if ends[0] == ends[1] {
+                        return Err("obstacle segments need two different ends".to_string());
+                    }
+                }
+                ObstacleShape::Polygon(vertices) => shape::validate_outline(vertices)?,
+                ObstacleShape::Arc { center, radius, start, sweep } => {
+                    if !(center.iter().all(|c| c.is_finite()) && start.is_finite()) {
+                        return Err("obstacle arcs must be finite".to_string());
+                    }
+                    if !(*radius > 0.0 && radius.is_finite()) {
+                        return Err(format!("obstacle arc radius must be larger than 0, got {}", radius));
+                    }
+                    if !(*sweep > 0.0 && *sweep <= 360.0) {
+                        return Err(format!("obstacle arc sweep must be in (0, 360] degrees, got {}", sweep));
+                    }
+                }
+            }
+        }
+        for region in &self.arena.regions {
+            shape::validate_outline(&region.polygon)?;
+            if !(region.index > 0.0 && region.index.is_finite()) {
+                return Err(format!("refractive index must be larger than 0, got {}", region.index));
+            }
+        }
+        if !self.arena.regions.is_empty() && self.has_forces() {
+            return Err("a ball in a curved flight can not refract, use regions without forces".to_string());
+        }
+        for wall in &self.arena.moving_walls {
+            wall.validate()?;
+        }
+        if !self.arena.moving_walls.is_empty() {
+            if self.has_forces() || self.dynamics == Dynamics::Outer {
+                return Err("moving walls are only met by straight flights, without forces or outer billiards".to_string());
+            }
+            if self.ball_radius > 0.0 {
+                return Err("moving walls are thin, use them without a ball radius".to_string());
+            }
+        }
+        if !(self.corner_tolerance > 0.0 && self.corner_tolerance < 0.01) {
+            return Err(format!("corner tolerance must be larger than 0 and less than 0.01, got {}", self.corner_tolerance));
+        }
+        if !(0.0..0.01).contains(&self.reflection_offset) {
+            return Err(format!("reflection offset must be at least 0 and less than 0.01, got {}", self.reflection_offset));
+        }
+        if let Some(tumble) = &self.tumble {
+            tumble.validate()?;
+            if self.dynamics != Dynamics::Inner || self.has_forces() || self.has_several_balls() {
+                return Err("only a single ball in a straight flight of inner billiards tumbles".to_string());
+            }
+        }
+        if !self.bounce_exponent.is_finite() {
+            return Err(format!("bounce exponent must be finite, got {}", self.bounce_exponent));
+        }
+        if self.output.exit_map && (self.dynamics != Dynamics::Inner || self.arena.openings.is_empty()) {
+            return Err("an exit map needs openings, in the arena of inner billiards".to_string());
+        }
+        if self.deposit_at != DepositAt::Termination && self.deposit != Deposit::End {
+            return Err("only end deposits go where the ball started".to_string());
+        }
+        if self.deposit == Deposit::Trail {
+            if self.dynamics != Dynamics::Inner || self.has_several_balls() || self.fold_symmetry {
+                return Err("only a single ball of inner billiards, without folding, is deposited along its trail".to_string());
+            }
+            if self.trail_memory.is_some() || self.trail_full == TrailFull::Forget {
+                return Err("a ball deposited along its trail can not forget trail segments".to_string());
+            }
+        }
+        if let Some(on_cycle) = self.on_cycle {
+            if let OnCycle::Perturb(degrees) = on_cycle {
+                if !(degrees > 0.0 && degrees.is_finite()) {
+                    return Err(format!("cycle perturbation must be larger than 0 degrees, got {}", degrees));
+                }
+            }
+            if !(self.cycle_tolerance > 0.0 && self.cycle_tolerance <= 0.01) {
+                return Err(format!("cycle tolerance must be larger than 0 and at most 0.01, got {}", self.cycle_tolerance));
+            }
+            if self.cycle_window == 0 {
+                return Err("cycle window must be at least 1".to_string());
+            }
+            if self.dynamics != Dynamics::Inner {
+                return Err("periodic orbits are only looked for in inner billiards".to_string());
+            }
+        }
+        if self.corner_rule != CornerRule::Nearest && self.ball_radius > 0.0 {
+            return Err("a thick ball rounds off the corners already, use corner rules without a ball radius".to_string());
+        }
+        let materials = self.arena.scatterers.iter().map(|s| s.material)
+            .chain(self.arena.obstacles.iter().map(|o| o.material))
+            .chain([self.arena.material, self.trail_material]);
+        for material in materials {
+            if let Material::Transmit(probability) = material {
+                if !(0.0..=1.0).contains(&probability) {
+                    return Err(format!("transmit probability must be from 0 to 1, got {}", probability));
+                }
+            }
+            if let Material::Rough(roughness) = material {
+                if !(0.0..=1.0).contains(&roughness) {
+                    return Err(format!("roughness must be from 0 to 1, got {}", roughness));
+                }
+            }
+            if let Material::Sticky(distance) = material {
+                if !(distance > 0.0 && distance.is_finite()) {
+                    return Err(format!("sticky slide distance must be larger than 0, got {}", distance));
+                }
+                if self.dynamics != Dynamics::Inner || self.has_forces() || self.ball_radius > 0.0 {
+                    return Err("only a thin ball in a straight flight of inner billiards slides along sticky walls".to_string());
+                }
+            }
+        }
+        for opening in &self.arena.openings {
+            if !opening.center.iter().all(|c| c.is_finite()) {
+                return Err("opening center must be finite".to_string());
+            }
+            if !(opening.radius > 0.0 && opening.radius.is_finite()) {
+                return Err(format!("opening radius must be larger than 0, got {}", opening.radius));
+            }
+        }
+        if self.arena.kind == ArenaKind::Random && self.arena.edges.saturating_add(self.arena.random_scatterers) > MAX_NO_WALLS {
+            return Err(format!("an arena can have at most {} walls, got {} edges and {} scatterers",
+                               MAX_NO_WALLS, self.arena.edges, self.arena.random_scatterers));
+        }
+        if !(1..=MAX_COMPOSITE).contains(&self.arena.composite) {
+            return Err(format!("composite must be from 1 to {}, got {}", MAX_COMPOSITE, self.arena.composite));
+        }
+        if self.arena.composite > 1 && !matches!(self.arena.kind, ArenaKind::Random | ArenaKind::Rough | ArenaKind::Maze | ArenaKind::Open) {
+            return Err(format!("{:?} arenas are not random, a composite of them would be copies on top of each other", self.arena.kind));
+        }
+        if self.arena.kind == ArenaKind::Maze && !(1..=MAX_MAZE_SIZE).contains(&self.arena.maze_size) {
+            return Err(format!("maze size must be from 1 to {}, got {}", MAX_MAZE_SIZE, self.arena.maze_size));
+        }
+        if self.arena.kind == ArenaKind::Open {
+            let [lo, hi] = self.arena.viewport;
+            if !self.arena.viewport.iter().flatten().all(|c| c.is_finite()) || lo[0] >= hi[0] || lo[1] >= hi[1] {
+                return Err(format!("viewport must go from its lower to its upper corner, got {:?}", self.arena.viewport));
+            }
+            // Every step of a ball is 10 long, and has to be able to hit the edge
+            if hi[0] - lo[0] > MAX_VIEWPORT_SIZE || hi[1] - lo[1] > MAX_VIEWPORT_SIZE {
+                return Err(format!("viewport can be at most {} images wide and high", MAX_VIEWPORT_SIZE));
+            }
+            if !(self.arena.segment_density >= 0.0 && self.arena.segment_density.is_finite()) {
+                return Err(format!("segment density can not be negative, got {}", self.arena.segment_density));
+            }
+            if !(self.arena.segment_length > 0.0 && self.arena.segment_length.is_finite()) {
+                return Err(format!("segment length must be larger than 0, got {}", self.arena.segment_length));
+            }
+            if open_segment_count(&self.arena) > MAX_NO_WALLS {
+                return Err(format!("an arena can have at most {} walls, got {} segments",
+                                   MAX_NO_WALLS, open_segment_count(&self.arena)));
+            }
+        }
+        let no_walls = initial_arena(&self.arena, self.arena.seed).walls.len();
+        if no_walls > MAX_NO_WALLS {
+            let hint = match self.arena.kind {
+                ArenaKind::Mask => ", a larger mask tolerance gives simpler contours",
+                _ => ""
+            };
+            return Err(format!("an arena can have at most {} walls, got {}{}", MAX_NO_WALLS, no_walls, hint));
+        }
+        if self.tiles.contains(&0) {
+            return Err("there must be at least 1 column and 1 row of tiles".to_string());
+        }
+        if !self.width.is_multiple_of(self.tiles[0]) || !self.height.is_multiple_of(self.tiles[1]) {
+            return Err(format!("a {} x {} image can not be split into {} x {} equal tiles",
+                               self.width, self.height, self.tiles[0], self.tiles[1]));
+        }
+        if self.fold_symmetry {
+            arena_symmetry(&self.arena)?;
+            if self.tiles != [1, 1] {
+                return Err("tiled runs can not be folded".to_string());
+            }
+            if self.output.vector_field {
+                return Err("vector fields can not be folded, the directions of the wedge would not be mirrored".to_string());
+            }
+        }
+        if !(self.reflection_jitter >= 0.0 && self.reflection_jitter.is_finite()) {
+            return Err(format!("reflection jitter must be 0 or larger, got {}", self.reflection_jitter));
+        }
+        if !self.gravity.iter().all(|g| g.is_finite()) {
+            return Err(format!("gravity must be finite, got {:?}", self.gravity));
+        }
+        if !(self.flight_step > 0.0 && self.flight_step.is_finite()) {
+            return Err(format!("flight step must be larger than 0, got {}", self.flight_step));
+        }
+        if let Some(radius) = self.larmor_radius {
+            if !(radius != 0.0 && radius.is_finite()) {
+                return Err(format!("larmor radius must be finite and not 0, got {}", radius));
+            }
+        }
+        if let Some(wind) = &self.wind {
+            wind.validate()?;
+        }
+        if let Some(potential) = &self.potential {
+            potential.validate()?;
+        }
+        if !(self.frame_rotation.is_finite() && self.frame_center.iter().all(|c| c.is_finite())) {
+            return Err("frame rotation and center must be finite".to_string());
+        }
+        if !(self.restitution > 0.0 && self.restitution <= 1.0) {
+            return Err(format!("restitution must be larger than 0 and at most 1, got {}", self.restitution));
+        }
+        if let Some(restitution) = self.trail_restitution.filter(|r| !(*r > 0.0 && *r <= 1.0)) {
+            return Err(format!("trail restitution must be larger than 0 and at most 1, got {}", restitution));
+        }
+        if !(0.0..1.0).contains(&self.min_energy) {
+            return Err(format!("minimum energy must be at least 0 and less than 1, got {}", self.min_energy));
+        }
+        if !(self.deceleration >= 0.0 && self.deceleration.is_finite()) {
+            return Err(format!("deceleration must be at least 0, got {}", self.deceleration));
+        }
+        if !(self.bounce_speedup > 0.0 && self.bounce_speedup.is_finite()) {
+            return Err(format!("bounce speedup must be larger than 0, got {}", self.bounce_speedup));
+        }
+        if self.deceleration > 0.0 || self.bounce_speedup != 1.0 {
+            if self.dynamics == Dynamics::Outer {
+                return Err("outer billiards jump, they have no speed to change".to_string());
+            }
+            if self.has_forces() {
+                return Err("forces set the speed of a curved flight, use deceleration and bounce speedup without them".to_string());
+            }
+        }
+        if !(0.0..MAX_BALL_RADIUS).contains(&self.ball_radius) {
+            return Err(format!("ball radius must be at least 0 and less than {}, got {}", MAX_BALL_RADIUS, self.ball_radius));
+        }
+        if !(0.0..MAX_BALL_RADIUS).contains(&self.trail_width) {
+            return Err(format!("trail width must be at least 0 and less than {}, got {}", MAX_BALL_RADIUS, self.trail_width));
+        }
+        if self.has_forces() {
+            if self.dynamics == Dynamics::Outer {
+                return Err("outer billiards jump, there is no flight for forces to bend".to_string());
+            }
+            if self.fold_symmetry {
+                return Err("forces break the symmetry of the arena, it can not be folded".to_string());
+            }
+        }
+        if self.balls == 0 {
+            return Err("there has to be at least 1 ball".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.branching) {
+            return Err(format!("branching must be in [0, 1], got {}", self.branching));
+        }
+        if self.has_several_balls() {
+            if self.dynamics == Dynamics::Outer {
+                return Err("outer billiards have a single point, not several or branching balls".to_string());
+            }
+            if self.has_forces() || self.ball_radius > 0.0 || self.trail_width > 0.0 {
+                return Err("several or branching balls fly straight, without forces, and without a ball radius or trail width".to_string());
+            }
+        }
+        // A ball that is not trapped by a full trail could go on forever
+        if self.trail_full != TrailFull::Trap && self.max_bounces.is_none() && self.max_path_length.is_none()
+            && !(self.restitution.min(self.trail_restitution.unwrap_or(1.0)) < 1.0 && self.min_energy > 0.0) && self.deceleration == 0.0 {
+            return Err("without trapping balls when the trail is full, they need max_bounces, max_path_length, \
+                        a restitution and min_energy, or a deceleration to stop".to_string());
+        }
+        if self.max_bounces == Some(0) {
+            return Err("max bounces must be at least 1".to_string());
+        }
+        if let Some(length) = self.max_path_length {
+            if !(length > 0.0 && length.is_finite()) {
+                return Err(format!("max path length must be larger than 0, got {}", length));
+            }
+        }
+        if let Some(radius) = self.return_radius {
+            if !(radius > 0.0 && radius.is_finite()) {
+                return Err(format!("return radius must be larger than 0, got {}", radius));
+            }
+        }
+        if self.trail_window == Some(0) {
+            return Err("trail window must be at least 1, leave it out for all segments".to_string());
+        }
+        if self.trail_length == Some(0) {
+            return Err("trail length must be at least 1".to_string());
+        }
+        if let Some(geometry) = geodesic::geometry(self.dynamics, self.sphere_projection) {
+            let name = if self.dynamics == Dynamics::Hyperbolic { "hyperbolic" } else { "spherical" };
+            geodesic::validate_arena(&initial_arena(&self.arena, self.arena.seed), geometry.as_ref(), name)?;
+            if self.has_forces() || self.has_several_balls() || self.ball_radius > 0.0 || self.trail_width > 0.0
+                || self.deceleration > 0.0 || self.bounce_speedup != 1.0 {
+                return Err(format!("{} billiards have a single thin ball, that flies along geodesics at the same speed", name));
+            }
+            if self.fold_symmetry || self.return_radius.is_some() || self.reflection_jitter > 0.0 || !self.arena.openings.is_empty()
+                || !self.arena.regions.is_empty() || !self.arena.moving_walls.is_empty() {
+                return Err(format!("{} billiards can not be folded, and have no return radius, reflection jitter, openings, \
+                                    regions or moving walls", name));
+            }
+        }
+        if self.dynamics == Dynamics::Solid {
+            self.solid.validate()?;
+            if self.ball_radius == 0.0 && self.trail_width == 0.0 {
+                return Err("solid billiards need a ball radius or a trail width, a thin ball never hits its trail in space".to_string());
+            }
+            let inradius = Solid::new(&self.solid).inradius();
+            if self.ball_radius >= inradius {
+                return Err(format!("ball radius must be less than {:.4} to fit in a {}, got {}",
+                                   inradius, self.solid.polyhedron.to_possible_value().unwrap().get_name(), self.ball_radius));
+            }
+            if !matches!(self.trail_material, Material::Reflect | Material::Absorb) || self.trail_sides != TrailSides::Both
+                || self.trail_memory.is_some() || self.trail_window.is_some() {
+                return Err("the trail of solid billiards reflects or absorbs, from both sides, and all of it counts".to_string());
+            }
+            if self.has_forces() || self.has_several_balls() || self.deceleration > 0.0 || self.bounce_speedup != 1.0 {
+                return Err("solid billiards have a single ball, that flies straight at the same speed".to_string());
+            }
+            if self.fold_symmetry || self.return_radius.is_some() || self.reflection_jitter > 0.0 || !self.arena.openings.is_empty()
+                || !self.arena.regions.is_empty() || !self.arena.moving_walls.is_empty() {
+                return Err("solid billiards can not be folded, and have no return radius, reflection jitter, openings, \
+                            regions or moving walls".to_string());
+            }
+        }
+        if !(self.table_scale > 0.0 && self.table_scale <= 1.0) {
+            return Err(format!("table scale must be in (0, 1], got {}", self.table_scale));
+        }
+        if self.dynamics == Dynamics::Outer && outer::table(&initial_arena(&self.arena, self.arena.seed), 1.0).len() < 3 {
+            return Err("outer billiards need an arena with closed walls, their convex hull is the table".to_string());
+        }
+        let weight = build_shader(self)?;
+        build_color_shader(self, &weight)?;
+        if self.color.is_some() && self.output.escape_map {
+            return Err("color runs have no escape map".to_string());
+        }
+        build_layered_shader(self, &weight)?;
+        if self.color.is_some() && !self.extra_shaders.is_empty() {
+            return Err("color runs have no extra shaders".to_string());
+        }
+        if let Some(wavenumber) = self.wavenumber {
+            if !wavenumber.is_finite() {
+                return Err(format!("wavenumber must be finite, got {}", wavenumber));
+            }
+            if self.color.is_some() || !self.extra_shaders.is_empty() || self.output.escape_map {
+                return Err("phase runs are not in color, and have no extra shaders or escape map".to_string());
+            }
+        }
+        if let Some(bins) = self.direction_bins {
+            if !(2..=MAX_BINS).contains(&bins) {
+                return Err(format!("direction bins must be between 2 and {}, got {}", MAX_BINS, bins));
+            }
+            if self.color.is_some() || self.wavenumber.is_some() || !self.extra_shaders.is_empty() || self.output.escape_map {
+                return Err("direction runs are not in color or phase runs, and have no extra shaders or escape map".to_string());
+            }
+            if self.fold_symmetry {
+                return Err("direction runs can not be folded, the directions of the wedge would not be mirrored".to_string());
+            }
+        }
+        if !self.bounce_bands.is_empty() {
+            if self.bounce_bands.len() >= MAX_BINS {
+                return Err(format!("there can be at most {} bounce bands, got {}", MAX_BINS, self.bounce_bands.len() + 1));
+            }
+            if self.bounce_bands.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(format!("bounce bands must have increasing bounce counts, got {:?}", self.bounce_bands));
+            }
+            if self.direction_bins.is_some() {
+                return Err("band runs can not be direction runs as well".to_string());
+            }
+            if self.color.is_some() || self.wavenumber.is_some() || !self.extra_shaders.is_empty() || self.output.escape_map {
+                return Err("band runs are not in color or phase runs, and have no extra shaders or escape map".to_string());
+            }
+        }
+        if let Some(hue) = self.output.bin_hues.iter().find(|hue| !hue.is_finite()) {
+            return Err(format!("bin hues must be finite, got {}", hue));
+        }
+        if !self.output.bin_hues.is_empty() && Some(self.output.bin_hues.len()) != self.bins() {
+            return Err(format!("there must be a bin hue for each of the {} bins, got {}", self.bins().unwrap_or(0), self.output.bin_hues.len()));
+        }
+        // The reduction is of the gray canvas and its escape map, the channels of the other
+        // outputs are always summed
+        if self.reduction != Reduction::Sum && (self.color.is_some() || self.wavenumber.is_some() || self.bins().is_some()
+                                                || !self.extra_shaders.is_empty() || self.output.vector_field) {
+            return Err("only runs that are not in color, phase, direction or band runs, and have no extra shaders or \
+                        vector field, can combine the weights in a pixel other than by summing them".to_string());
+        }
+        if !self.shader_exponent.is_finite() {
+            return Err(format!("shader exponent must be finite, got {}", self.shader_exponent));
+        }
+        template::render(&self.output.name, &template::run_vars(self, "", ""))?;
+
+        Ok(())
+    }
+}
+
+
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table)
+{
+    for (key, value) in overrides {
+        if let (Some(toml::Value::Table(base_table)), toml::Value::Table(override_table)) = (base.get_mut(&key), &value) {
+            merge_tables(base_table, override_table.clone());
+        } else {
+            base.insert(key, value);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use cgmath::{Vector2, Vector3};
+use cgmath::num_traits::Zero;
+use serde::{Deserialize, Serialize};
+use simple_canvas::Canvas;
+
+use crate::config::RunConfig;
+use crate::output::{bin_colors, bin_hues, bin_sum, channel_sum, phase_image};
+use crate::simulation::{Bins, Tally};
+
+// A dump is the raw accumulation canvas of a simulation run, so it can be post
+// processed again later without simulating again. Layout:
+//
+//   MAGIC | header length: u64 LE | header: TOML text | width * height * channels f64 LE
+//
+// The channels of a color run follow each other per pixel, red, green and blue, those
+// of a phase run, the real and imaginary part, and those of a direction run, its bins.
+const MAGIC: &[u8; 8] = b"SABDUMP1";
+
+// Of version 1.0 of the .npy format
+const NPY_MAGIC: &[u8; 8] = b"\x93NUMPY\x01\x00";
+
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DumpHeader {
+    pub width: usize,
+    pub height: usize,
+    pub simulations: usize,
+    pub tally: Option<Tally>,       // not in dumps of older versions
+    pub channels: Option<usize>,    // 3 for color runs, 2 for phase runs, the bins of direction runs, 1 when missing
+    pub config: RunConfig,
+}
+
+
+pub struct Dump {
+    pub header: DumpHeader,
+    pub canvas: Canvas<f64>,        // of a color run, the sum of its channels, of a phase run, its phase image
+    pub colors: Option<Canvas<Vector3<f64>>>,   // also the colors of the directions of a direction run
+    pub phases: Option<Canvas<Vector2<f64>>>,
+    pub bins: Option<Canvas<Bins>>,
+}
+
+
+// What a dump has in every pixel, besides the canvas
+#[derive(Clone, Copy)]
+pub enum Channels<'a> {
+    Gray,
+    Colors(&'a Canvas<Vector3<f64>>),
+    Phases(&'a Canvas<Vector2<f64>>),
+    Bins(&'a Canvas<Bins>, usize),
+}
+
+
+impl<'a> Channels<'a> {
+    // The channels of a run with these canvases, the bins of a direction or band run
+    // before its colors
+    pub fn of(config: &RunConfig, bins: Option<&'a Canvas<Bins>>, phases: Option<&'a Canvas<Vector2<f64>>>,
+              colors: Option<&'a Canvas<Vector3<f64>>>) -> Channels<'a>
+    {
+        match (bins, phases, colors) {
+            (Some(bins), _, _) => Channels::Bins(bins, config.bins().unwrap()),
+            (_, Some(phases), _) => Channels::Phases(phases),
+            (_, _, Some(colors)) => Channels::Colors(colors),
+            _ => Channels::Gray,
+        }
+    }
+
+    pub fn count(&self) -> Option<usize>
+    {
+        match self {
+            Channels::Gray => None,
+            Channels::Colors(_) => Some(3),
+            Channels::Phases(_) => Some(2),
+            Channels::Bins(_, bins) => Some(*bins),
+        }
+    }
+}
+
+
+// The channels are written instead of the canvas, when there are any
+pub fn write_dump(path: &Path, header: &DumpHeader, canvas: &Canvas<f64>, channels: Channels) -> Result<(), String>
+{
+    let header_text = toml::to_string(header).map_err(|e| e.to_string())?;
+    let write_error = |e: std::io::Error| format!("Could not write dump {}: {}", path.display(), e);
+
+    let mut f = BufWriter::new(File::create(path).map_err(write_error)?);
+    f.write_all(MAGIC).map_err(write_error)?;
+    f.write_all(&(header_text.len() as u64).to_le_bytes()).map_err(write_error)?;
+    f.write_all(header_text.as_bytes()).map_err(write_error)?;
+    for v in channel_values(canvas, channels) {
+        f.write_all(&v.to_le_bytes()).map_err(write_error)?;
+    }
+    f.flush().map_err(write_error)
+}
+
+
+// Write the same values as the dump, as a NumPy .npy array of little endian f64, with
+// the channels, when there are any, in its last axis
+pub fn write_npy(path: &Path, canvas: &Canvas<f64>, channels: Channels) -> Result<(), String>
+{
+    let shape = match channels.count() {
+        Some(n) => format!("({}, {}, {})", canvas.height, canvas.width, n),
+        None => format!("({}, {})", canvas.height, canvas.width),
+    };
+    // The header is padded with spaces, and ends in a newline, so the data starts on a
+    // multiple of 64 bytes
+    let mut header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}", shape);
+    header.push_str(&" ".repeat(63 - (NPY_MAGIC.len() + 2 + header.len()) % 64));
+    header.push('\n');
+    let write_error = |e: std::io::Error| format!("Could not write array {}: {}", path.display(), e);
+
+    let mut f = BufWriter::new(File::create(path).map_err(write_error)?);
+    f.write_all(NPY_MAGIC).map_err(write_error)?;
+    f.write_all(&(header.len() as u16).to_le_bytes()).map_err(write_error)?;
+    f.write_all(header.as_bytes()).map_err(write_error)?;
+    for v in channel_values(canvas, channels) {
+        f.write_all(&v.to_le_bytes()).map_err(write_error)?;
+    }
+    f.flush().map_err(write_error)
+}
+
+
+// The values of every pixel in turn, the channels of a pixel after each other
+fn channel_values<'a>(canvas: &'a Canvas<f64>, channels: Channels<'a>) -> Box<dyn Iterator<Item = f64> + 'a>
+{
+    match channels {
+        Channels::Gray => Box::new(canvas.data.iter().copied()),
+        Channels::Colors(colors) => Box::new(colors.data.iter().flat_map(|c| [c.x, c.y, c.z])),
+        Channels::Phases(phases) => Box::new(phases.data.iter().flat_map(|z| [z.x, z.y])),
+        Channels::Bins(bins, n) => Box::new(bins.data.iter().flat_map(move |v| v.0.into_iter().take(n))),
+    }
+}
+
+
+pub fn read_dump(path: &Path) -> Result<Dump, String>
+{
+    let read_error = |e: std::io::Error| format!("Could not read dump {}: {}", path.display(), e);
+
+    let mut f = BufReader::new(File::open(path).map_err(read_error)?);
+
+    let mut magic = [0u8; 8];
+    f.read_exact(&mut magic).map_err(read_error)?;
+    if &magic != MAGIC {
+        return Err(format!("{} is not a simulation dump", path.display()));
+    }
+
+    let mut header_len = [0u8; 8];
+    f.read_exact(&mut header_len).map_err(read_error)?;
+    let mut header_text = vec![0u8; u64::from_le_bytes(header_len) as usize];
+    f.read_exact(&mut header_text).map_err(read_error)?;
+    let header: DumpHeader = toml::from_str(&String::from_utf8_lossy(&header_text))
+        .map_err(|e| format!("Corrupt header in dump {}: {}", path.display(), e))?;
+
+    let mut values: Vec<f64> = vec![0.0; header.width * header.height * header.channels.unwrap_or(1)];
+    let mut value = [0u8; 8];
+    for v in values.iter_mut() {
+        f.read_exact(&mut value).map_err(read_error)?;
+        *v = f64::from_le_bytes(value);
+    }
+
+    let (width, height) = (header.width, header.height);
+    let (mut colors, mut phases, mut bins) = (None, None, None);
+    match (header.channels, header.config.bins()) {
+        (Some(n), Some(b)) if n == b => {
+            let data = values.chunks_exact(n).map(|v| {
+                let mut bins = Bins::zero();
+                bins.0[..n].copy_from_slice(v);
+                bins
+            });
+            bins = Some(Canvas { data: data.collect(), width, height });
+        }
+        (None | Some(1), _) => {}
+        (Some(2), _) => phases = Some(Canvas { data: values.chunks_exact(2).map(|z| Vector2::new(z[0], z[1])).collect(), width, height }),
+        (Some(3), _) => colors = Some(Canvas { data: values.chunks_exact(3).map(|c| Vector3::new(c[0], c[1], c[2])).collect(), width, height }),
+        (Some(n), _) => return Err(format!("Dump {} has {} channels, only 1, 2, 3 or its bins are known", path.display(), n)),
+    }
+    let canvas = match (&colors, &phases, &bins) {
+        (Some(colors), _, _) => channel_sum(colors),
+        (_, Some(phases), _) => phase_image(phases, header.config.output.phase_image),
+        (_, _, Some(bins)) => bin_sum(bins),
+        _ => Canvas { data: values, width, height },
+    };
+    // The image of a direction or band run colors the bins
+    if let Some(bins) = &bins {
+        colors = Some(bin_colors(bins, &bin_hues(&header.config)));
+    }
+
+    Ok(Dump { header, canvas, colors, phases, bins })
+}
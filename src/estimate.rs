@@ -0,0 +1,89 @@
+use std::time::{Duration, Instant};
+
+use crate::config::{ImageFormat, Reduction, RunConfig};
+use crate::runner::WorkerPool;
+use crate::shaders::MAX_LAYERS;
+use crate::simulation::MAX_BINS;
+
+
+// Run a short calibration burst of a config, and print what the full run is going
+// to cost. Returns the estimated run time.
+pub fn dry_run(pool: &WorkerPool, config: &RunConfig, calibration_sims: usize) -> Result<Duration, String>
+{
+    let mut calibration_config = config.clone();
+    calibration_config.sims = calibration_sims.min(config.sims).max(1);
+    calibration_config.duration = None;
+
+    let start_t = Instant::now();
+    let simulations = pool.simulate(&calibration_config)?.simulations;
+    let rate = simulations as f64 / start_t.elapsed().as_secs_f64();
+
+    let (runtime, sims) = match config.duration {
+        Some(duration) => (duration, (rate * duration.as_secs_f64()) as usize),
+        None => (Duration::from_secs_f64(config.sims as f64 / rate), config.sims)
+    };
+
+    let pixels = config.width * config.height;
+    // every thread has its own canvas, plus the shared one, a copy of it and the normalized
+    // image. The escape map needs all of that a second time. A color run has three channels
+    // in its canvases, a phase run two, a direction or band run all its bins, a run with extra
+    // shaders a layer for each shader, and all of them end in images of their own. A
+    // run that does not sum the weights keeps a count and the reduction with them. The
+    // phases end in one image, the bins in a color image, but all are in the dump. First
+    // arrivals, hit counts and exit maps each take one more canvas per thread, and a dump
+    // and image of their own. A vector field takes two, and an image of two float pages.
+    let escapes = if config.output.escape_map { 2 } else { 1 };
+    let (layers, images) = match (&config.color, config.extra_shaders.len()) {
+        (Some(_), _) => (3, 3),
+        (None, 0) if config.bins().is_some() => (MAX_BINS, 3),
+        (None, 0) if config.wavenumber.is_some() => (2, 1),
+        (None, 0) if config.reduction != Reduction::Sum => (3, 1),
+        (None, 0) => (1, 1),
+        (None, extra) => (MAX_LAYERS, 1 + extra),
+    };
+    let arrival_files = config.first_arrival.map_or(0, |_| 1) + [config.output.hit_counts, config.output.exit_map].iter().filter(|on| **on).count();
+    let arrivals = arrival_files * ((pool.size() + 2) * pixels * size_of::<f64>() + pixels * size_of::<u32>());
+    let vector_components = if config.output.vector_field { 2 } else { 0 };
+    let vectors = vector_components * ((pool.size() + 2) * pixels * size_of::<f64>() + pixels * size_of::<f32>());
+    let peak_memory = escapes * ((pool.size() + 2) * layers * pixels * size_of::<f64>() + images * pixels * size_of::<u32>())
+        + arrivals + vectors;
+    let dumped = match (config.wavenumber, config.bins()) {
+        (Some(_), _) => 2,
+        (_, Some(bins)) => bins,
+        _ => images,
+    };
+    let dump_size = escapes * (dumped * pixels * size_of::<f64>() + 1024) + arrival_files * (pixels * size_of::<f64>() + 1024);
+    // The .npy arrays have the same values as the dumps
+    let dump_size = if config.output.npy { dump_size * 2 } else { dump_size };
+    // Pngs take at most as many bytes as their samples, they are compressed
+    let sample = match config.output.image_format {
+        ImageFormat::Tiff => size_of::<u32>(),
+        ImageFormat::Png8 => size_of::<u8>(),
+        ImageFormat::Png16 => size_of::<u16>(),
+    };
+    let image_size = escapes * (images * pixels * sample + 1024) + arrival_files * (pixels * sample + 1024)
+        + vector_components * (pixels * size_of::<f32>() + 1024);
+
+    println!("throughput:         {:.0} simulations/s on {} threads", rate, pool.size());
+    println!("simulations:        {}", sims);
+    println!("estimated runtime:  {}", humantime::format_duration(Duration::from_secs(runtime.as_secs())));
+    println!("peak memory:        {}", format_bytes(peak_memory));
+    println!("dump size:          {}", format_bytes(dump_size));
+    println!("image size:         {}", format_bytes(image_size));
+
+    Ok(runtime)
+}
+
+
+fn format_bytes(bytes: usize) -> String
+{
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
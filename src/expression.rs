@@ -0,0 +1,334 @@
+use crate::scene::Contact;
+use crate::shaders::Shader;
+use crate::simulation::{Termination, Trajectory, Trap};
+
+
+type Variable = fn(&Trajectory) -> f64;
+type Function = fn(f64) -> f64;
+type FunctionOfTwo = fn(f64, f64) -> f64;
+
+
+// A shader written as an arithmetic expression of what the trajectory tells, like
+// "log(1 + path_length) * bounces". It is parsed once, and evaluated for every ball.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(f64),
+    Variable(&'static str, Variable),   // with its name
+    Unary(Function, Box<Expr>),
+    Binary(FunctionOfTwo, Box<Expr>, Box<Expr>),
+}
+
+
+pub const VARIABLES: &[(&str, Variable)] = &[
+    ("path_length", |t| t.path_length),
+    ("travel_time", |t| t.travel_time),
+    ("bounces", |t| t.no_bounces as f64),
+    ("energy", |t| t.energy),
+    ("displacement", |t| t.displacement),
+    ("start_x", |t| t.start_pos.x),
+    ("start_y", |t| t.start_pos.y),
+    ("end_x", |t| t.end_pos.x),
+    ("end_y", |t| t.end_pos.y),
+    ("end_dx", |t| t.end_dir.x),
+    ("end_dy", |t| t.end_dir.y),
+    ("end_angle", |t| t.end_dir.y.atan2(t.end_dir.x)),
+    ("winding", |t| t.winding),
+    ("crossings", |t| t.crossings as f64),
+    ("end_wall", |t| match t.contact { Some(Contact::Wall(i)) => i as f64, _ => -1.0 }),
+    ("end_segment", |t| match t.contact { Some(Contact::Trail(n)) => n as f64, _ => -1.0 }),
+    ("trapped", |t| matches!(t.termination, Termination::Trapped(_)) as usize as f64),
+    ("self_hit", |t| (t.termination == Termination::Trapped(Trap::SelfHit)) as usize as f64),
+    ("trail_full", |t| (t.termination == Termination::Trapped(Trap::TrailFull)) as usize as f64),
+    ("degenerate", |t| (t.termination == Termination::Trapped(Trap::Degenerate)) as usize as f64),
+    ("escaped", |t| (t.termination == Termination::Escaped) as usize as f64),
+    ("absorbed", |t| (t.termination == Termination::Absorbed) as usize as f64),
+    ("stopped", |t| (t.termination == Termination::Stopped) as usize as f64),
+    ("bounce_limit", |t| (t.termination == Termination::BounceLimit) as usize as f64),
+    ("path_limit", |t| (t.termination == Termination::PathLimit) as usize as f64),
+    ("returned", |t| (t.termination == Termination::Returned) as usize as f64),
+    ("periodic", |t| (t.termination == Termination::Periodic) as usize as f64),
+];
+
+const CONSTANTS: &[(&str, f64)] = &[("pi", std::f64::consts::PI), ("e", std::f64::consts::E)];
+
+const FUNCTIONS: &[(&str, Function)] = &[
+    ("log", f64::ln), ("log10", f64::log10), ("exp", f64::exp), ("sqrt", f64::sqrt), ("abs", f64::abs),
+    ("floor", f64::floor), ("ceil", f64::ceil), ("sin", f64::sin), ("cos", f64::cos), ("tanh", f64::tanh),
+];
+
+const FUNCTIONS_OF_TWO: &[(&str, FunctionOfTwo)] = &[("min", f64::min), ("max", f64::max), ("pow", f64::powf)];
+
+
+impl Expr {
+    pub fn eval(&self, trajectory: &Trajectory) -> f64
+    {
+        match self {
+            Expr::Number(x) => *x,
+            Expr::Variable(_, variable) => variable(trajectory),
+            Expr::Unary(f, a) => f(a.eval(trajectory)),
+            Expr::Binary(f, a, b) => f(a.eval(trajectory), b.eval(trajectory)),
+        }
+    }
+
+    // Whether it uses the variable, somewhere in it
+    fn uses(&self, name: &str) -> bool
+    {
+        match self {
+            Expr::Variable(variable, _) => *variable == name,
+            Expr::Unary(_, a) => a.uses(name),
+            Expr::Binary(_, a, b) => a.uses(name) || b.uses(name),
+            Expr::Number(_) => false,
+        }
+    }
+}
+
+
+impl Shader<f64> for Expr {
+    fn shade(&self, trajectory: &Trajectory) -> f64
+    {
+        self.eval(trajectory)
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        self.uses("crossings")
+    }
+}
+
+
+// Parses an expression of numbers, the variables and constants above, the operators
+// + - * / and ^, that binds strongest and to the right, parentheses, and calls of the
+// functions above
+pub fn parse(source: &str) -> Result<Expr, String>
+{
+    let mut parser = Parser { source: source.as_bytes(), pos: 0 };
+    let expr = parser.sum()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(_) => Err(parser.error("an operator")),
+    }
+}
+
+
+struct Parser<'a> {
+    source: &'a [u8],
+    pos: usize,
+}
+
+
+impl Parser<'_> {
+    // The next character that is not white space
+    fn peek(&mut self) -> Option<u8>
+    {
+        while self.source.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        self.source.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool
+    {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn error(&self, expected: &str) -> String
+    {
+        format!("expected {} at position {} of the shader expression", expected, self.pos + 1)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String>
+    {
+        let mut expr = self.product()?;
+        loop {
+            let op: FunctionOfTwo = if self.eat(b'+') { |a, b| a + b } else if self.eat(b'-') { |a, b| a - b } else { break };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String>
+    {
+        let mut expr = self.unary()?;
+        loop {
+            let op: FunctionOfTwo = if self.eat(b'*') { |a, b| a * b } else if self.eat(b'/') { |a, b| a / b } else { break };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    // -x^2 is -(x^2), and 2^-x is 2^(-x)
+    fn unary(&mut self) -> Result<Expr, String>
+    {
+        if self.eat(b'-') {
+            return Ok(Expr::Unary(|a| -a, Box::new(self.unary()?)));
+        }
+        let base = self.atom()?;
+        match self.eat(b'^') {
+            true => Ok(Expr::Binary(f64::powf, Box::new(base), Box::new(self.unary()?))),
+            false => Ok(base),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String>
+    {
+        if self.eat(b'(') {
+            let expr = self.sum()?;
+            return match self.eat(b')') {
+                true => Ok(expr),
+                false => Err(self.error("')'")),
+            };
+        }
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == b'.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() || c == b'_' => self.name(),
+            _ => Err(self.error("a number, a name or '('")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Expr, String>
+    {
+        let start = self.pos;
+        let digits = |parser: &mut Parser| {
+            while parser.source.get(parser.pos).is_some_and(|c| c.is_ascii_digit() || *c == b'.') {
+                parser.pos += 1;
+            }
+        };
+        digits(self);
+        // An exponent, when the e is followed by one
+        let exponent = &self.source[self.pos..];
+        if let [b'e' | b'E', rest @ ..] = exponent {
+            if let [b'0'..=b'9', ..] | [b'+' | b'-', b'0'..=b'9', ..] = rest {
+                self.pos += 2;
+                digits(self);
+            }
+        }
+        let text = std::str::from_utf8(&self.source[start..self.pos]).unwrap();
+        text.parse::<f64>().map(Expr::Number).map_err(|_| format!("'{}' in the shader expression is not a number", text))
+    }
+
+    // A variable, a constant or a call
+    fn name(&mut self) -> Result<Expr, String>
+    {
+        let start = self.pos;
+        while self.source.get(self.pos).is_some_and(|c| c.is_ascii_alphanumeric() || *c == b'_') {
+            self.pos += 1;
+        }
+        let name = std::str::from_utf8(&self.source[start..self.pos]).unwrap();
+
+        if !self.eat(b'(') {
+            if let Some((name, variable)) = VARIABLES.iter().find(|(n, _)| *n == name) {
+                return Ok(Expr::Variable(name, *variable));
+            }
+            if let Some((_, value)) = CONSTANTS.iter().find(|(n, _)| *n == name) {
+                return Ok(Expr::Number(*value));
+            }
+            let names: Vec<&str> = VARIABLES.iter().map(|(n, _)| *n).chain(CONSTANTS.iter().map(|(n, _)| *n)).collect();
+            return Err(format!("unknown variable '{}' in the shader expression, available are: {}", name, names.join(", ")));
+        }
+
+        let mut args = vec![self.sum()?];
+        while self.eat(b',') {
+            args.push(self.sum()?);
+        }
+        if !self.eat(b')') {
+            return Err(self.error("',' or ')'"));
+        }
+        let function = FUNCTIONS.iter().find(|(n, _)| *n == name);
+        let function_of_two = FUNCTIONS_OF_TWO.iter().find(|(n, _)| *n == name);
+        match (function, function_of_two, args.len()) {
+            (Some((_, f)), _, 1) => Ok(Expr::Unary(*f, Box::new(args.remove(0)))),
+            (_, Some((_, f)), 2) => {
+                let b = args.pop().unwrap();
+                Ok(Expr::Binary(*f, Box::new(args.pop().unwrap()), Box::new(b)))
+            }
+            (Some(_), _, n) => Err(format!("{} in the shader expression takes 1 argument, got {}", name, n)),
+            (_, Some(_), n) => Err(format!("{} in the shader expression takes 2 arguments, got {}", name, n)),
+            (None, None, _) => {
+                let names: Vec<&str> = FUNCTIONS.iter().map(|(n, _)| *n).chain(FUNCTIONS_OF_TWO.iter().map(|(n, _)| *n)).collect();
+                Err(format!("unknown function '{}' in the shader expression, available are: {}", name, names.join(", ")))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use geo::coord;
+
+    use super::*;
+
+    // A ball of path length 2 that bounced 3 times, and ended against wall 4
+    fn ball() -> Trajectory
+    {
+        let center = coord! {x: 0.5, y: 0.5};
+        Trajectory { start_pos: center, end_pos: center, end_dir: coord! {x: 1.0, y: 0.0}, path_length: 2.0, travel_time: 2.0,
+                     no_bounces: 3, energy: 1.0, termination: Termination::Trapped(Trap::SelfHit), bounce_points: Vec::new(),
+                     winding: 0.0, crossings: 0, displacement: 0.0, contact: Some(Contact::Wall(4)), exit: None }
+    }
+
+    fn value(source: &str) -> f64
+    {
+        parse(source).unwrap().eval(&ball())
+    }
+
+    #[test]
+    fn operators_bind_by_precedence()
+    {
+        assert_eq!(value("1 + 2 * 3"), 7.0);
+        assert_eq!(value("(1 + 2) * 3"), 9.0);
+        assert_eq!(value("10 - 4 - 3"), 3.0);
+        assert_eq!(value("12 / 2 / 3"), 2.0);
+        assert_eq!(value("2 * 3 ^ 2"), 18.0);
+        assert_eq!(value("2 ^ 3 ^ 2"), 512.0);
+    }
+
+    #[test]
+    fn minus_binds_weaker_than_the_power()
+    {
+        assert_eq!(value("-2^2"), -4.0);
+        assert_eq!(value("(-2)^2"), 4.0);
+        assert_eq!(value("2^-1"), 0.5);
+        assert_eq!(value("--3"), 3.0);
+        assert_eq!(value("1 - -bounces"), 4.0);
+    }
+
+    #[test]
+    fn numbers_take_exponents()
+    {
+        assert_eq!(value("1.5e2"), 150.0);
+        assert_eq!(value("2E-1"), 0.2);
+        assert_eq!(value("1e+1"), 10.0);
+        assert_eq!(value(".5"), 0.5);
+        // Without digits after it, the e is the constant
+        assert_eq!(value("2*e"), 2.0 * std::f64::consts::E);
+    }
+
+    #[test]
+    fn names_are_variables_constants_and_functions()
+    {
+        assert_eq!(value("path_length * bounces"), 6.0);
+        assert_eq!(value("max(bounces, path_length) + min(1, 2)"), 4.0);
+        assert_eq!(value("pow(2, bounces)"), 8.0);
+        assert_eq!(value("sqrt(abs(-16))"), 4.0);
+        assert_eq!(value("end_wall + end_segment"), 3.0);
+        assert_eq!(value("trapped + self_hit + escaped"), 2.0);
+    }
+
+    #[test]
+    fn errors_tell_the_position()
+    {
+        assert_eq!(parse("1 +").unwrap_err(), "expected a number, a name or '(' at position 4 of the shader expression");
+        assert_eq!(parse("(1 + 2").unwrap_err(), "expected ')' at position 7 of the shader expression");
+        assert_eq!(parse("1 2").unwrap_err(), "expected an operator at position 3 of the shader expression");
+        assert_eq!(parse("1e").unwrap_err(), "expected an operator at position 2 of the shader expression");
+        assert_eq!(parse("max(1 2)").unwrap_err(), "expected ',' or ')' at position 7 of the shader expression");
+        assert_eq!(parse("min(1)").unwrap_err(), "min in the shader expression takes 2 arguments, got 1");
+        assert!(parse("speed").unwrap_err().starts_with("unknown variable 'speed'"));
+        assert!(parse("tan(1)").unwrap_err().starts_with("unknown function 'tan'"));
+    }
+}
@@ -0,0 +1,257 @@
+use std::f64::consts::PI;
+
+use geo::{Coord, coord, Vector2DOps};
+use serde::{Deserialize, Serialize};
+
+use crate::scene::{Boundary, rotate, Scene};
+
+
+// A wind that carries the ball along, on top of its own velocity. Its own velocity
+// is what reflects off the walls. Written in a config file as, for example,
+// wind = { vortex = { center = [0.5, 0.5], strength = 0.5, radius = 0.2 } }
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Wind {
+    Uniform([f64; 2]),
+    // Turns clockwise around the center, at `strength` at the radius. It speeds up
+    // linearly from the center to there, and slows down with the distance outside.
+    Vortex { center: [f64; 2], strength: f64, radius: f64 },
+    // Rows of wind vectors, from the top of the image to the bottom, interpolated
+    // between. The first and last rows and columns are on the edges of the image.
+    Grid(Vec<Vec<[f64; 2]>>),
+}
+
+
+impl Wind {
+    // The wind at a point, in image widths per unit of time
+    pub fn at(&self, p: Coord) -> Coord
+    {
+        match self {
+            Wind::Uniform([x, y]) => coord! {x: *x, y: *y},
+            Wind::Vortex { center, strength, radius } => {
+                let d = p - coord! {x: center[0], y: center[1]};
+                let r = d.magnitude();
+                let speed = if r < *radius { r / radius } else { radius / r };
+                // Turned a quarter clockwise, with y pointing down
+                match coord! {x: -d.y, y: d.x}.try_normalize() {
+                    Some(around) => around * (strength * speed),
+                    None => coord! {x: 0.0, y: 0.0},
+                }
+            }
+            Wind::Grid(rows) => {
+                let (columns, rows_count) = (rows[0].len(), rows.len());
+                let cell = |v: f64, n: usize| -> (usize, f64) {
+                    let f = (v.clamp(0.0, 1.0) * (n - 1) as f64).min((n - 1) as f64 - 1e-9);
+                    (f as usize, f.fract())
+                };
+                let (x, fx) = cell(p.x, columns);
+                let (y, fy) = cell(p.y, rows_count);
+                let get = |x: usize, y: usize| coord! {x: rows[y][x][0], y: rows[y][x][1]};
+                let top = get(x, y) * (1.0 - fx) + get(x + 1, y) * fx;
+                let bottom = get(x, y + 1) * (1.0 - fx) + get(x + 1, y + 1) * fx;
+                top * (1.0 - fy) + bottom * fy
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String
+    {
+        match self {
+            Wind::Uniform([x, y]) => format!("uniform {} x {}", x, y),
+            Wind::Vortex { center, strength, radius } => {
+                format!("vortex around ({}, {}), strength {}, radius {}", center[0], center[1], strength, radius)
+            }
+            Wind::Grid(rows) => format!("grid of {} x {}", rows[0].len(), rows.len()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String>
+    {
+        match self {
+            Wind::Uniform(w) => {
+                if !w.iter().all(|c| c.is_finite()) {
+                    return Err(format!("uniform wind must be finite, got {:?}", w));
+                }
+            }
+            Wind::Vortex { center, strength, radius } => {
+                if !(center.iter().all(|c| c.is_finite()) && strength.is_finite()) {
+                    return Err("vortex center and strength must be finite".to_string());
+                }
+                if !(*radius > 0.0 && radius.is_finite()) {
+                    return Err(format!("vortex radius must be larger than 0, got {}", radius));
+                }
+            }
+            Wind::Grid(rows) => {
+                if rows.len() < 2 || rows[0].len() < 2 {
+                    return Err("a wind grid needs at least 2 rows and 2 columns".to_string());
+                }
+                if rows.iter().any(|row| row.len() != rows[0].len()) {
+                    return Err("all rows of a wind grid must have the same length".to_string());
+                }
+                if !rows.iter().flatten().flatten().all(|c| c.is_finite()) {
+                    return Err("wind grid vectors must be finite".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// A force on the ball that depends on where it is, towards or away from a center.
+// Written in a config file as, for example,
+// potential = { harmonic = { center = [0.5, 0.5], stiffness = 4.0 } }
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Potential {
+    // A well that pulls the ball to the center with `stiffness` times its distance
+    Harmonic { center: [f64; 2], stiffness: f64 },
+    // Pulls the ball to the center with `strength` times its distance to the power
+    // `exponent`, pushes it away when the strength is negative. For -2 it is gravity
+    // of a mass at the center. The distance is softened, as sqrt(d^2 + softening^2),
+    // so the force stays finite there.
+    Central { center: [f64; 2], strength: f64, exponent: f64, #[serde(default)] softening: f64 },
+}
+
+
+impl Potential {
+    // The acceleration of the ball at a point
+    pub fn force(&self, p: Coord) -> Coord
+    {
+        match self {
+            Potential::Harmonic { center, stiffness } => (coord! {x: center[0], y: center[1]} - p) * *stiffness,
+            Potential::Central { center, strength, exponent, softening } => {
+                let d = coord! {x: center[0], y: center[1]} - p;
+                let r = (d.magnitude_squared() + softening * softening).sqrt();
+                if r == 0.0 {
+                    return coord! {x: 0.0, y: 0.0};
+                }
+                d * (strength * r.powf(exponent - 1.0))
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String
+    {
+        match self {
+            Potential::Harmonic { center, stiffness } => format!("harmonic well around ({}, {}), stiffness {}", center[0], center[1], stiffness),
+            Potential::Central { center, strength, exponent, softening } => {
+                format!("central force around ({}, {}), {} x distance^{}, softened by {}", center[0], center[1], strength, exponent, softening)
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String>
+    {
+        match self {
+            Potential::Harmonic { center, stiffness } => {
+                if !(center.iter().all(|c| c.is_finite()) && stiffness.is_finite()) {
+                    return Err("harmonic well center and stiffness must be finite".to_string());
+                }
+            }
+            Potential::Central { center, strength, exponent, softening } => {
+                if !(center.iter().all(|c| c.is_finite()) && strength.is_finite() && exponent.is_finite()) {
+                    return Err("central force center, strength and exponent must be finite".to_string());
+                }
+                if !(*softening >= 0.0 && softening.is_finite()) {
+                    return Err(format!("central force softening must be at least 0, got {}", softening));
+                }
+                // Otherwise the force grows without a limit at the center
+                if *exponent < 1.0 && *softening == 0.0 {
+                    return Err("a central force with an exponent below 1 needs a softening".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// How the ball flies between bounces when a force bends its path. The curve is
+// followed in short straight steps, that are tested against the walls and become
+// the trail, like the straight flights of a ball without forces.
+#[derive(Debug, Clone)]
+pub struct Flight {
+    pub gravity: Coord,                 // acceleration, in image widths per unit of time squared
+    pub turn_rate: f64,                 // of the velocity in a magnetic field, in radians per unit of time
+    pub wind: Option<Wind>,
+    pub potential: Option<Potential>,
+    pub frame: Option<(Coord, f64)>,    // center and rate, in radians per unit of time, of a rotating frame
+    pub step: f64,                      // length of the straight steps
+    bounds: Option<(Coord, Coord)>,     // box around the walls, a ball outside it has left the arena
+}
+
+
+impl Flight {
+    // The flight under the forces, or None without any, the ball flies straight then.
+    // A ball with speed 1 goes round a circle of the Larmor radius in the magnetic field.
+    // In a frame that turns with the arena, `frame_rotation` turns per unit of time, the
+    // Coriolis force turns the velocity the other way at twice the rate, like a magnetic
+    // field, and the centrifugal force pushes the ball away from the center.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(gravity: [f64; 2], larmor_radius: Option<f64>, wind: Option<Wind>, potential: Option<Potential>, frame_rotation: f64,
+               frame_center: [f64; 2], step: f64, scene: &Scene) -> Option<Flight>
+    {
+        if gravity == [0.0, 0.0] && larmor_radius.is_none() && wind.is_none() && potential.is_none() && frame_rotation == 0.0 {
+            return None;
+        }
+        let omega = 2.0 * PI * frame_rotation;
+
+        // Scenes with an edge end the flights that leave it themselves
+        let bounds = match scene.boundary {
+            Boundary::Walls => scene.walls.iter().map(|wall| wall.shape.bounding_box()).reduce(|(lo, hi), (l, h)| {
+                (coord! {x: lo.x.min(l.x), y: lo.y.min(l.y)}, coord! {x: hi.x.max(h.x), y: hi.y.max(h.y)})
+            }),
+            Boundary::Periodic | Boundary::Viewport(..) => None,
+        };
+        Some(Flight {
+            gravity: coord! {x: gravity[0], y: gravity[1]},
+            turn_rate: larmor_radius.map_or(0.0, |r| 1.0 / r) - 2.0 * omega,
+            wind,
+            potential,
+            frame: Some((coord! {x: frame_center[0], y: frame_center[1]}, omega)).filter(|_| omega != 0.0),
+            step,
+            bounds,
+        })
+    }
+
+    // The acceleration of the ball at a point, by gravity, the centrifugal force and the potential
+    fn acceleration(&self, p: Coord) -> Coord
+    {
+        let centrifugal = self.frame.map_or(coord! {x: 0.0, y: 0.0}, |(center, omega)| (p - center) * (omega * omega));
+        let potential = self.potential.as_ref().map_or(coord! {x: 0.0, y: 0.0}, |potential| potential.force(p));
+        self.gravity + centrifugal + potential
+    }
+
+    // Where the ball at `pos` with `velocity` is after one step, and its velocity
+    // there. The time step is chosen so the ball moves about `step`, also where it
+    // stands still at the top of its flight. The magnetic field turns the velocity
+    // along a circular arc, that is exact without other forces, those are added on
+    // top. They are integrated with velocity Verlet: the ball moves with the
+    // acceleration at the start of the step, and its velocity changes with the mean of
+    // the accelerations at both ends. That is symplectic, so the energy of a ball in a
+    // well does not drift over many steps. The wind carries the ball with the wind
+    // halfway along the step. Returns the time step too.
+    pub fn advance(&self, pos: Coord, velocity: Coord) -> (Coord, Coord, f64)
+    {
+        let wind_at = |p: Coord| self.wind.as_ref().map_or(coord! {x: 0.0, y: 0.0}, |wind| wind.at(p));
+        let acceleration = self.acceleration(pos);
+        let speed = velocity.magnitude() + wind_at(pos).magnitude();
+        let dt = self.step / (speed + (2.0 * self.step * acceleration.magnitude()).sqrt());
+        let turn = self.turn_rate * dt;
+
+        // The chord of the arc is along the velocity halfway, and shorter than the arc
+        let chord = if turn == 0.0 { 1.0 } else { (turn / 2.0).sin() / (turn / 2.0) };
+        let arc = rotate(velocity, turn / 2.0) * (dt * chord);
+        let flown = arc + acceleration * (0.5 * dt * dt);
+        let end = pos + flown + wind_at(pos + flown * 0.5) * dt;
+        (end, rotate(velocity, turn) + (acceleration + self.acceleration(end)) * (0.5 * dt), dt)
+    }
+
+    // Whether a ball at `pos` is outside the box around the walls. It can only get
+    // there through a wall it passed, it would fall forever otherwise.
+    pub fn is_lost(&self, pos: Coord) -> bool
+    {
+        self.bounds.is_some_and(|(lo, hi)| pos.x < lo.x || pos.x > hi.x || pos.y < lo.y || pos.y > hi.y)
+    }
+}
@@ -0,0 +1,196 @@
+use std::f64::consts::PI;
+
+use geo::{Coord, coord, Line, Vector2DOps};
+use rand::prelude::*;
+use simple_canvas::Canvas;
+
+use crate::config::{Dynamics, Material, SphereProjection};
+use crate::hyperbolic::Hyperbolic;
+use crate::scene::{Boundary, Scene, Shape, Surface, Wall};
+use crate::shaders::Shader;
+use crate::simulation::{bounce_path, CONTACT_DISTANCE, Crossings, deposit_weight, swept, trap, Termination, Trajectory, Trap, Weight};
+use crate::spherical::Spherical;
+
+const MAX_START_ATTEMPTS: usize = 1000;
+
+// Length of the line the ball flies along in a chart, it has to reach past the walls
+const FLIGHT_LENGTH: f64 = 1000.0;
+
+
+// A curved geometry that has a chart, a map of (part of) it, in which its geodesics
+// are straight lines. The ball and its trail are simulated in the chart, with the
+// walls of the plane, and the image shows the geometry in a projection of its own.
+pub trait Geometry {
+    // The point of the image in the chart, None outside the part it maps
+    fn to_chart(&self, p: Coord) -> Option<Coord>;
+    // The point of the chart in the image
+    fn to_image(&self, c: Coord) -> Coord;
+    // The length of the geodesic between two points of the chart
+    fn distance(&self, a: Coord, b: Coord) -> f64;
+    // The direction, in the chart, of a ball that starts at a point of the image, at
+    // `angle` in the geometry
+    fn start_direction(&self, p: Coord, angle: f64) -> Option<Coord>;
+    // Reflect the ball, that came from `ball`, off the geodesic with direction
+    // `tangent` at `intersection`, all in the chart
+    fn reflection(&self, ball: Coord, tangent: Coord, intersection: Coord) -> Option<Line>;
+}
+
+
+// The geometry of the dynamics, None for those in the plane, or in space
+pub fn geometry(dynamics: Dynamics, projection: SphereProjection) -> Option<Box<dyn Geometry>>
+{
+    match dynamics {
+        Dynamics::Inner | Dynamics::Outer | Dynamics::Solid => None,
+        Dynamics::Hyperbolic => Some(Box::new(Hyperbolic)),
+        Dynamics::Spherical => Some(Box::new(Spherical { projection })),
+    }
+}
+
+
+// The point that is `length` from `from` towards `to`, that is further
+fn along(geometry: &dyn Geometry, from: Coord, to: Coord, length: f64) -> Coord
+{
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..60 {
+        let mid = (lo + hi) / 2.0;
+        if geometry.distance(from, from + (to - from) * mid) < length { lo = mid } else { hi = mid }
+    }
+    from + (to - from) * lo
+}
+
+
+// The line the ball flies along from `intersection`, in direction `dir`, moved
+// forward a little bit, like in the plane, not to hit the wall it bounces off again
+pub fn flight(intersection: Coord, dir: Coord) -> Line
+{
+    Line::new(intersection + dir * CONTACT_DISTANCE, intersection + dir * FLIGHT_LENGTH)
+}
+
+
+// The scene with its walls in the chart. The walls of the arena are taken as
+// geodesics between their ends in the image, straight walls are all there are, see
+// validate_arena().
+pub fn chart_scene(scene: &Scene, geometry: &dyn Geometry) -> Scene
+{
+    let chart = |p: Coord| geometry.to_chart(p).unwrap_or(p);
+    let walls = |walls: &[Wall]| -> Vec<Wall> {
+        walls.iter()
+            .map(|wall| match wall.shape {
+                Shape::Segment(line) => Wall::new(Shape::Segment(Line::new(chart(line.start), chart(line.end))), wall.material),
+                _ => *wall,
+            })
+            .collect()
+    };
+    let mut charted = Scene::new(walls(&scene.walls[..scene.no_closed_walls]), walls(&scene.walls[scene.no_closed_walls..]), Vec::new());
+    charted.center = chart(scene.center);
+    charted
+}
+
+
+// Whether the arena can be a billiard of the geometry: enclosed by straight walls,
+// in the part of the image the chart maps
+pub fn validate_arena(scene: &Scene, geometry: &dyn Geometry, name: &str) -> Result<(), String>
+{
+    if scene.boundary != Boundary::Walls || scene.no_closed_walls == 0 {
+        return Err(format!("{} billiards need an arena enclosed by walls", name));
+    }
+    for wall in &scene.walls {
+        let Shape::Segment(line) = wall.shape else {
+            return Err(format!("{} billiards need an arena of straight walls, they become geodesics", name));
+        };
+        if geometry.to_chart(line.start).is_none() || geometry.to_chart(line.end).is_none() {
+            return Err(format!("the walls of a {} billiard have to be within the part of the image it can map", name));
+        }
+    }
+    Ok(())
+}
+
+
+// Simulate one ball of the billiard of a curved geometry, in the scene of
+// chart_scene(). It starts anywhere in the arena, seen in the image, and flies along
+// geodesics. Its path length is their length in the geometry, and the trapped ball is
+// deposited in the image. Returns how the ball ended, if it did in the arena.
+pub fn geodesic_simulation<T: Weight, R: Rng>(canvas: &mut Canvas<T>,
+                                              scene: &mut Scene,
+                                              geometry: &dyn Geometry,
+                                              rng: &mut R,
+                                              canvas_shader: &dyn Shader<T>,
+                                              resample_starts: bool) -> Option<Trajectory>
+{
+    let attempts = if resample_starts { MAX_START_ATTEMPTS } else { 1 };
+    let (start_pos, start) = (0..attempts)
+        .map(|_| coord! {x: rng.gen_range(0.0 .. 1.0), y: rng.gen_range(0.0 .. 1.0)})
+        .filter_map(|p| geometry.to_chart(p).map(|c| (p, c)))
+        .find(|(_, c)| scene.contains(*c))?;
+    let dir = geometry.start_direction(start_pos, rng.gen_range(0.0 .. PI * 2.0))?;
+
+    let mut ball = Line::new(start, start + dir * FLIGHT_LENGTH);
+    let mut path_length: f64 = 0.0;
+    let mut no_bounces: usize = 0;
+    let mut energy: f64 = 1.0;
+    let mut winding: f64 = 0.0;
+    let mut crossings = scene.count_crossings.then(Crossings::default);
+    let mut bounce_points = scene.record_bounces.then(|| vec![start_pos]);
+
+    let ending = loop {
+        // No walls at all, it most likely started outside
+        let Some(hit) = scene.test_ball(ball) else {
+            break None;
+        };
+
+        let length = geometry.distance(ball.start, hit.point);
+        if let Some(max) = scene.max_path_length.filter(|max| path_length + length > *max) {
+            let end = along(geometry, ball.start, hit.point, max - path_length);
+            path_length = max;
+            break Some((end, Termination::PathLimit, None));
+        }
+        path_length += length;
+
+        if hit.material == Material::Absorb {
+            no_bounces += 1;
+            break Some((hit.point, Termination::Absorbed, hit.contact()));
+        }
+        if let Some(trap) = trap(hit.distance, scene) {
+            break Some((hit.point, Termination::Trapped(trap), hit.contact()));
+        }
+        no_bounces += 1;
+        energy *= scene.restitution_off(matches!(hit.surface, Some(Surface::Trail(_))));
+        if energy < scene.min_energy {
+            break Some((hit.point, Termination::Stopped, hit.contact()));
+        }
+        if scene.max_bounces.is_some_and(|max| no_bounces >= max) {
+            break Some((hit.point, Termination::BounceLimit, hit.contact()));
+        }
+
+        if let Some(points) = bounce_points.as_mut() {
+            points.push(geometry.to_image(hit.point));
+        }
+        winding += swept(scene.center, ball.start, hit.point);
+        if let Some(crossings) = crossings.as_mut() {
+            crossings.fly(Line::new(ball.start, hit.point));
+        }
+        scene.trail.push(Line::new(ball.start, hit.point));
+        scene.trail.bounce();
+        match geometry.reflection(ball.start, hit.tangent, hit.point) {
+            Some(b) => ball = b,
+            None => break Some((hit.point, Termination::Trapped(Trap::Degenerate), hit.contact())),
+        }
+    };
+
+    // Leave the scene in state that we started with
+    scene.trail.clear();
+
+    let (end, termination, contact) = ending?;
+    if let Some(crossings) = crossings.as_mut() {
+        crossings.fly(Line::new(ball.start, end));
+    }
+    let end_pos = geometry.to_image(end);
+    let end_dir = (end_pos - geometry.to_image(ball.start)).try_normalize().unwrap_or(coord! {x: 0.0, y: 0.0});
+    let trajectory = Trajectory { start_pos, end_pos, end_dir, path_length, travel_time: path_length, no_bounces, energy, termination,
+                                  bounce_points: bounce_path(&bounce_points, end_pos),
+                                  winding: winding + swept(scene.center, ball.start, end),
+                                  crossings: crossings.map_or(0, |crossings| crossings.count),
+                                  displacement: geometry.distance(start, end), contact, exit: None };
+    deposit_weight(canvas, scene, &trajectory, end_pos, canvas_shader.shade(&trajectory));
+    Some(trajectory)
+}
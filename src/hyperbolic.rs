@@ -0,0 +1,80 @@
+use geo::{Coord, coord, Line, Vector2DOps};
+
+use crate::geodesic::{flight, Geometry};
+use crate::simulation::angle;
+
+// The disk fills the image, around its center
+const CENTER: Coord = Coord { x: 0.5, y: 0.5 };
+
+
+// Hyperbolic geometry. Its chart is the Beltrami-Klein model of the disk, where the
+// geodesics are straight lines. The image shows the Poincaré disk model, where
+// geodesics are arcs that meet the edge of the disk at right angles. Both are in
+// image coordinates, the unit disk scaled to fill the image.
+pub struct Hyperbolic;
+
+
+fn unit(p: Coord) -> Coord
+{
+    (p - CENTER) * 2.0
+}
+
+
+fn image(p: Coord) -> Coord
+{
+    CENTER + p * 0.5
+}
+
+
+// A point of the Poincaré disk in the Klein disk
+fn to_klein(p: Coord) -> Coord
+{
+    let u = unit(p);
+    image(u * (2.0 / (1.0 + u.magnitude_squared())))
+}
+
+
+impl Geometry for Hyperbolic {
+    fn to_chart(&self, p: Coord) -> Option<Coord>
+    {
+        (unit(p).magnitude_squared() < 1.0).then(|| to_klein(p))
+    }
+
+    fn to_image(&self, c: Coord) -> Coord
+    {
+        let k = unit(c);
+        image(k / (1.0 + (1.0 - k.magnitude_squared()).max(0.0).sqrt()))
+    }
+
+    fn distance(&self, a: Coord, b: Coord) -> f64
+    {
+        let (a, b) = (unit(a), unit(b));
+        let cosh = (1.0 - a.dot_product(b)) / ((1.0 - a.magnitude_squared()) * (1.0 - b.magnitude_squared())).sqrt();
+        cosh.max(1.0).acosh()
+    }
+
+    // The Poincaré disk keeps angles, a short step in the direction there shows it in the Klein disk
+    fn start_direction(&self, p: Coord, direction: f64) -> Option<Coord>
+    {
+        (to_klein(p + angle(direction) * 1e-6) - to_klein(p)).try_normalize()
+    }
+
+    // The hyperbolic reflection in a geodesic is the reflection of the hyperboloid
+    // model in the plane with normal `n` through its points, with respect to the
+    // Minkowski product. The point the ball came from is reflected, the ball goes on
+    // along the reflected line.
+    fn reflection(&self, ball: Coord, tangent: Coord, intersection: Coord) -> Option<Line>
+    {
+        let homogeneous = |p: Coord| { let u = unit(p); [1.0, u.x, u.y] };
+        let minkowski = |a: [f64; 3], b: [f64; 3]| -a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+        let (p, q) = (homogeneous(intersection), homogeneous(intersection + tangent));
+        let n = [-(p[1] * q[2] - p[2] * q[1]), p[2] * q[0] - p[0] * q[2], p[0] * q[1] - p[1] * q[0]];
+        let x = homogeneous(ball);
+        let s = 2.0 * minkowski(x, n) / minkowski(n, n);
+        let r = [x[0] - s * n[0], x[1] - s * n[1], x[2] - s * n[2]];
+        let mirrored = image(coord! {x: r[1] / r[0], y: r[2] / r[0]});
+
+        Some(flight(intersection, (intersection - mirrored).try_normalize()?))
+    }
+}
@@ -0,0 +1,133 @@
+use std::f64::consts::PI;
+
+use geo::{Coord, coord, Vector2DOps};
+
+use crate::arena;
+use crate::config::RunConfig;
+use crate::scene::{Boundary, Scene, Shape};
+
+// The area is sampled on a grid of this many points along each side of the bounding box
+const AREA_SAMPLES: usize = 256;
+
+
+// Print properties of the arena of a config, without simulating. Only the arena of
+// the arena seed is inspected, variations are made during the run.
+pub fn print_properties(config: &RunConfig)
+{
+    let scene = arena::initial_arena(&config.arena, config.arena.seed);
+    let closed_walls = &scene.walls[..scene.no_closed_walls];
+    // Loose walls face the arena on both sides
+    let perimeter = scene.walls.iter().enumerate()
+        .map(|(i, wall)| if i < scene.no_closed_walls { 1.0 } else { 2.0 } * wall.shape.length())
+        .fold(0.0, |sum, length| sum + length);
+
+    println!("arena:              {}", arena::describe(&config.arena));
+    println!("boundary:           {}", match scene.boundary {
+        Boundary::Walls => "walls".to_string(),
+        Boundary::Periodic => "periodic, the edges of the image".to_string(),
+        Boundary::Viewport(lo, hi) => format!("viewport ({}, {}) - ({}, {})", lo.x, lo.y, hi.x, hi.y),
+    });
+    println!("walls:              {} ({} closed, {} loose)", scene.walls.len(), closed_walls.len(), scene.walls.len() - closed_walls.len());
+    if !scene.openings.is_empty() {
+        println!("openings:           {}", scene.openings.len());
+    }
+    println!("perimeter:          {:.6}", perimeter);
+
+    let Some((lo, hi)) = scene.edge_box().or_else(|| bounding_box(closed_walls.iter().map(|wall| wall.shape))) else {
+        println!("area:               0, there are no closed walls");
+        return;
+    };
+    let area = sampled_area(&scene, lo, hi);
+    println!("area:               {:.6} (sampled)", area);
+    println!("convex:             {}", if is_convex(&scene) { "yes" } else { "no" });
+    if let Some((lo, hi)) = bounding_box(scene.walls.iter().map(|wall| wall.shape)) {
+        println!("bounding box:       ({:.4}, {:.4}) - ({:.4}, {:.4})", lo.x, lo.y, hi.x, hi.y);
+    }
+    // The mean free path of a billiard is π A / P, whatever its shape
+    if perimeter > 0.0 {
+        println!("mean free path:     {:.6} (estimate, pi * area / perimeter)", PI * area / perimeter);
+    } else {
+        println!("mean free path:     infinite, there are no walls");
+    }
+}
+
+
+fn bounding_box(shapes: impl Iterator<Item = Shape>) -> Option<(Coord, Coord)>
+{
+    shapes.flat_map(wall_points).map(|p| (p, p)).reduce(|(lo, hi), (p, _)| {
+        (coord! {x: lo.x.min(p.x), y: lo.y.min(p.y)}, coord! {x: hi.x.max(p.x), y: hi.y.max(p.y)})
+    })
+}
+
+
+// The area inside the arena, from the fraction of the points of a grid over the box
+// from `lo` to `hi` that is inside
+fn sampled_area(scene: &Scene, lo: Coord, hi: Coord) -> f64
+{
+    let size = hi - lo;
+    let mut inside = 0;
+    for y in 0..AREA_SAMPLES {
+        for x in 0..AREA_SAMPLES {
+            let point = lo + coord! {x: size.x * (x as f64 + 0.5) / AREA_SAMPLES as f64,
+                                     y: size.y * (y as f64 + 0.5) / AREA_SAMPLES as f64};
+            inside += scene.contains(point) as usize;
+        }
+    }
+    size.x * size.y * inside as f64 / (AREA_SAMPLES * AREA_SAMPLES) as f64
+}
+
+
+// A single circle or ellipse, or a single outline that turns the same way
+// everywhere. Obstacles inside the arena make it concave for the ball.
+fn is_convex(scene: &Scene) -> bool
+{
+    let closed_walls = &scene.walls[..scene.no_closed_walls];
+    if scene.boundary != Boundary::Walls || closed_walls.len() != scene.walls.len() {
+        return false;
+    }
+    if let [wall] = closed_walls {
+        return matches!(wall.shape, Shape::Circle { .. } | Shape::Ellipse { .. });
+    }
+
+    // Join the walls up to one outline, they can be in either direction
+    let mut pieces: Vec<Vec<Coord>> = closed_walls.iter().map(|wall| wall_points(wall.shape)).collect();
+    let joins = |a: Coord, b: Coord| (a - b).magnitude() < 1e-9;
+    let mut outline = pieces.swap_remove(0);
+    while !pieces.is_empty() {
+        let end = *outline.last().unwrap();
+        let Some(i) = pieces.iter().position(|p| joins(p[0], end) || joins(*p.last().unwrap(), end)) else {
+            return false;
+        };
+        let mut piece = pieces.swap_remove(i);
+        if !joins(piece[0], end) {
+            piece.reverse();
+        }
+        outline.extend(&piece[1..]);
+    }
+    if !joins(outline[0], *outline.last().unwrap()) {
+        return false;
+    }
+    outline.pop();
+
+    let n = outline.len();
+    let turns: Vec<f64> = (0..n)
+        .map(|i| (outline[(i + 1) % n] - outline[i]).wedge_product(outline[(i + 2) % n] - outline[(i + 1) % n]))
+        .collect();
+    turns.iter().all(|t| *t >= -1e-12) || turns.iter().all(|t| *t <= 1e-12)
+}
+
+
+// Points along the wall, from its start to its end. Closed curves only get their
+// bounding box corners, that is all they are used for.
+fn wall_points(shape: Shape) -> Vec<Coord>
+{
+    const ARC_POINTS: usize = 64;
+
+    match shape {
+        Shape::Circle { .. } | Shape::Ellipse { .. } => {
+            let (lo, hi) = shape.bounding_box();
+            vec![lo, hi]
+        }
+        _ => shape.points(ARC_POINTS),
+    }
+}
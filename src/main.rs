@@ -1,350 +1,1168 @@
-use std::f64::consts::PI;
-use std::fs::File;
-use std::iter::zip;
-use std::ops::{Add, AddAssign};
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc;
-use std::sync::mpsc::RecvTimeoutError;
-use std::thread;
-use std::time;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::Duration;
 
-use bresenham;
-use cgmath::num_traits::clamp;
-use chrono::prelude::*;
-use geo::{Coord, coord, EuclideanDistance, Line, Vector2DOps};
-use geo::line_intersection::{line_intersection, LineIntersection};
-use heapless;
-use indicatif::{ProgressBar, ProgressStyle};
-use rand::prelude::*;
+use cgmath::Vector3;
+use clap::{Args, Parser, Subcommand};
+
 use simple_canvas::Canvas;
-use tiff;
-use tiff::encoder::colortype;
 
-use crate::FromThreadMsg::REPORT;
-use crate::ToThreadMsg::{ACCUMULATE, STOP};
+use crate::animate::LastFrame;
+use crate::batch::load_batch;
+use crate::config::{ArenaKind, ArenaVariation, Arrival, CornerRule, Deposit, DepositAt, Dynamics, ImageFormat, Material, OnCycle, PhaseImage, Reduction, RunConfig, SphereProjection, ToneMap, TrailFull, TrailSides};
+use crate::dump::{Channels, DumpHeader, read_dump, write_dump, write_npy};
+use crate::presets::{preset_by_name, PRESETS};
+use crate::runner::{no_threads, RunResult, WorkerPool};
+use crate::shaders::{COLOR_SHADERS, SHADERS};
+use crate::simulation::{Tally, Tumble};
+use crate::solid::Polyhedron;
+use crate::sweep::SweepParam;
+use crate::template::output_stem;
+
+mod analyze;
+mod animate;
+mod arena;
+mod batch;
+mod config;
+mod dump;
+mod estimate;
+mod expression;
+mod flight;
+mod geodesic;
+mod hyperbolic;
+mod inspect;
+mod mask;
+mod moving;
+mod multi;
+mod outer;
+mod parametric;
+mod output;
+mod presets;
+mod runner;
+mod scene;
+mod scene_file;
+mod script;
+mod shaders;
+mod simulation;
+mod shape;
+mod solid;
+mod spherical;
+mod sweep;
+mod symmetry;
+mod template;
+mod vector;
+
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Generates images indicating where self avoiding billiards are likely to get trapped")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-type ShaderFunc<T> = fn(start_pos: Coord, path_length: f64, no_bounces: usize) -> T;
-type Obsctacles = heapless::Vec<Line, MAX_NO_OBSTACLES>;
 
-const MAX_NO_OBSTACLES: usize = 200;
-const ARENA_EDGES: usize = 5;
-const ARENA_SIZE: f64 = 0.98;                       // size of arena, as ratio of the whole image
-const IMAGE_SIZE: usize = 512;                      // width and height in pixels
-const MIN_NUM_OF_SIMULATIONS: usize = 10_000_000;   // Minimum number of simulations to do, should not be much more
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the simulations, writes a raw dump and an image of it
+    Simulate(SimulateArgs),
 
-const SHADER_FUNC: ShaderFunc<f64> = |_start_pos: Coord, path_length: f64, _no_bounces: usize| path_length;
+    /// Run a series of simulations, varying one parameter, writes a dump and image per value
+    Sweep(SweepArgs),
 
-fn initial_obstacles() -> Obsctacles
-{
-    let mut obstacles: Obsctacles = Obsctacles::new();
+    /// Run a series of simulations while the arena changes, writes a numbered image per frame
+    Animate(AnimateArgs),
 
-    for i in 0..ARENA_EDGES {
-        let angle0 = (i as f64)         * 2.0 * PI / (ARENA_EDGES as f64);
-        let angle1 = ((i as f64) + 1.0) * 2.0 * PI / (ARENA_EDGES as f64);
-        let center = coord! {x: 0.5, y:0.5};
+    /// Run all simulations listed in a batch file, reusing the threads between runs
+    Batch(BatchArgs),
 
-        obstacles.push(Line::new(center + angle(angle0) * ARENA_SIZE / 2.0,
-                                 center + angle(angle1) * ARENA_SIZE / 2.0)).unwrap();
-    }
+    /// Turn a raw dump into an image again, possibly with a different tone mapping
+    Postprocess(PostprocessArgs),
 
-    obstacles
+    /// Print summary statistics of a raw dump
+    Analyze(AnalyzeArgs),
+
+    /// Print properties of the arena of a run, like its area and perimeter, without simulating
+    Inspect(SimulateArgs),
+
+    /// List the built-in presets
+    Presets,
+
+    /// List the built-in shaders and color shaders
+    Shaders,
 }
 
 
-fn test_ball_with_obstacles(ball: Line, obstacles: &Obsctacles) -> Option<(Line, Coord, f64)>
-{
-    let mut result: Option<(Line, Coord, f64)> = None;
-
-    for line in obstacles {
-
-        match line_intersection(*line, ball) {
-            Some(LineIntersection::SinglePoint{intersection: pt, is_proper: _is_proper}) => {
-                let distance = pt.euclidean_distance(&ball.start);
-                let closest_distance_so_far = match result {
-                    Some((_, __, x)) => {x}
-                    None => {f64::INFINITY}
-                };
-                if distance < closest_distance_so_far {
-                    result = Some((*line, pt, distance));
-                }
-            }
-            _ => {}
-        }
-    }
+#[derive(Args, Debug)]
+struct SimulateArgs {
+    /// Built-in preset to start from, see the presets command
+    #[arg(long)]
+    preset: Option<String>,
+
+    /// TOML file with run parameters, overrides the preset. Other arguments override its values
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Width of the output image in pixels [default: 512]
+    #[arg(long)]
+    width: Option<usize>,
+
+    /// Height of the output image in pixels [default: 512]
+    #[arg(long)]
+    height: Option<usize>,
+
+    /// Number of simulations to do [default: 10000000]
+    #[arg(long)]
+    sims: Option<usize>,
+
+    /// Simulate for this long instead of a number of simulations, for example 2h or 1h30m
+    #[arg(long, value_parser = humantime::parse_duration, conflicts_with = "sims")]
+    duration: Option<Duration>,
+
+    /// Number of simulation threads [default: available parallelism]
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Shape of the arena [default: polygon]
+    #[arg(long, value_enum)]
+    arena: Option<ArenaKind>,
+
+    /// Number of edges of the regular polygon arena, of points of the star arena, or of the polygon fractal arenas start with [default: 5]
+    #[arg(long)]
+    edges: Option<usize>,
+
+    /// Radius of the arcs the corners of the polygon arena are rounded with, in image coordinates [default: 0]
+    #[arg(long)]
+    corner_radius: Option<f64>,
+
+    /// Every how many corners the points of the star arena are connected [default: 2]
+    #[arg(long)]
+    step: Option<usize>,
+
+    /// Recursion depth of the fractal arenas [default: 2]
+    #[arg(long)]
+    depth: Option<usize>,
+
+    /// How far the midpoints of the rough arena are displaced, as ratio of the edge length [default: 0.3]
+    #[arg(long)]
+    roughness: Option<f64>,
+
+    /// Seed for randomly generated arenas [default: 0]
+    #[arg(long)]
+    arena_seed: Option<u64>,
+
+    /// Whether random arenas differ between runs, or between the workers of a run [default: none]
+    #[arg(long, value_enum)]
+    arena_variation: Option<ArenaVariation>,
+
+    /// Number of random arenas laid over each other, each generated after the one before [default: 1]
+    #[arg(long)]
+    arena_composite: Option<usize>,
+
+    /// Number of scatterers in the random arena [default: 0]
+    #[arg(long)]
+    random_scatterers: Option<usize>,
+
+    /// Number of cells along each side of the maze arena [default: 8]
+    #[arg(long)]
+    maze_size: Option<usize>,
+
+    /// Number of random segments per image area, in the open arena [default: 100]
+    #[arg(long)]
+    segment_density: Option<f64>,
+
+    /// Length of the random segments of the open arena, as ratio of the image width [default: 0.05]
+    #[arg(long)]
+    segment_length: Option<f64>,
+
+    /// Eccentricity of the elliptical arena, the major axis is horizontal [default: 0.6]
+    #[arg(long)]
+    eccentricity: Option<f64>,
+
+    /// Width / height of the stadium arena [default: 2]
+    #[arg(long)]
+    aspect_ratio: Option<f64>,
+
+    /// Width of the stem of the mushroom arena, as ratio of the diameter of the cap [default: 0.5]
+    #[arg(long)]
+    stem_width: Option<f64>,
+
+    /// Height of the stem of the mushroom arena, as ratio of the diameter of the cap [default: 0.5]
+    #[arg(long)]
+    stem_height: Option<f64>,
+
+    /// Radii of the two discs of the lens and lune arenas, as r1,r2 [default: 1,1]
+    #[arg(long, value_parser = parse_point)]
+    disc_radii: Option<[f64; 2]>,
+
+    /// Distance between the centers of the discs of the lens and lune arenas [default: 1]
+    #[arg(long)]
+    disc_separation: Option<f64>,
+
+    /// WKT or GeoJSON file with the outline of the custom arena
+    #[arg(long)]
+    shape_file: Option<PathBuf>,
+
+    /// Number of segments the curve of the parametric arena is sampled into [default: 256]
+    #[arg(long)]
+    curve_resolution: Option<usize>,
+
+    /// Black and white image, the contours of its dark parts are the walls of the mask arena
+    #[arg(long)]
+    mask_file: Option<PathBuf>,
+
+    /// Use the light parts of the mask as arena
+    #[arg(long)]
+    mask_invert: bool,
+
+    /// How far, in pixels, the simplified contours of the mask may be off [default: 1]
+    #[arg(long)]
+    mask_tolerance: Option<f64>,
+
+    /// Scene file written by --save-scene, its walls are the arena
+    #[arg(long)]
+    load_scene: Option<PathBuf>,
+
+    /// Material of the walls of the arena: reflect, absorb, transmit:P to pass through with probability P, rough:R to blend in diffuse reflection by R, or sticky:D to slide D along the wall before detaching [default: reflect]
+    #[arg(long, value_parser = parse_material)]
+    wall_material: Option<Material>,
+
+    /// Size of the arena, as ratio of the whole image [default: 0.98]
+    #[arg(long)]
+    arena_size: Option<f64>,
+
+    /// Center of the arena in image coordinates, from 0 to 1 [default: 0.5,0.5]
+    #[arg(long, value_parser = parse_point, allow_hyphen_values = true)]
+    arena_center: Option<[f64; 2]>,
+
+    /// Rotation of the arena, in degrees clockwise [default: 0]
+    #[arg(long, allow_hyphen_values = true)]
+    arena_rotation: Option<f64>,
+
+    /// Offset of the arena from its center, in image coordinates [default: 0,0]
+    #[arg(long, value_parser = parse_point, allow_hyphen_values = true)]
+    arena_offset: Option<[f64; 2]>,
+
+    /// Scale of the arena, around its center [default: 1]
+    #[arg(long)]
+    arena_scale: Option<f64>,
+
+    /// Stretch of the arena along the x and y axis, before it is rotated [default: 1,1]
+    #[arg(long, value_parser = parse_point)]
+    arena_anisotropy: Option<[f64; 2]>,
+
+    /// Billiards inside the arena, outer billiards, around the convex hull of the arena, hyperbolic billiards in the Poincaré disk, spherical billiards, or solid billiards in a polyhedron [default: inner]
+    #[arg(long, value_enum)]
+    dynamics: Option<Dynamics>,
+
+    /// How the sphere of spherical billiards is shown in the image [default: orthographic]
+    #[arg(long, value_enum)]
+    sphere_projection: Option<SphereProjection>,
+
+    /// Of outer billiards, the table is the arena shrunk by this around its center, so the orbits around it stay in the image [default: 0.3]
+    #[arg(long)]
+    table_scale: Option<f64>,
+
+    /// The polyhedron of solid billiards [default: cube]
+    #[arg(long, value_enum)]
+    polyhedron: Option<Polyhedron>,
+
+    /// Angle the camera of solid billiards is turned by around the vertical axis, in degrees [default: 30]
+    #[arg(long, allow_hyphen_values = true)]
+    camera_yaw: Option<f64>,
+
+    /// Angle the camera of solid billiards is tilted by towards the viewer, in degrees [default: 20]
+    #[arg(long, allow_hyphen_values = true)]
+    camera_pitch: Option<f64>,
+
+    /// Distance of the camera of solid billiards from the center, for a perspective view [default: orthographic]
+    #[arg(long)]
+    camera_distance: Option<f64>,
+
+    /// Shader used to weigh each trapped or escaped ball, see the shaders command [default: path-length]
+    #[arg(long)]
+    shader: Option<String>,
+
+    /// Exponent of the path-power shader [default: 1]
+    #[arg(long, allow_hyphen_values = true)]
+    shader_exponent: Option<f64>,
+
+    /// Shader written as an expression, instead of a built-in shader, like "log(1 + path_length) * bounces". It can use path_length, travel_time, bounces, energy, displacement, start_x, start_y, end_x, end_y, the direction it flew in last as end_dx, end_dy and end_angle, how many turns it went around the center of the arena as winding, how often its path crossed itself as crossings, the index of the wall it ended against as end_wall and the number of the trail segment as end_segment (-1 when it did not), how the ball ended as trapped, escaped, absorbed, stopped, bounce_limit, path_limit, returned or periodic, how a trapped ball got trapped as self_hit, trail_full or degenerate, pi and e, + - * / ^, and log, log10, exp, sqrt, abs, floor, ceil, sin, cos, tanh, min, max and pow
+    #[arg(long)]
+    shader_expr: Option<String>,
+
+    /// Shader script file in Rhai, instead of a built-in shader or an expression. It gets the variables of --shader-expr as constants, and how the ball ended as termination, like "self-hit" or "escaped", the value it ends in is the weight of the ball. It is read again for every run
+    #[arg(long)]
+    shader_script: Option<PathBuf>,
+
+    /// Built-in shader that weighs the same balls in a canvas of its own, with its name after the file name. Up to 3, repeat the option for more
+    #[arg(long)]
+    extra_shader: Vec<String>,
+
+    /// Where the weight of a ball goes: the pixel where it ended, every pixel along its trail, or the pixels of its bounces [default: end]
+    #[arg(long, value_enum)]
+    deposit: Option<Deposit>,
+
+    /// Of end deposits, whether the weight goes where the ball ended, where it started, to see where the trapped balls come from, or both [default: termination]
+    #[arg(long, value_enum)]
+    deposit_at: Option<DepositAt>,
+
+    /// Exponent of the bounce index the weight at every bounce is multiplied with, for bounce deposits, 1 to weigh the later bounces more [default: 0]
+    #[arg(long, allow_hyphen_values = true)]
+    bounce_exponent: Option<f64>,
+
+    /// How the weights that go in the same pixel are combined: summed, the smallest or largest of them, or their mean. Only of runs that are not in color, phase, direction or band runs, without extra shaders or a vector field, the others are refused [default: sum]
+    #[arg(long, value_enum)]
+    reduction: Option<Reduction>,
+
+    /// Color shader, that tints the weight of every ball for a color image, see the shaders command [default: grayscale]
+    #[arg(long)]
+    color: Option<String>,
+
+    /// Make it a phase run: every ball adds the weight of the shader as a complex number, turned by this times its path length, in radians, so balls that end in the same pixel interfere [default: no phase]
+    #[arg(long, allow_hyphen_values = true)]
+    wavenumber: Option<f64>,
+
+    /// What the image of a phase run shows of the complex sum in every pixel [default: magnitude]
+    #[arg(long, value_enum)]
+    phase_image: Option<PhaseImage>,
+
+    /// Make it a direction run: the weight of every ball goes in one of this many bins, by the direction it flew in last, and the image colors every pixel by the directions of its balls, 2 to 8 [default: no bins]
+    #[arg(long)]
+    direction_bins: Option<usize>,
+
+    /// Make it a band run: the weight of every ball goes in the band of its bounce count, every band holding the balls with at most its number of bounces, and one more for all others, with 5,20,100 the bands 0-5, 6-20, 21-100 and above. The image colors every pixel by the bands of its balls [default: no bands]
+    #[arg(long, value_delimiter = ',')]
+    bounce_bands: Option<Vec<usize>>,
+
+    /// The hue of every bin of a direction or band run, in turns, like 0.0,0.33,0.67 [default: spread evenly]
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    bin_hues: Option<Vec<f64>>,
+
+    /// Also keep, in every pixel, the smallest of this of the balls that ended in it, in a dump and image of their own [default: no first arrivals]
+    #[arg(long, value_enum)]
+    first_arrival: Option<Arrival>,
+
+    /// Directory to write the dump and image in [default: .]
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// File name template, without extension. Placeholders: {date}, {arena}, {edges}, {size}, {sims}, {duration}, {shader}, {seed}, {width}, {height} [default: raw-{date}]
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Seed for the random number generators [default: a random one]
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Pick a new start when it falls outside the arena, or inside an obstacle, instead of skipping it. Solid billiards always do
+    #[arg(long)]
+    resample_starts: bool,
+
+    /// The ball is trapped when its trail has this many segments [default: what the walls leave of 200, but at least 100]
+    #[arg(long)]
+    trail_length: Option<usize>,
+
+    /// Forget trail segments this many bounces after they were laid, they still count for the trail length [default: never]
+    #[arg(long)]
+    trail_memory: Option<usize>,
+
+    /// Balls flying in the arena at the same time, avoiding the trails of the others as well as their own [default: 1]
+    #[arg(long)]
+    balls: Option<usize>,
+
+    /// Chance that a bounce splits the ball in two, one reflected and one passing straight through [default: 0]
+    #[arg(long)]
+    branching: Option<f64>,
+
+    /// Most balls a simulation can split off by branching [default: 32]
+    #[arg(long)]
+    max_branches: Option<usize>,
+
+    /// What happens when the trail has reached its length: the ball is trapped, the oldest segment is forgotten, or the trail grows [default: trap]
+    #[arg(long, value_enum)]
+    trail_full: Option<TrailFull>,
+
+    /// The ball stops after this many bounces [default: no limit]
+    #[arg(long)]
+    max_bounces: Option<usize>,
+
+    /// The ball stops when it flew this far, in image widths [default: no limit]
+    #[arg(long)]
+    max_path_length: Option<f64>,
+
+    /// The ball stops when it comes back within this distance of its start, after at least one bounce [default: never]
+    #[arg(long)]
+    return_radius: Option<f64>,
+
+    /// Only this many of the most recent trail segments block the ball [default: all]
+    #[arg(long)]
+    trail_window: Option<usize>,
+
+    /// What the trail does to the ball: reflect, absorb, transmit:P to pass through with probability P, rough:R or sticky:D [default: reflect]
+    #[arg(long, value_parser = parse_material)]
+    trail_material: Option<Material>,
+
+    /// From which sides the trail blocks the ball, seen in the direction it was laid in, it passes through from the other [default: both]
+    #[arg(long, value_enum)]
+    trail_sides: Option<TrailSides>,
+
+    /// How the ball reflects where it hits a corner between two straight walls: off the nearest wall, reverse to go back the way it came, or bisector to reflect off the wall that cuts the corner off [default: nearest]
+    #[arg(long, value_enum)]
+    corner_rule: Option<CornerRule>,
+
+    /// The ball hits a corner when it gets this close to it [default: 0.000001]
+    #[arg(long)]
+    corner_tolerance: Option<f64>,
+
+    /// How far from where it bounced the ball starts again. The wall it bounced off and the trail segment it just laid are left out of its next flight, so 0 works, and no trail segment close to a wall is passed [default: 0.0001]
+    #[arg(long)]
+    reflection_offset: Option<f64>,
+
+    /// Test for hits on thin straight walls and trail segments with exact orientation predicates, a ball that grazes one, or flies along it, hits it too. Slower
+    #[arg(long)]
+    robust_predicates: bool,
+
+    /// Look for balls caught in periodic orbits, that leave a bounce point again in the same direction: stop to end them there, or perturb:DEG to turn them by a random angle with this standard deviation
+    #[arg(long, value_parser = parse_on_cycle)]
+    on_cycle: Option<OnCycle>,
+
+    /// Bounces at points and in directions this close are the same [default: 0.000001]
+    #[arg(long)]
+    cycle_tolerance: Option<f64>,
+
+    /// Number of recent bounces a bounce is compared with [default: 64]
+    #[arg(long)]
+    cycle_window: Option<usize>,
+
+    /// Lay out copies of the arena in a grid, as columns x rows, every copy gets its own simulations [default: 1x1]
+    #[arg(long, value_parser = parse_tiles)]
+    tiles: Option<[usize; 2]>,
+
+    /// Fold where balls end into one wedge of the symmetry of the arena, every pixel gets the simulations of all its copies. The images are unfolded again
+    #[arg(long)]
+    fold_symmetry: bool,
+
+    /// Turn the ball by a random angle at every reflection, normally distributed with this standard deviation in degrees [default: 0]
+    #[arg(long)]
+    reflection_jitter: Option<f64>,
+
+    /// The ball tumbles into a random direction after free paths of "exponential:mean" or "power-law:min,alpha", still avoiding its trail
+    #[arg(long, value_parser = parse_tumble)]
+    tumble: Option<Tumble>,
+
+    /// Acceleration of the ball as x,y, it flies in parabolas. The ball starts with speed 1, y points down [default: 0,0]
+    #[arg(long, value_parser = parse_point, allow_hyphen_values = true)]
+    gravity: Option<[f64; 2]>,
+
+    /// Radius of the circles a magnetic field bends the ball around at speed 1, clockwise, or counterclockwise when negative
+    #[arg(long, allow_hyphen_values = true)]
+    larmor_radius: Option<f64>,
+
+    /// The ball flies in a frame that turns clockwise with the arena at this many turns per unit of time, counterclockwise when negative [default: 0]
+    #[arg(long, allow_hyphen_values = true)]
+    frame_rotation: Option<f64>,
+
+    /// Center the frame turns around, as x,y [default: 0.5,0.5]
+    #[arg(long, value_parser = parse_point, allow_hyphen_values = true)]
+    frame_center: Option<[f64; 2]>,
+
+    /// The energy of the ball, that starts at 1, is multiplied with this at every bounce [default: 1]
+    #[arg(long)]
+    restitution: Option<f64>,
+
+    /// The energy of the ball is multiplied with this instead at bounces off its trail [default: the restitution]
+    #[arg(long)]
+    trail_restitution: Option<f64>,
+
+    /// The ball stops where its energy gets below this [default: 0]
+    #[arg(long)]
+    min_energy: Option<f64>,
+
+    /// The ball starts with speed 1 and slows down at this rate along its path, it stops where its speed gets to 0 [default: 0]
+    #[arg(long)]
+    deceleration: Option<f64>,
+
+    /// The speed of the ball is multiplied with this at every bounce [default: 1]
+    #[arg(long)]
+    bounce_speedup: Option<f64>,
+
+    /// Radius of the ball, it hits walls and its trail when its center gets this close [default: 0]
+    #[arg(long)]
+    ball_radius: Option<f64>,
+
+    /// Width of the trail, the ball hits it when its center gets within half of it, plus its radius [default: 0]
+    #[arg(long)]
+    trail_width: Option<f64>,
+
+    /// Length of the straight steps curved flights are followed in, every step is a segment of the trail [default: 0.005]
+    #[arg(long)]
+    flight_step: Option<f64>,
+
+    /// Tone mapping of the image [default: log]
+    #[arg(long, value_enum)]
+    tone_map: Option<ToneMap>,
+
+    /// File format of the images, 32 bit tiffs, or 8 or 16 bit pngs for viewers that do not open those [default: tiff]
+    #[arg(long, value_enum)]
+    image_format: Option<ImageFormat>,
+
+    /// Also write the values of every dump as a NumPy .npy array, the raw canvas without normalizing, the channels in the last axis
+    #[arg(long)]
+    npy: bool,
+
+    /// Also write a dump and image of the points where balls escaped through openings
+    #[arg(long)]
+    escape_map: bool,
+
+    /// Also write a dump and image of how many balls ended in every pixel, for postprocess --divide-by
+    #[arg(long)]
+    hit_counts: bool,
+
+    /// Also write the sum of the directions the balls ended in, in every pixel, as a float tiff of an x and y in every pixel, and with --npy as an (h, w, 2) .npy array
+    #[arg(long)]
+    vector_field: bool,
+
+    /// Also write a dump and image of where balls escaped across the openings, along x, and the sine of the angle they left in, along y
+    #[arg(long)]
+    exit_map: bool,
 
-    result
+    /// Write the walls of the arena, as generated for the first worker, to a scene file. Only for the simulate command
+    #[arg(long)]
+    save_scene: Option<PathBuf>,
+
+    /// Only run a short calibration, and print estimates of the runtime, memory use and file sizes
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Number of simulations of the calibration of a dry run
+    #[arg(long, default_value_t = 1_000_000)]
+    calibration_sims: usize,
 }
 
 
-fn reflection(ball: Coord, line: Line, intersection: Coord) -> Option<Line>
-{
-    let centered_line_endpoint = line.start - intersection;
-    let centered_ball = ball - intersection;
+#[derive(Args, Debug)]
+struct SweepArgs {
+    #[command(flatten)]
+    run: SimulateArgs,
+
+    /// Parameter to vary
+    #[arg(long, value_enum)]
+    param: SweepParam,
 
-    let x =  centered_line_endpoint.try_normalize()? * (-centered_ball.dot_product(centered_line_endpoint.try_normalize()?));
-    let reflected_dir = (x * 2.0 + centered_ball).try_normalize()?;
+    /// First value of the parameter
+    #[arg(long, allow_negative_numbers = true)]
+    from: f64,
 
-    // Move ball forward a little bit to prevent immediate collision with itself
-    // or the line it just bounced of from
-    //                            VVVVVVVVVVVVVVVVVVVVVV
-    Some(Line::new(intersection + reflected_dir * 0.0001 , intersection + reflected_dir * 10.0))
+    /// Last value of the parameter
+    #[arg(long, allow_negative_numbers = true)]
+    to: f64,
+
+    /// Number of values, from and to included [default: every integer in between, for integer parameters]
+    #[arg(long)]
+    steps: Option<usize>,
 }
 
 
-enum SimStepOutcome {
-    Trapped(Coord),
-    Bounced,
-    Escaped         // probably started outside already
+#[derive(Args, Debug)]
+struct AnimateArgs {
+    #[command(flatten)]
+    run: SimulateArgs,
+
+    /// Number of frames, the first one has the arena of the run config, the last one the values below
+    #[arg(long)]
+    frames: usize,
+
+    /// Edges of the polygon arena in the last frame, frames in between morph between polygons
+    #[arg(long)]
+    to_edges: Option<usize>,
+
+    /// Arena size in the last frame
+    #[arg(long)]
+    to_size: Option<f64>,
+
+    /// Arena rotation in the last frame, in degrees clockwise
+    #[arg(long, allow_hyphen_values = true)]
+    to_rotation: Option<f64>,
 }
 
 
-fn single_simulation<T: AddAssign>(canvas: &mut Canvas<T>,
-                                   obstacles: &mut Obsctacles,
-                                   rng: &mut ThreadRng,
-                                   canvas_shader: ShaderFunc<T>)
-{
-    let clean_scene_size = obstacles.len();
-
-    let start_pos = coord! {x: rng.gen_range(0.0 .. 1.0),
-                            y: rng.gen_range(0.0 .. 1.0)};
-    let rand_dir =  angle(rng.gen_range(0.0 .. PI*2.0)) * 10.0;
-
-    let mut ball = Line::new(start_pos, start_pos + rand_dir);
-    let mut path_length: f64 = 0.0;
-    let mut no_bounces: usize = 0;
-
-    loop {
-        let step_outcome = match test_ball_with_obstacles(ball, &obstacles) {
-
-            Some((line, col_point, distance)) => {
-                path_length += distance;
-                no_bounces += 1;
-
-                if distance < 0.0001 || obstacles.is_full() {
-                    SimStepOutcome::Trapped(col_point) // trapped
-                } else {
-                    obstacles.push(Line::new(ball.start, col_point)).unwrap();
-
-                    match reflection(ball.start, line, col_point) {
-                        Some(b) => {
-                            ball = b;
-                            SimStepOutcome::Bounced // continue bouncing
-                        }
-
-                        // reflection calculation failed
-                        None => {
-                            SimStepOutcome::Trapped(col_point) // trapped
-                        }
-                    }
-                }
-            }
+#[derive(Args, Debug)]
+struct BatchArgs {
+    /// TOML file with a [[run]] table per run
+    batch: PathBuf,
 
-            // no collision, it must have escaped, (or more likely, it started outside)
-            None => {
-                SimStepOutcome::Escaped
-            }
-        };
+    /// Number of simulation threads, shared by all runs [default: from the batch file, or available parallelism]
+    #[arg(long)]
+    threads: Option<usize>,
 
-        match step_outcome {
-            SimStepOutcome::Trapped(pt) => {
-                let x = clamp(f64::round(pt.x * canvas.width as f64) as usize, 0, canvas.width - 1);
-                let y = clamp(f64::round(pt.y * canvas.height as f64) as usize, 0, canvas.height - 1);
+    /// Number of runs simulated at the same time [default: from the batch file, or 1]
+    #[arg(long)]
+    parallel: Option<usize>,
 
-                canvas.data[x + canvas.width * y] += canvas_shader(start_pos, path_length, no_bounces);
-                break;
-            }
-            SimStepOutcome::Bounced => {
-                // keep looping
-            }
-            SimStepOutcome::Escaped => {
-                break;
-            }
+    /// Directory to write the dumps and images in [default: from the run configs]
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+}
+
+
+#[derive(Args, Debug)]
+struct PostprocessArgs {
+    /// Raw dump written by the simulate command
+    dump: PathBuf,
+
+    /// Tone mapping of the image [default: the one the dump was simulated with]
+    #[arg(long, value_enum)]
+    tone_map: Option<ToneMap>,
+
+    /// What the image of a phase run shows of the complex sum in every pixel [default: the one the dump was simulated with]
+    #[arg(long, value_enum)]
+    phase_image: Option<PhaseImage>,
+
+    /// The hue of every bin of a direction or band run, in turns [default: the ones the dump was simulated with]
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    bin_hues: Option<Vec<f64>>,
+
+    /// Hit counts of the same run, written with --hit-counts, to divide every pixel by, for the mean weight of the balls that ended in it instead of their sum
+    #[arg(long)]
+    divide_by: Option<PathBuf>,
+
+    /// File format of the image [default: the one the dump was simulated with]
+    #[arg(long, value_enum)]
+    image_format: Option<ImageFormat>,
+
+    /// Also write the values of the dump as a NumPy .npy array next to it, without normalizing them
+    #[arg(long)]
+    npy: bool,
+
+    /// Image to write [default: the dump path, with the extension of the image format]
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+
+#[derive(Args, Debug)]
+struct AnalyzeArgs {
+    /// Raw dump written by the simulate command
+    dump: PathBuf,
+}
+
+
+impl SimulateArgs {
+    fn run_config(&self) -> Result<RunConfig, String>
+    {
+        let mut config = match &self.preset {
+            Some(name) => preset_by_name(name)?.config(),
+            None => RunConfig::default()
+        };
+        if let Some(path) = &self.config {
+            config = RunConfig::load(path, &config)?;
         }
 
+        if let Some(width) = self.width { config.width = width; }
+        if let Some(height) = self.height { config.height = height; }
+        if let Some(sims) = self.sims {
+            config.sims = sims;
+            config.duration = None;
+        }
+        if let Some(duration) = self.duration { config.duration = Some(duration); }
+        if let Some(threads) = self.threads { config.threads = Some(threads); }
+        if let Some(kind) = self.arena { config.arena.kind = kind; }
+        if let Some(edges) = self.edges { config.arena.edges = edges; }
+        if let Some(radius) = self.corner_radius { config.arena.corner_radius = radius; }
+        if let Some(step) = self.step { config.arena.step = step; }
+        if let Some(depth) = self.depth { config.arena.depth = depth; }
+        if let Some(roughness) = self.roughness { config.arena.roughness = roughness; }
+        if let Some(seed) = self.arena_seed { config.arena.seed = seed; }
+        if let Some(variation) = self.arena_variation { config.arena.variation = variation; }
+        if let Some(count) = self.arena_composite { config.arena.composite = count; }
+        if let Some(count) = self.random_scatterers { config.arena.random_scatterers = count; }
+        if let Some(maze_size) = self.maze_size { config.arena.maze_size = maze_size; }
+        if let Some(density) = self.segment_density { config.arena.segment_density = density; }
+        if let Some(length) = self.segment_length { config.arena.segment_length = length; }
+        if let Some(eccentricity) = self.eccentricity { config.arena.eccentricity = eccentricity; }
+        if let Some(aspect_ratio) = self.aspect_ratio { config.arena.aspect_ratio = aspect_ratio; }
+        if let Some(stem_width) = self.stem_width { config.arena.stem_width = stem_width; }
+        if let Some(stem_height) = self.stem_height { config.arena.stem_height = stem_height; }
+        if let Some(radii) = self.disc_radii { config.arena.disc_radii = radii; }
+        if let Some(separation) = self.disc_separation { config.arena.disc_separation = separation; }
+        if let Some(shape_file) = &self.shape_file { config.arena.shape_file = Some(shape_file.clone()); }
+        if let Some(resolution) = self.curve_resolution { config.arena.curve_resolution = resolution; }
+        if let Some(mask_file) = &self.mask_file { config.arena.mask_file = Some(mask_file.clone()); }
+        if self.mask_invert { config.arena.mask_invert = true; }
+        if let Some(tolerance) = self.mask_tolerance { config.arena.mask_tolerance = tolerance; }
+        if let Some(scene_file) = &self.load_scene {
+            config.arena.kind = ArenaKind::Scene;
+            config.arena.scene_file = Some(scene_file.clone());
+        }
+        if let Some(material) = self.wall_material { config.arena.material = material; }
+        if let Some(size) = self.arena_size { config.arena.size = size; }
+        if let Some(center) = self.arena_center { config.arena.center = center; }
+        if let Some(rotation) = self.arena_rotation { config.arena.transform.rotation = rotation; }
+        if let Some(offset) = self.arena_offset { config.arena.transform.offset = offset; }
+        if let Some(scale) = self.arena_scale { config.arena.transform.scale = scale; }
+        if let Some(anisotropy) = self.arena_anisotropy { config.arena.transform.anisotropy = anisotropy; }
+        if let Some(dynamics) = self.dynamics { config.dynamics = dynamics; }
+        if let Some(projection) = self.sphere_projection { config.sphere_projection = projection; }
+        if let Some(scale) = self.table_scale { config.table_scale = scale; }
+        if let Some(polyhedron) = self.polyhedron { config.solid.polyhedron = polyhedron; }
+        if let Some(yaw) = self.camera_yaw { config.solid.camera.yaw = yaw; }
+        if let Some(pitch) = self.camera_pitch { config.solid.camera.pitch = pitch; }
+        if let Some(distance) = self.camera_distance { config.solid.camera.distance = Some(distance); }
+        if let Some(shader) = &self.shader { config.shader = shader.clone(); }
+        if let Some(exponent) = self.shader_exponent { config.shader_exponent = exponent; }
+        if let Some(expr) = &self.shader_expr { config.shader_expr = Some(expr.clone()); }
+        if let Some(path) = &self.shader_script { config.shader_script = Some(path.clone()); }
+        if let Some(color) = &self.color { config.color = Some(color.clone()); }
+        if let Some(wavenumber) = self.wavenumber { config.wavenumber = Some(wavenumber); }
+        if let Some(image) = self.phase_image { config.output.phase_image = image; }
+        if let Some(bins) = self.direction_bins { config.direction_bins = Some(bins); }
+        if let Some(bands) = &self.bounce_bands { config.bounce_bands = bands.clone(); }
+        if let Some(hues) = &self.bin_hues { config.output.bin_hues = hues.clone(); }
+        if let Some(arrival) = self.first_arrival { config.first_arrival = Some(arrival); }
+        if !self.extra_shader.is_empty() { config.extra_shaders = self.extra_shader.clone(); }
+        if let Some(deposit) = self.deposit { config.deposit = deposit; }
+        if let Some(deposit_at) = self.deposit_at { config.deposit_at = deposit_at; }
+        if let Some(exponent) = self.bounce_exponent { config.bounce_exponent = exponent; }
+        if let Some(reduction) = self.reduction { config.reduction = reduction; }
+        if let Some(dir) = &self.output_dir { config.output.directory = dir.clone(); }
+        if let Some(name) = &self.name { config.output.name = name.clone(); }
+        if let Some(seed) = self.seed { config.seed = Some(seed); }
+        if self.resample_starts { config.resample_starts = true; }
+        if let Some(trail_length) = self.trail_length { config.trail_length = Some(trail_length); }
+        if let Some(balls) = self.balls { config.balls = balls; }
+        if let Some(branching) = self.branching { config.branching = branching; }
+        if let Some(branches) = self.max_branches { config.max_branches = branches; }
+        if let Some(full) = self.trail_full { config.trail_full = full; }
+        if let Some(bounces) = self.max_bounces { config.max_bounces = Some(bounces); }
+        if let Some(length) = self.max_path_length { config.max_path_length = Some(length); }
+        if let Some(radius) = self.return_radius { config.return_radius = Some(radius); }
+        if let Some(memory) = self.trail_memory { config.trail_memory = Some(memory); }
+        if let Some(window) = self.trail_window { config.trail_window = Some(window); }
+        if let Some(material) = self.trail_material { config.trail_material = material; }
+        if let Some(sides) = self.trail_sides { config.trail_sides = sides; }
+        if let Some(rule) = self.corner_rule { config.corner_rule = rule; }
+        if let Some(tolerance) = self.corner_tolerance { config.corner_tolerance = tolerance; }
+        if let Some(offset) = self.reflection_offset { config.reflection_offset = offset; }
+        if self.robust_predicates { config.robust_predicates = true; }
+        if let Some(on_cycle) = self.on_cycle { config.on_cycle = Some(on_cycle); }
+        if let Some(tolerance) = self.cycle_tolerance { config.cycle_tolerance = tolerance; }
+        if let Some(window) = self.cycle_window { config.cycle_window = window; }
+        if let Some(tiles) = self.tiles { config.tiles = tiles; }
+        if self.fold_symmetry { config.fold_symmetry = true; }
+        if let Some(jitter) = self.reflection_jitter { config.reflection_jitter = jitter; }
+        if let Some(tumble) = &self.tumble { config.tumble = Some(tumble.clone()); }
+        if let Some(gravity) = self.gravity { config.gravity = gravity; }
+        if let Some(radius) = self.larmor_radius { config.larmor_radius = Some(radius); }
+        if let Some(rotation) = self.frame_rotation { config.frame_rotation = rotation; }
+        if let Some(center) = self.frame_center { config.frame_center = center; }
+        if let Some(restitution) = self.restitution { config.restitution = restitution; }
+        if let Some(restitution) = self.trail_restitution { config.trail_restitution = Some(restitution); }
+        if let Some(energy) = self.min_energy { config.min_energy = energy; }
+        if let Some(deceleration) = self.deceleration { config.deceleration = deceleration; }
+        if let Some(speedup) = self.bounce_speedup { config.bounce_speedup = speedup; }
+        if let Some(radius) = self.ball_radius { config.ball_radius = radius; }
+        if let Some(width) = self.trail_width { config.trail_width = width; }
+        if let Some(step) = self.flight_step { config.flight_step = step; }
+        if let Some(tone_map) = self.tone_map { config.output.tone_map = tone_map; }
+        if let Some(image_format) = self.image_format { config.output.image_format = image_format; }
+        if self.npy { config.output.npy = true; }
+        if self.escape_map { config.output.escape_map = true; }
+        if self.hit_counts { config.output.hit_counts = true; }
+        if self.vector_field { config.output.vector_field = true; }
+        if self.exit_map { config.output.exit_map = true; }
+
+        config.load_files()?;
+        config.validate()?;
+        Ok(config)
     }
+}
 
-    // Leave the scene in state that we started with
-    obstacles.truncate(clean_scene_size);
+
+// Write the dump and image of a finished run, and of its escape map. Those of its extra
+// shaders get the name of the shader after the stem, and their dumps the config of a
+// run with only that shader.
+fn write_results(config: &RunConfig, stem: &str, result: &RunResult) -> Result<(), String>
+{
+    // Like the points of outer billiards around a table the size of the image
+    if result.tally.balls() > 0 && result.tally.escaped == result.tally.balls() {
+        eprintln!("warning: every ball escaped, the image of {} is empty", stem);
+    }
+    let channels = Channels::of(config, result.bins.as_ref(), result.phases.as_ref(), result.colors.as_ref());
+    write_canvas(config, stem, &result.canvas, channels, result.colors.as_ref(), result.simulations, &result.tally)?;
+    if let Some(escapes) = &result.escapes {
+        write_canvas(config, &format!("{}-escapes", stem), escapes, Channels::Gray, None, result.simulations, &result.tally)?;
+    }
+    if let Some(arrivals) = &result.arrivals {
+        write_canvas(config, &format!("{}-arrival", stem), arrivals, Channels::Gray, None, result.simulations, &result.tally)?;
+    }
+    if let Some(hits) = &result.hits {
+        write_canvas(config, &format!("{}-hits", stem), hits, Channels::Gray, None, result.simulations, &result.tally)?;
+    }
+    if let Some(exits) = &result.exits {
+        write_canvas(config, &format!("{}-exits", stem), exits, Channels::Gray, None, result.simulations, &result.tally)?;
+    }
+    if let Some(vectors) = &result.vectors {
+        output::write_vector_tiff(&config.output.directory.join(format!("{}-vectors.tiff", stem)), vectors)?;
+        // The canvas only gives the size, the vectors are the two channels
+        if config.output.npy {
+            write_npy(&config.output.directory.join(format!("{}-vectors.npy", stem)), &result.canvas, Channels::Phases(vectors))?;
+        }
+    }
+    for extra in &result.extras {
+        let config = &RunConfig { shader: extra.shader.clone(), shader_expr: None, shader_script: None, extra_shaders: Vec::new(),
+                                  ..config.clone() };
+        let stem = format!("{}-{}", stem, extra.shader);
+        write_canvas(config, &stem, &extra.canvas, Channels::Gray, None, result.simulations, &result.tally)?;
+        if let Some(escapes) = &extra.escapes {
+            write_canvas(config, &format!("{}-escapes", stem), escapes, Channels::Gray, None, result.simulations, &result.tally)?;
+        }
+    }
+    Ok(())
 }
 
 
-enum ToThreadMsg {
-    ACCUMULATE,
-    STOP
+// The dump gets the channels, the image the colors, when there are any
+fn write_canvas(config: &RunConfig, stem: &str, canvas: &Canvas<f64>, channels: Channels, colors: Option<&Canvas<Vector3<f64>>>,
+                simulations: usize, tally: &Tally) -> Result<(), String>
+{
+    let dump_path = config.output.directory.join(format!("{}.dump", stem));
+    if let Some(dir) = dump_path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create directory {}: {}", dir.display(), e))?;
+    }
+
+    let header = DumpHeader {
+        width: canvas.width,
+        height: canvas.height,
+        simulations,
+        tally: Some(tally.clone()),
+        channels: channels.count(),
+        config: config.clone(),
+    };
+    write_dump(&dump_path, &header, canvas, channels)?;
+    if config.output.npy {
+        write_npy(&config.output.directory.join(format!("{}.npy", stem)), canvas, channels)?;
+    }
+    let image_path = config.output.directory.join(format!("{}.{}", stem, config.output.image_format.extension()));
+    write_image(&image_path, canvas, colors, config, config.output.tone_map, config.output.image_format)?;
+
+    Ok(())
 }
 
 
-enum FromThreadMsg {
-    REPORT(usize)
+// Write the image of a canvas, in color when the run was
+fn write_image(path: &Path, canvas: &Canvas<f64>, colors: Option<&Canvas<Vector3<f64>>>, config: &RunConfig, tone_map: ToneMap,
+               format: ImageFormat) -> Result<(), String>
+{
+    match (colors, format) {
+        (Some(colors), ImageFormat::Tiff) => output::write_color_tiff(path, &output::normalize_colors(&symmetry::image_canvas(colors, config), tone_map)),
+        (None, ImageFormat::Tiff) => output::write_tiff(path, &output::normalize(&symmetry::image_canvas(canvas, config), tone_map)),
+        (Some(colors), _) => output::write_color_png(path, &output::normalize_colors(&symmetry::image_canvas(colors, config), tone_map),
+                                                     png_bits(format))?,
+        (None, _) => output::write_png(path, &output::normalize(&symmetry::image_canvas(canvas, config), tone_map), png_bits(format))?,
+    }
+    Ok(())
 }
 
 
-fn sim_thread<T: AddAssign + Default + Clone>(rx: mpsc::Receiver<ToThreadMsg>,
-              tx: mpsc::Sender<FromThreadMsg>,
-              result_canvas: Arc<Mutex<Canvas<T>>>,
-              shader_func: ShaderFunc<T>)
+fn png_bits(format: ImageFormat) -> u8
 {
-    const THREAD_REPORT_INTERVAL: Duration = Duration::from_millis(50);
-    const SIM_BATCH_SIZE: usize = 100;
+    if format == ImageFormat::Png8 { 8 } else { 16 }
+}
 
-    let width = result_canvas.lock().unwrap().width;
-    let height = result_canvas.lock().unwrap().height;
 
-    let mut thread_canvas: Canvas<T> = Canvas::new(width, height, T::default());
-    let mut scene = initial_obstacles();
-    let mut rng = thread_rng();
-    let mut last_report_t = time::Instant::now();
+// Parses "x,y"
+fn parse_point(s: &str) -> Result<[f64; 2], String>
+{
+    let parse = |v: &str| v.trim().parse::<f64>().map_err(|e| format!("{}: {}", v, e));
 
-    loop {
+    match s.split_once(',') {
+        Some((x, y)) => Ok([parse(x)?, parse(y)?]),
+        None => Err(format!("expected x,y but got '{}'", s))
+    }
+}
 
-        let mut no_simulations_to_report = 0;
 
-        loop {
-            for _ in 0..SIM_BATCH_SIZE {
-                single_simulation(&mut thread_canvas, &mut scene, &mut rng, shader_func);
-            }
+// Parses "columnsxrows", like "3x2"
+fn parse_tiles(s: &str) -> Result<[usize; 2], String>
+{
+    let parse = |v: &str| v.trim().parse::<usize>().map_err(|e| format!("{}: {}", v, e));
 
-            no_simulations_to_report += SIM_BATCH_SIZE;
+    match s.split_once('x') {
+        Some((columns, rows)) => Ok([parse(columns)?, parse(rows)?]),
+        None => Err(format!("expected columnsxrows but got '{}'", s))
+    }
+}
 
-            let now = time::Instant::now();
-            if (now - last_report_t) > THREAD_REPORT_INTERVAL {
-                last_report_t = now;
-                break;
-            }
-        }
 
-        tx.send(REPORT(no_simulations_to_report)).unwrap();
+// Parses "reflect", "absorb", "transmit:probability", "rough:roughness" or "sticky:distance"
+fn parse_material(s: &str) -> Result<Material, String>
+{
+    match s.split_once(':') {
+        None if s == "reflect" => Ok(Material::Reflect),
+        None if s == "absorb" => Ok(Material::Absorb),
+        Some(("transmit", p)) => Ok(Material::Transmit(p.trim().parse::<f64>().map_err(|e| format!("{}: {}", p, e))?)),
+        Some(("rough", r)) => Ok(Material::Rough(r.trim().parse::<f64>().map_err(|e| format!("{}: {}", r, e))?)),
+        Some(("sticky", d)) => Ok(Material::Sticky(d.trim().parse::<f64>().map_err(|e| format!("{}: {}", d, e))?)),
+        _ => Err(format!("expected reflect, absorb, transmit:probability, rough:roughness or sticky:distance but got '{}'", s))
+    }
+}
 
-        match rx.recv_timeout(Duration::ZERO) {
-            Ok(ACCUMULATE) => {
-                let mut locked_canvas = result_canvas.lock().unwrap();
-                for (p_in, p_out) in zip(thread_canvas.iter(), locked_canvas.iter_mut()) {
-                    *p_out += p_in.clone();
-                }
-            }
-            Ok(STOP) => {
-                return
-            }
-            Err(RecvTimeoutError::Disconnected) => {
-                panic!();
-            }
-            _ => {}
-        }
+
+// Parses "stop" or "perturb:degrees"
+fn parse_on_cycle(s: &str) -> Result<OnCycle, String>
+{
+    match s.split_once(':') {
+        None if s == "stop" => Ok(OnCycle::Stop),
+        Some(("perturb", d)) => Ok(OnCycle::Perturb(d.trim().parse::<f64>().map_err(|e| format!("{}: {}", d, e))?)),
+        _ => Err(format!("expected stop or perturb:degrees but got '{}'", s))
     }
 }
 
 
-#[derive(Debug)]
-struct ThreadHandle {
-    join_handle: thread::JoinHandle<()>,
-    to_thread: mpsc::Sender<ToThreadMsg>,
-    from_thread: mpsc::Receiver<FromThreadMsg>,
+// Parses "exponential:mean" or "power-law:min,alpha"
+fn parse_tumble(s: &str) -> Result<Tumble, String>
+{
+    let parse = |v: &str| v.trim().parse::<f64>().map_err(|e| format!("{}: {}", v, e));
+    match s.split_once(':') {
+        Some(("exponential", mean)) => Ok(Tumble::Exponential { mean: parse(mean)? }),
+        Some(("power-law", params)) => match params.split_once(',') {
+            Some((min, alpha)) => Ok(Tumble::PowerLaw { min: parse(min)?, alpha: parse(alpha)? }),
+            None => Err(format!("expected power-law:min,alpha but got '{}'", s)),
+        },
+        _ => Err(format!("expected exponential:mean or power-law:min,alpha but got '{}'", s))
+    }
 }
 
 
-fn main()
+fn simulate(args: &SimulateArgs) -> Result<(), String>
 {
-    let canvas: Canvas<f64> = Canvas::new(IMAGE_SIZE, IMAGE_SIZE, 0.0);
-    let shared_canvas = Arc::new(Mutex::new(canvas));
+    let mut config = args.run_config()?;
+    config.fix_seed();
 
-    let no_threads: usize = std::thread::available_parallelism().unwrap().into();
+    if let Some(path) = &args.save_scene {
+        let seed = arena::arena_seed(&config.arena, config.seed.unwrap(), 0);
+        scene_file::save_scene(path, &arena::initial_arena(&config.arena, seed), &arena::describe(&config.arena))?;
+    }
 
-    println!("Starting {} threads", no_threads);
+    let pool = WorkerPool::new(no_threads(config.threads));
+    if args.dry_run {
+        estimate::dry_run(&pool, &config, args.calibration_sims)?;
+        return Ok(());
+    }
 
-    // Start all threads
-    let mut thread_handles: Vec<ThreadHandle> = Vec::new();
-    for _ in 0..no_threads {
-        let (to_thread, to_thread_rx) = mpsc::channel();
-        let (from_thread_tx, from_thread) = mpsc::channel();
+    let result = pool.simulate(&config)?;
+    write_results(&config, &output_stem(&config, "", "")?, &result)
+}
 
-        let canvas_ref = shared_canvas.clone();
 
-        thread_handles.push(ThreadHandle {
-            join_handle: thread::spawn(move || {
-                sim_thread(to_thread_rx, from_thread_tx, canvas_ref, SHADER_FUNC)
-            }),
-            to_thread,
-            from_thread,
-        });
+fn sweep(args: &SweepArgs) -> Result<(), String>
+{
+    if args.run.save_scene.is_some() {
+        return Err("--save-scene only works with the simulate command".to_string());
+    }
+    let base_config = args.run.run_config()?;
+    let values = args.param.values(args.from, args.to, args.steps)?;
+
+    // Check all configs before spending hours on the first ones
+    let mut configs: Vec<RunConfig> = Vec::new();
+    for value in &values {
+        let mut config = base_config.clone();
+        args.param.apply(&mut config, *value);
+        config.validate()?;
+        config.fix_seed();
+        configs.push(config);
     }
 
-    // Keep track of the progress of all threads and report with a nice progress bar
-    let progbar = ProgressBar::new(MIN_NUM_OF_SIMULATIONS as u64);
-    progbar.set_style(ProgressStyle::with_template("[{elapsed}]/[{eta} left] {bar:40.cyan/blue} {percent}% {pos:>7}/{len:7} {per_sec}").unwrap());
-
-    let mut simulations_done: usize = 0;
-    while simulations_done < MIN_NUM_OF_SIMULATIONS {
-        for thread in &thread_handles {
-            assert!(!thread.join_handle.is_finished());
-
-            match thread.from_thread.recv_timeout(Duration::from_millis(1)) {
-                Ok(REPORT(n)) => {
-                    simulations_done += n;
-                    progbar.inc(n as u64);
-                }
-                Err(RecvTimeoutError::Disconnected) => {
-                    panic!();
-                }
-
-                Err(RecvTimeoutError::Timeout) => {}
-            }
+    let pool = WorkerPool::new(no_threads(base_config.threads));
+    let mut total_runtime = Duration::ZERO;
+    for (i, (config, value)) in configs.iter().zip(&values).enumerate() {
+        let label = format!("{}={}", args.param.name(), args.param.format(*value));
+        println!("Run {}/{}: {}", i + 1, configs.len(), label);
+
+        if args.run.dry_run {
+            total_runtime += estimate::dry_run(&pool, config, args.run.calibration_sims)?;
+            continue;
         }
+
+        let result = pool.simulate(config)?;
+        write_results(config, &output_stem(config, "", &label)?, &result)?;
     }
-    progbar.finish();
+
+    if args.run.dry_run {
+        println!("Estimated runtime of the whole sweep: {}", humantime::format_duration(Duration::from_secs(total_runtime.as_secs())));
+    }
+
+    Ok(())
+}
 
 
-    // Asks all threads to accumulate in the shared canvas and ask them to stop working
-    for thread in &thread_handles {
-        thread.to_thread.send(ACCUMULATE).unwrap();
-        thread.to_thread.send(STOP).unwrap();
+fn animate(args: &AnimateArgs) -> Result<(), String>
+{
+    if args.run.save_scene.is_some() {
+        return Err("--save-scene only works with the simulate command".to_string());
     }
+    let mut first_config = args.run.run_config()?;
+    // The same seed for every frame, so they only differ by their arena
+    first_config.fix_seed();
+    let last = LastFrame { edges: args.to_edges, size: args.to_size, rotation: args.to_rotation };
+    let configs = animate::frame_configs(&first_config, &last, args.frames)?;
+    let stem = output_stem(&first_config, "", "")?;
+
+    let pool = WorkerPool::new(no_threads(first_config.threads));
+    let mut total_runtime = Duration::ZERO;
+    for (i, config) in configs.iter().enumerate() {
+        println!("Frame {}/{}: {}", i + 1, configs.len(), arena::describe(&config.arena));
+
+        if args.run.dry_run {
+            total_runtime += estimate::dry_run(&pool, config, args.run.calibration_sims)?;
+            continue;
+        }
 
-    // Join all threads
-    while ! thread_handles.is_empty() {
-        let handle = thread_handles.pop().unwrap();
-        handle.join_handle.join().unwrap();
+        // Numbered from 0, for ffmpeg -i {stem}-%04d.png
+        let frame_stem = format!("{}-{:04}", stem, i);
+        let result = pool.simulate(config)?;
+        write_results(config, &frame_stem, &result)?;
+        let png_path = config.output.directory.join(format!("{}.png", frame_stem));
+        match &result.colors {
+            Some(colors) => output::write_color_png(&png_path, &output::normalize_colors(&symmetry::image_canvas(colors, config),
+                                                                                         config.output.tone_map), 16)?,
+            None => output::write_png(&png_path, &output::normalize(&symmetry::image_canvas(&result.canvas, config),
+                                                                    config.output.tone_map), 16)?,
+        }
     }
 
-    // Make a new canvas, normalized and scaled to u32::MAX
-    let canvas = shared_canvas.lock().unwrap();
-    let mut normalized_canvas: Canvas<u32> = Canvas::new(canvas.width, canvas.height, 0);
-    let src_max = canvas.iter().max_by(|a, b| a.partial_cmp(&b).unwrap()).unwrap();
-    for (src, target) in zip(canvas.iter(), normalized_canvas.iter_mut()) {
-        *target = clamp((u32::MAX as f64 * src.log10() / src_max.log10()) as u32, 0, u32::MAX);
+    if args.run.dry_run {
+        println!("Estimated runtime of the whole animation: {}", humantime::format_duration(Duration::from_secs(total_runtime.as_secs())));
     }
 
-    // Write a 32bit grayscale tiff
-    let f = File::create(format!("raw-{}.tiff", Local::now())).unwrap();
-    let mut encoder = tiff::encoder::TiffEncoder::new(f).unwrap();
-    encoder.write_image::<colortype::Gray32>(normalized_canvas.width as u32,
-                                             normalized_canvas.height as u32,
-                                             &normalized_canvas.data).unwrap();
+    Ok(())
 }
 
 
-fn angle(angle: f64) -> Coord
+fn batch(args: &BatchArgs) -> Result<(), String>
 {
-    coord! {x: f64::cos(angle), y: f64::sin(angle)}
+    let mut batch = load_batch(&args.batch)?;
+    for run in &mut batch.runs {
+        if let Some(dir) = &args.output_dir {
+            run.config.output.directory = dir.clone();
+        }
+        run.config.fix_seed();
+    }
+    let configs: Vec<RunConfig> = batch.runs.iter().map(|run| run.config.clone()).collect();
+
+    let mut pool = WorkerPool::new(no_threads(args.threads.or(batch.threads)));
+    let parallel = args.parallel.unwrap_or(batch.parallel);
+    println!("{} runs, {} at a time", configs.len(), parallel.min(pool.size()));
+
+    pool.simulate_all(&configs, parallel, |i, result| {
+        let stem = output_stem(&configs[i], &batch.runs[i].name, "")?;
+        write_results(&configs[i], &stem, &result)
+    })
 }
 
 
-fn _draw_line<T: Copy + Add<Output = T>>(canvas: &mut Canvas<T>, p0: bresenham::Point, p1: bresenham::Point, v: T)
+fn postprocess(args: &PostprocessArgs) -> Result<(), String>
 {
-    for (x, y) in bresenham::Bresenham::new(p0, p1) {
-        let x = x as usize;
-        let y = y as usize;
-        if x < canvas.width && y < canvas.height {
-            let idx = x + canvas.width * y;
-            canvas.data[idx] = canvas.data[idx] + v;
+    let dump = read_dump(&args.dump)?;
+    if args.npy {
+        let channels = Channels::of(&dump.header.config, dump.bins.as_ref(), dump.phases.as_ref(), dump.colors.as_ref());
+        let npy_path = replace_dump_extension(&args.dump, "npy");
+        write_npy(&npy_path, &dump.canvas, channels)?;
+        println!("Wrote {}", npy_path.display());
+    }
+
+    let tone_map = args.tone_map.unwrap_or(dump.header.config.output.tone_map);
+    let format = args.image_format.unwrap_or(dump.header.config.output.image_format);
+    let output_path = match &args.output {
+        Some(path) => path.clone(),
+        None => replace_dump_extension(&args.dump, format.extension())
+    };
+
+    // The phases of a phase run can be shown another way than they were
+    let mut canvas = match (&dump.phases, args.phase_image) {
+        (Some(phases), Some(image)) => output::phase_image(phases, image),
+        _ => dump.canvas,
+    };
+    // The bins of direction and band runs can be colored with other hues than they were
+    let mut colors = match (&dump.bins, &args.bin_hues) {
+        (Some(bins), Some(hues)) if Some(hues.len()) == dump.header.config.bins() => Some(output::bin_colors(bins, hues)),
+        (Some(_), Some(hues)) => {
+            return Err(format!("the dump has {} bins, got {} hues", dump.header.config.bins().unwrap_or(0), hues.len()));
+        }
+        _ => dump.colors,
+    };
+    if let Some(path) = &args.divide_by {
+        if dump.header.config.reduction != Reduction::Sum {
+            return Err("only dumps of runs that summed the weights in every pixel can be divided by the hit counts".to_string());
         }
+        let hits = read_dump(path)?.canvas;
+        if (hits.width, hits.height) != (canvas.width, canvas.height) {
+            return Err(format!("the hit counts in {} are {} x {}, the dump is {} x {}",
+                               path.display(), hits.width, hits.height, canvas.width, canvas.height));
+        }
+        canvas = output::per_hit(&canvas, &hits);
+        colors = colors.map(|colors| output::per_hit(&colors, &hits));
     }
+    write_image(&output_path, &canvas, colors.as_ref(), &dump.header.config, tone_map, format)?;
+    println!("Wrote {}", output_path.display());
+
+    Ok(())
 }
 
 
-fn _draw_segment<T: Copy + Add<Output = T>>(canvas: &mut Canvas<T>, segment: Line, val: T)
+fn analyze(args: &AnalyzeArgs) -> Result<(), String>
 {
-    let p0 = ((segment.start.x * canvas.width as f64) as isize, (segment.start.y * canvas.height as f64) as isize);
-    let p1 = ((segment.end.x * canvas.width as f64) as isize, (segment.end.y * canvas.height as f64) as isize);
-    _draw_line(canvas, p0, p1, val);
+    let dump = read_dump(&args.dump)?;
+    analyze::print_summary(&dump);
+
+    Ok(())
+}
+
+
+// Path::with_extension replaces everything after the last dot, but the timestamps
+// in our file names contain dots as well
+fn replace_dump_extension(path: &Path, extension: &str) -> PathBuf
+{
+    let path = path.to_string_lossy();
+    let stem = path.strip_suffix(".dump").unwrap_or(&path);
+    PathBuf::from(format!("{}.{}", stem, extension))
+}
+
+
+fn main()
+{
+    let cli = Cli::parse();
+
+    let result = match &cli.command {
+        Command::Simulate(args) => simulate(args),
+        Command::Sweep(args) => sweep(args),
+        Command::Animate(args) => animate(args),
+        Command::Batch(args) => batch(args),
+        Command::Postprocess(args) => postprocess(args),
+        Command::Analyze(args) => analyze(args),
+        Command::Inspect(args) => args.run_config().map(|config| inspect::print_properties(&config)),
+        Command::Presets => {
+            for preset in PRESETS {
+                println!("{:20} {}", preset.name, preset.description);
+            }
+            Ok(())
+        }
+        Command::Shaders => {
+            for shader in SHADERS {
+                println!("{:22} {}", shader.name, shader.description);
+            }
+            println!();
+            println!("Color shaders, for --color:");
+            for shader in COLOR_SHADERS {
+                println!("{:22} {}", shader.name, shader.description);
+            }
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(2);
+    }
 }
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use geo::{Coord, coord, LineString, Simplify};
+
+
+// The edges of the sample grid a contour can cross, horizontal ones go from (x, y)
+// to (x + 1, y), vertical ones from (x, y) to (x, y + 1)
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+struct GridEdge {
+    x: i64,
+    y: i64,
+    horizontal: bool,
+}
+
+
+// Read a black and white image, and trace the contours of its dark parts with
+// marching squares, or of its light parts when `invert` is set. The contours are
+// simplified until no point is more than `tolerance` pixels off. The result is in
+// pixel coordinates, one closed outline per contour, without repeating the first point.
+pub fn load_mask(path: &Path, invert: bool, tolerance: f64) -> Result<Vec<Vec<[f64; 2]>>, String>
+{
+    let image = image::open(path)
+        .map_err(|e| format!("Could not read mask {}: {}", path.display(), e))?
+        .to_luma32f();
+
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    // Everything around the image is outside, so every contour is closed
+    let inside_value = |x: i64, y: i64| -> f64 {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return 0.0;
+        }
+        let luma = image.get_pixel(x as u32, y as u32).0[0] as f64;
+        if invert { luma } else { 1.0 - luma }
+    };
+
+    let contours = trace_contours(inside_value, width, height);
+
+    let outlines: Vec<Vec<[f64; 2]>> = contours.into_iter()
+        .map(|contour| {
+            let mut line = LineString::from(contour);
+            line.close();
+            let simplified = line.simplify(&tolerance);
+            simplified.0[..simplified.0.len() - 1].iter().map(|c| [c.x, c.y]).collect::<Vec<[f64; 2]>>()
+        })
+        .filter(|outline| outline.len() >= 3)
+        .collect();
+
+    if outlines.is_empty() {
+        return Err(format!("Mask {} has no contours", path.display()));
+    }
+    Ok(outlines)
+}
+
+
+// Marching squares over the pixel centers from (-1, -1) to (width, height). A
+// sample is inside when its value is above 0.5. Every contour is walked with the
+// inside on its left, so each crossing has exactly one successor.
+fn trace_contours(value: impl Fn(i64, i64) -> f64, width: i64, height: i64) -> Vec<Vec<Coord>>
+{
+    let crossing = |edge: GridEdge| -> Coord {
+        let (x1, y1) = if edge.horizontal { (edge.x + 1, edge.y) } else { (edge.x, edge.y + 1) };
+        let (a, b) = (value(edge.x, edge.y), value(x1, y1));
+        let t = ((0.5 - a) / (b - a)).clamp(0.0, 1.0);
+        coord! {x: edge.x as f64 + t * (x1 - edge.x) as f64 + 0.5,
+                y: edge.y as f64 + t * (y1 - edge.y) as f64 + 0.5}
+    };
+
+    let mut next: BTreeMap<GridEdge, GridEdge> = BTreeMap::new();
+    for y in -1..height {
+        for x in -1..width {
+            let top = GridEdge { x, y, horizontal: true };
+            let bottom = GridEdge { x, y: y + 1, horizontal: true };
+            let left = GridEdge { x, y, horizontal: false };
+            let right = GridEdge { x: x + 1, y, horizontal: false };
+
+            let tl = value(x, y) > 0.5;
+            let tr = value(x + 1, y) > 0.5;
+            let br = value(x + 1, y + 1) > 0.5;
+            let bl = value(x, y + 1) > 0.5;
+
+            // With the y axis pointing down, walking clockwise around a corner keeps it on the left
+            let segments: &[(GridEdge, GridEdge)] = match (tl, tr, br, bl) {
+                (false, false, false, false) | (true, true, true, true) => &[],
+                (false, false, false, true) => &[(bottom, left)],
+                (false, false, true, false) => &[(right, bottom)],
+                (false, false, true, true) => &[(right, left)],
+                (false, true, false, false) => &[(top, right)],
+                (false, true, false, true) => &[(top, right), (bottom, left)],
+                (false, true, true, false) => &[(top, bottom)],
+                (false, true, true, true) => &[(top, left)],
+                (true, false, false, false) => &[(left, top)],
+                (true, false, false, true) => &[(bottom, top)],
+                (true, false, true, false) => &[(left, top), (right, bottom)],
+                (true, false, true, true) => &[(right, top)],
+                (true, true, false, false) => &[(left, right)],
+                (true, true, false, true) => &[(bottom, right)],
+                (true, true, true, false) => &[(left, bottom)],
+            };
+            for (from, to) in segments {
+                next.insert(*from, *to);
+            }
+        }
+    }
+
+    let mut contours: Vec<Vec<Coord>> = Vec::new();
+    while let Some(&start) = next.keys().next() {
+        let mut contour: Vec<Coord> = Vec::new();
+        let mut edge = start;
+        while let Some(to) = next.remove(&edge) {
+            contour.push(crossing(edge));
+            edge = to;
+        }
+        contours.push(contour);
+    }
+    contours
+}
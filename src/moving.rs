@@ -0,0 +1,145 @@
+use std::f64::consts::PI;
+
+use geo::{Coord, coord, Line, Vector2DOps};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Material;
+use crate::scene::WallHit;
+use crate::simulation::angle;
+
+// The flight of the ball is searched for a moving wall in steps of this length
+const SEARCH_STEP: f64 = 0.001;
+
+// A ball that just bounced off a moving wall is not searched for it right at its start
+const SEARCH_GAP: f64 = 1e-6;
+
+
+// A wall segment that moves with the time of the ball, the path length it flew at
+// speed 1. Written in a config file as, for example,
+// { rotating = { center = [0.5, 0.5], length = 0.3, rate = 0.5 } }
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MovingWall {
+    // A paddle turning clockwise around its middle at `rate` turns per unit of time,
+    // and at `angle` degrees from pointing right at time 0
+    Rotating { center: [f64; 2], length: f64, rate: f64, #[serde(default)] angle: f64 },
+    // A segment moving back and forth by `amplitude` times the sine of 2 pi time / period
+    Oscillating { ends: [[f64; 2]; 2], amplitude: [f64; 2], period: f64 },
+}
+
+
+impl MovingWall {
+    // Where the wall is at the time
+    pub fn at(&self, time: f64) -> Line
+    {
+        match self {
+            MovingWall::Rotating { center, length, rate, angle: start } => {
+                let center = coord! {x: center[0], y: center[1]};
+                let half = angle(start.to_radians() + 2.0 * PI * rate * time) * (length / 2.0);
+                Line::new(center - half, center + half)
+            }
+            MovingWall::Oscillating { ends: [start, end], amplitude, period } => {
+                let shift = coord! {x: amplitude[0], y: amplitude[1]} * (2.0 * PI * time / period).sin();
+                Line::new(coord! {x: start[0], y: start[1]} + shift, coord! {x: end[0], y: end[1]} + shift)
+            }
+        }
+    }
+
+    // Center and radius of a disc the wall stays within
+    fn reach(&self) -> (Coord, f64)
+    {
+        match self {
+            MovingWall::Rotating { center, length, .. } => (coord! {x: center[0], y: center[1]}, length / 2.0),
+            MovingWall::Oscillating { ends: [start, end], amplitude, .. } => {
+                let (start, end) = (coord! {x: start[0], y: start[1]}, coord! {x: end[0], y: end[1]});
+                ((start + end) * 0.5, (end - start).magnitude() / 2.0 + coord! {x: amplitude[0], y: amplitude[1]}.magnitude())
+            }
+        }
+    }
+
+    // The first point where the ball runs into the wall, where the wall is when the
+    // ball gets there. `time_at` is the time the ball gets a distance along its
+    // flight. The flight is searched in short steps for where the ball changes sides
+    // of the line through the wall, and that is narrowed down to where it does.
+    pub fn hit(&self, ball: Line, time_at: impl Fn(f64) -> f64) -> Option<WallHit>
+    {
+        let length = ball.delta().magnitude();
+        let dir = ball.delta() / length;
+
+        // Only the part of the flight within the reach of the wall has to be searched
+        let (center, radius) = self.reach();
+        let along = (center - ball.start).dot_product(dir);
+        let off = (center - ball.start).wedge_product(dir);
+        if off.abs() > radius {
+            return None;
+        }
+        let half = (radius * radius - off * off).sqrt();
+        let (first, last) = ((along - half).max(SEARCH_GAP), (along + half).min(length));
+
+        let side = |distance: f64| {
+            let wall = self.at(time_at(distance));
+            wall.delta().wedge_product(ball.start + dir * distance - wall.start)
+        };
+        let mut a = first;
+        let mut side_a = side(a);
+        while a < last {
+            let b = (a + SEARCH_STEP).min(last);
+            let side_b = side(b);
+            if (side_a < 0.0) != (side_b < 0.0) {
+                let (mut lo, mut hi) = (a, b);
+                for _ in 0..50 {
+                    let mid = (lo + hi) / 2.0;
+                    if (side(mid) < 0.0) == (side_a < 0.0) { lo = mid } else { hi = mid }
+                }
+                // It only hits where the wall is, not elsewhere on its line
+                let wall = self.at(time_at(hi));
+                let point = ball.start + dir * hi;
+                let t = (point - wall.start).dot_product(wall.delta()) / wall.delta().magnitude_squared();
+                if (0.0..=1.0).contains(&t) {
+                    return Some(WallHit { point, distance: hi, tangent: wall.delta(), opening: false, interface: false,
+                                          material: Material::Reflect, surface: None, wall: None });
+                }
+            }
+            (a, side_a) = (b, side_b);
+        }
+        None
+    }
+
+    pub fn describe(&self) -> String
+    {
+        match self {
+            MovingWall::Rotating { center, length, rate, .. } => {
+                format!("paddle of length {} around ({}, {}), {} turns per unit of time", length, center[0], center[1], rate)
+            }
+            MovingWall::Oscillating { amplitude, period, .. } => {
+                format!("wall oscillating by {} x {}, period {}", amplitude[0], amplitude[1], period)
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String>
+    {
+        match self {
+            MovingWall::Rotating { center, length, rate, angle } => {
+                if !(center.iter().all(|c| c.is_finite()) && rate.is_finite() && angle.is_finite()) {
+                    return Err("rotating wall center, rate and angle must be finite".to_string());
+                }
+                if !(*length > 0.0 && length.is_finite()) {
+                    return Err(format!("rotating wall length must be larger than 0, got {}", length));
+                }
+            }
+            MovingWall::Oscillating { ends, amplitude, period } => {
+                if !(ends.iter().flatten().all(|c| c.is_finite()) && amplitude.iter().all(|c| c.is_finite())) {
+                    return Err("oscillating wall ends and amplitude must be finite".to_string());
+                }
+                if ends[0] == ends[1] {
+                    return Err("oscillating walls need two different ends".to_string());
+                }
+                if !(*period > 0.0 && period.is_finite()) {
+                    return Err(format!("oscillating wall period must be larger than 0, got {}", period));
+                }
+            }
+        }
+        Ok(())
+    }
+}
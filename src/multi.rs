@@ -0,0 +1,117 @@
+use std::iter::zip;
+
+use geo::{EuclideanDistance, Line, Vector2DOps};
+use rand::prelude::*;
+use simple_canvas::Canvas;
+
+use crate::scene::{Scene, segment_hit, WallHit};
+use crate::shaders::Shader;
+use crate::simulation::{Ball, Crossings, deposit_ending, Plan, random_start, SimStepOutcome, Trajectory, Weight};
+
+// A ball that runs into the flight of another ball stops this much short of it, so
+// the end of its trail is not in the way of that flight later
+const CROSSING_MARGIN: f64 = 1e-7;
+
+
+// Simulate `balls` balls in the same arena at the same time, that avoid the trails
+// of the others as well as their own. They all fly at the same speed and lay their
+// trail as they go, so a ball only runs into the flight of another where that one
+// passed earlier. Every round the ball with the earliest next event is advanced to
+// it, the others wait where they are, on their way. With branching, a bouncing ball
+// can split off another, that goes straight on where it bounced. The balls are
+// deposited like single balls, and returned as they ended.
+pub fn multi_simulation<T: Weight, R: Rng>(canvas: &mut Canvas<T>,
+                                           mut escapes: Option<&mut Canvas<T>>,
+                                           scene: &mut Scene,
+                                           rng: &mut R,
+                                           canvas_shader: &dyn Shader<T>,
+                                           resample_starts: bool,
+                                           balls: usize) -> Vec<Trajectory>
+{
+    let mut flying: Vec<Ball> = (0..balls)
+        .filter_map(|_| random_start(scene, rng, resample_starts))
+        .map(|(start_pos, dir)| {
+            let mut ball = Ball::new(start_pos, dir);
+            ball.bounce_points = scene.record_bounces.then(|| vec![start_pos]);
+            ball.crossings = scene.count_crossings.then(Crossings::default);
+            ball
+        })
+        .collect();
+    let mut ended = Vec::with_capacity(flying.len());
+    let mut branches = 0;
+
+    while !flying.is_empty() {
+        let mut plans: Vec<Plan> = flying.iter_mut().map(|ball| ball.plan(scene)).collect();
+        // The flights up to where something happens. A ball that never runs into
+        // anything is lost, that is its next event.
+        let flights: Vec<Option<Line>> = zip(&flying, &plans)
+            .map(|(ball, plan)| plan.distance().map(|distance| {
+                Line::new(ball.line.start, ball.line.start + ball.line.delta() * (distance / ball.line.delta().magnitude()))
+            }))
+            .collect();
+        // When a ball gets `distance` along its flight
+        let arrival = |i: usize, distance: f64| flying[i].arrival(distance, scene);
+
+        let mut next: Option<(usize, f64, Option<WallHit>)> = None;
+        for (i, flight) in flights.iter().enumerate() {
+            let Some(flight) = flight else {
+                next = Some((i, f64::NEG_INFINITY, None));
+                break;
+            };
+
+            let mut time = arrival(i, flight.delta().magnitude());
+            let mut crossing = None;
+            for (j, other) in flights.iter().enumerate() {
+                let Some(other) = other.filter(|other| j != i && scene.blocks_from(*other, flight.start)) else {
+                    continue;
+                };
+                if let Some(hit) = segment_hit(other, *flight) {
+                    let t = arrival(i, hit.distance);
+                    if arrival(j, hit.point.euclidean_distance(&other.start)) < t && t < time {
+                        time = t;
+                        crossing = Some(hit);
+                    }
+                }
+            }
+            if next.as_ref().is_none_or(|(_, earliest, _)| time < *earliest) {
+                next = Some((i, time, crossing));
+            }
+        }
+        let (i, _, crossing) = next.unwrap();
+
+        let mut plan = plans.swap_remove(i);
+        if let Some(hit) = crossing {
+            let flight = flights[i].unwrap();
+            let dir = flight.delta() / flight.delta().magnitude();
+            plan = plan.hitting(WallHit { point: hit.point - dir * CROSSING_MARGIN, distance: hit.distance - CROSSING_MARGIN,
+                                          material: scene.trail_material, ..hit });
+        }
+        let bounce = plan.bounce_point().map(|point| (point, flying[i].line.delta(), flying[i].no_bounces));
+        match flying[i].advance(plan, scene, rng) {
+            SimStepOutcome::Bounced | SimStepOutcome::Flew => {
+                // Passing through a wall is not a bounce, and a branch has to start in the arena
+                if let Some((point, delta, bounces)) = bounce {
+                    if flying[i].no_bounces > bounces && branches < scene.max_branches && scene.branching > 0.0
+                        && rng.gen_bool(scene.branching) {
+                        let branch = flying[i].branch(point, delta / delta.magnitude(), scene);
+                        if scene.contains(branch.line.start) {
+                            flying.push(branch);
+                            branches += 1;
+                        }
+                    }
+                }
+            }
+            outcome => {
+                let ball = flying.swap_remove(i);
+                if let Some(trajectory) = ball.ended(outcome) {
+                    deposit_ending(canvas, escapes.as_deref_mut(), scene, canvas_shader, &ball, &trajectory);
+                    ended.push(trajectory);
+                }
+            }
+        }
+    }
+
+    // Leave the scene in state that we started with
+    scene.trail.clear();
+    ended
+}
@@ -0,0 +1,160 @@
+use geo::{ConvexHull, Coord, coord, Line, MultiPoint, Point, Vector2DOps};
+use rand::prelude::*;
+use simple_canvas::Canvas;
+
+use crate::scene::{Contact, Scene, segment_hit};
+use crate::shaders::Shader;
+use crate::simulation::{bounce_path, Crossings, deposit_weight, swept, Termination, Trajectory, Trap, Weight};
+
+const MAX_START_ATTEMPTS: usize = 1000;
+
+// Curved walls are approximated by this many points for the table
+const CURVE_POINTS: usize = 256;
+
+
+// The table of outer billiards: the convex hull of the closed walls of the scene,
+// shrunk by `scale` around its center, without repeating the first vertex
+pub fn table(scene: &Scene, scale: f64) -> Vec<Coord>
+{
+    let points: Vec<Point> = scene.walls[..scene.no_closed_walls].iter()
+        .flat_map(|wall| wall.shape.points(CURVE_POINTS))
+        .map(Point::from)
+        .collect();
+    let hull = MultiPoint::from(points).convex_hull();
+
+    let ring = &hull.exterior().0;
+    ring[..ring.len().saturating_sub(1)].iter().map(|v| scene.center + (*v - scene.center) * scale).collect()
+}
+
+
+// Whether the point is on the table, or on its edges
+fn on_table(table: &[Coord], point: Coord) -> bool
+{
+    let n = table.len();
+    let sides: Vec<f64> = (0..n).map(|i| (table[(i + 1) % n] - table[i]).wedge_product(point - table[i])).collect();
+    sides.iter().all(|s| *s >= 0.0) || sides.iter().all(|s| *s <= 0.0)
+}
+
+
+// The vertex the point jumps through: the one with the table on its right, seen
+// from the point. The point has to be outside the table.
+fn tangent_vertex(table: &[Coord], point: Coord) -> Coord
+{
+    let mut best = table[0];
+    for vertex in &table[1..] {
+        if (best - point).wedge_product(*vertex - point) < 0.0 {
+            best = *vertex;
+        }
+    }
+    best
+}
+
+
+// Simulate one point of outer billiards. It starts somewhere outside the table,
+// and jumps to its mirror image in the tangent vertex every step. Like the ball, it
+// is trapped when a jump crosses one of its earlier jumps, or when its trail is
+// full, and deposited where the jump crossed. A point that jumps out of the image
+// escapes, and is deposited in `escapes`, if given, where it landed. Returns how
+// the point ended.
+pub fn outer_simulation<T: Weight, R: Rng>(canvas: &mut Canvas<T>,
+                                           mut escapes: Option<&mut Canvas<T>>,
+                                           scene: &mut Scene,
+                                           table: &[Coord],
+                                           rng: &mut R,
+                                           canvas_shader: &dyn Shader<T>,
+                                           resample_starts: bool) -> Option<Trajectory>
+{
+    let attempts = if resample_starts { MAX_START_ATTEMPTS } else { 1 };
+    let start_pos = (0..attempts)
+        .map(|_| coord! {x: rng.gen_range(0.0 .. 1.0), y: rng.gen_range(0.0 .. 1.0)})
+        .find(|p| !on_table(table, *p))?;
+
+    let mut pos = start_pos;
+    let mut path_length: f64 = 0.0;
+    let mut no_bounces: usize = 0;
+    let mut winding: f64 = 0.0;
+    let mut crossings = scene.count_crossings.then(Crossings::default);
+    let mut bounce_points = scene.record_bounces.then(|| vec![start_pos]);
+
+    let ending = loop {
+        let jump = Line::new(pos, tangent_vertex(table, pos) * 2.0 - pos);
+        let end_dir = jump.delta() / jump.delta().magnitude();
+        // How often the point crossed its path, with the jump up to `end`
+        let crossed = |end: Coord| crossings.clone().map_or(0, |mut crossings| {
+            crossings.fly(Line::new(jump.start, end));
+            crossings.count
+        });
+        // The last jump ends where this one starts
+        let previous = scene.trail.blocking().len().saturating_sub(1);
+        let hit = scene.trail.numbered().take(previous)
+            .filter_map(|(n, line)| segment_hit(*line, jump).map(|hit| (n, hit)))
+            .min_by(|a, b| a.1.distance.total_cmp(&b.1.distance));
+
+        // A point that would jump past the maximum path length stops on the way
+        let reach = hit.as_ref().map_or(jump.delta().magnitude(), |(_, hit)| hit.distance);
+        if let Some(max) = scene.max_path_length.filter(|max| path_length + reach > *max) {
+            let end_pos = jump.start + jump.delta() * ((max - path_length) / jump.delta().magnitude());
+            let trajectory = Trajectory { start_pos, end_pos, end_dir, path_length: max, travel_time: max, no_bounces, energy: 1.0,
+                                          termination: Termination::PathLimit, bounce_points: bounce_path(&bounce_points, end_pos),
+                                          winding: winding + swept(scene.center, jump.start, end_pos), crossings: crossed(end_pos),
+                                          displacement: (end_pos - start_pos).magnitude(), contact: None, exit: None };
+            deposit_weight(canvas, scene, &trajectory, end_pos, canvas_shader.shade(&trajectory));
+            break trajectory;
+        }
+
+        if let Some((n, hit)) = hit {
+            let trajectory = Trajectory { start_pos, end_pos: hit.point, end_dir, path_length: path_length + hit.distance,
+                                          travel_time: path_length + hit.distance, no_bounces,
+                                          energy: 1.0, termination: Termination::Trapped(Trap::SelfHit),
+                                          bounce_points: bounce_path(&bounce_points, hit.point),
+                                          winding: winding + swept(scene.center, jump.start, hit.point), crossings: crossed(hit.point),
+                                          displacement: (hit.point - start_pos).magnitude(), contact: Some(Contact::Trail(n)),
+                                          exit: None };
+            deposit_weight(canvas, scene, &trajectory, hit.point, canvas_shader.shade(&trajectory));
+            break trajectory;
+        }
+
+        path_length += jump.delta().magnitude();
+        winding += swept(scene.center, jump.start, jump.end);
+        if let Some(crossings) = crossings.as_mut() {
+            crossings.fly(jump);
+        }
+        no_bounces += 1;
+        pos = jump.end;
+
+        let trajectory = |termination: Termination| Trajectory { start_pos, end_pos: pos, end_dir, path_length, travel_time: path_length,
+                                                                 no_bounces, energy: 1.0, termination,
+                                                                 bounce_points: bounce_path(&bounce_points, pos), winding,
+                                                                 crossings: crossings.as_ref().map_or(0, |crossings| crossings.count),
+                                                                 displacement: (pos - start_pos).magnitude(), contact: None, exit: None };
+        if !(0.0..1.0).contains(&pos.x) || !(0.0..1.0).contains(&pos.y) {
+            let trajectory = trajectory(Termination::Escaped);
+            if let Some(escapes) = escapes.as_mut() {
+                deposit_weight(escapes, scene, &trajectory, pos, canvas_shader.shade(&trajectory));
+            }
+            break trajectory;
+        }
+        let limit = if scene.is_full() {
+            Some(Termination::Trapped(Trap::TrailFull))
+        } else if scene.return_radius.is_some_and(|radius| (pos - start_pos).magnitude() < radius) {
+            Some(Termination::Returned)
+        } else {
+            scene.max_bounces.filter(|max| no_bounces >= *max).map(|_| Termination::BounceLimit)
+        };
+        if let Some(termination) = limit {
+            let trajectory = trajectory(termination);
+            deposit_weight(canvas, scene, &trajectory, pos, canvas_shader.shade(&trajectory));
+            break trajectory;
+        }
+        if let Some(points) = bounce_points.as_mut() {
+            points.push(pos);
+        }
+        scene.trail.push(jump);
+        scene.trail.bounce();
+    };
+
+    // Leave the scene in state that we started with
+    scene.trail.clear();
+    Some(ending)
+}
+
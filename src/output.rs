@@ -0,0 +1,192 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::iter::zip;
+use std::ops::{Index, Mul};
+use std::path::Path;
+
+use cgmath::num_traits::{clamp, Zero};
+use cgmath::{Vector2, Vector3};
+use simple_canvas::Canvas;
+use tiff::encoder::colortype::{self, ColorType};
+use tiff::tags::{PhotometricInterpretation, SampleFormat, Tag};
+
+use crate::config::{PhaseImage, RunConfig, ToneMap};
+use crate::shaders::hue;
+use crate::simulation::{Bins, Reduced};
+
+
+// Make a new canvas, normalized and scaled to u32::MAX
+pub fn normalize(canvas: &Canvas<f64>, tone_map: ToneMap) -> Canvas<u32>
+{
+    let mut normalized_canvas: Canvas<u32> = Canvas::new(canvas.width, canvas.height, 0);
+    let src_max = canvas.iter().max_by(|a, b| a.partial_cmp(b).unwrap()).unwrap();
+    for (src, target) in zip(canvas.iter(), normalized_canvas.iter_mut()) {
+        let relative = match tone_map {
+            ToneMap::Log => src.log10() / src_max.log10(),
+            ToneMap::Linear => src / src_max,
+            ToneMap::Sqrt => src.sqrt() / src_max.sqrt(),
+        };
+        *target = clamp((u32::MAX as f64 * relative) as u32, 0, u32::MAX);
+    }
+
+    normalized_canvas
+}
+
+
+// One channel of a color canvas, 0, 1 and 2 are red, green and blue, or one layer of
+// a layered canvas
+pub fn channel<V: Index<usize, Output = f64>>(canvas: &Canvas<V>, i: usize) -> Canvas<f64>
+{
+    Canvas { data: canvas.data.iter().map(|v| v[i]).collect(), width: canvas.width, height: canvas.height }
+}
+
+
+// The sum of the channels of a color canvas, the weight of all balls in every pixel
+pub fn channel_sum(canvas: &Canvas<Vector3<f64>>) -> Canvas<f64>
+{
+    Canvas { data: canvas.iter().map(|v| v.x + v.y + v.z).collect(), width: canvas.width, height: canvas.height }
+}
+
+
+// What the image of a phase run shows of the complex sum in every pixel
+pub fn phase_image(canvas: &Canvas<Vector2<f64>>, image: PhaseImage) -> Canvas<f64>
+{
+    let value = |z: &Vector2<f64>| match image {
+        PhaseImage::Magnitude => z.x.hypot(z.y),
+        PhaseImage::Intensity => z.x * z.x + z.y * z.y,
+        PhaseImage::Phase if z.x == 0.0 && z.y == 0.0 => 0.0,
+        PhaseImage::Phase => z.y.atan2(z.x) / (PI * 2.0) + 0.5,
+    };
+    Canvas { data: canvas.data.iter().map(value).collect(), width: canvas.width, height: canvas.height }
+}
+
+
+// The colors of the bins of every pixel, every bin adds its weight in its hue
+pub fn bin_colors(canvas: &Canvas<Bins>, hues: &[f64]) -> Canvas<Vector3<f64>>
+{
+    let hues: Vec<Vector3<f64>> = hues.iter().map(|h| hue(*h)).collect();
+    let color = |v: &Bins| zip(&hues, v.0).map(|(hue, weight)| hue * weight).sum();
+    Canvas { data: canvas.data.iter().map(color).collect(), width: canvas.width, height: canvas.height }
+}
+
+
+// The hues of the bins of a run, in turns: the ones it asks for, or spread evenly. Those
+// of direction bins are the hues of the directions in their middles.
+pub fn bin_hues(config: &RunConfig) -> Vec<f64>
+{
+    if !config.output.bin_hues.is_empty() {
+        return config.output.bin_hues.clone();
+    }
+    let bins = config.bins().unwrap_or(0);
+    (0..bins).map(|k| (k as f64 + 0.5) / bins as f64).collect()
+}
+
+
+// The sum of the bins of every pixel, the weight of all balls in it
+pub fn bin_sum(canvas: &Canvas<Bins>) -> Canvas<f64>
+{
+    Canvas { data: canvas.data.iter().map(|v| v.0.iter().sum()).collect(), width: canvas.width, height: canvas.height }
+}
+
+
+// The combined weights of every pixel, of a run that does not sum them
+pub fn reduced_values(canvas: &Canvas<Reduced>) -> Canvas<f64>
+{
+    Canvas { data: canvas.data.iter().map(Reduced::result).collect(), width: canvas.width, height: canvas.height }
+}
+
+
+// Every pixel divided by the number of balls that ended in it, their mean weight, 0
+// where none did
+pub fn per_hit<T: Mul<f64, Output = T> + Zero + Copy>(canvas: &Canvas<T>, hits: &Canvas<f64>) -> Canvas<T>
+{
+    let data = zip(&canvas.data, &hits.data).map(|(v, n)| if *n > 0.0 { *v * (1.0 / n) } else { T::zero() }).collect();
+    Canvas { data, width: canvas.width, height: canvas.height }
+}
+
+
+// Every channel of a color canvas normalized on its own
+pub fn normalize_colors(canvas: &Canvas<Vector3<f64>>, tone_map: ToneMap) -> [Canvas<u32>; 3]
+{
+    [0, 1, 2].map(|i| normalize(&channel(canvas, i), tone_map))
+}
+
+
+// Write a 32bit grayscale tiff
+pub fn write_tiff(path: &Path, canvas: &Canvas<u32>)
+{
+    let f = File::create(path).unwrap();
+    let mut encoder = tiff::encoder::TiffEncoder::new(f).unwrap();
+    encoder.write_image::<colortype::Gray32>(canvas.width as u32,
+                                             canvas.height as u32,
+                                             &canvas.data).unwrap();
+}
+
+
+// Two 32bit floats in every pixel, the x and y of a vector. The tiff crate has no color
+// type of two samples.
+struct Vector32Float;
+
+impl ColorType for Vector32Float {
+    type Inner = f32;
+    const TIFF_VALUE: PhotometricInterpretation = PhotometricInterpretation::BlackIsZero;
+    const BITS_PER_SAMPLE: &'static [u16] = &[32, 32];
+    const SAMPLE_FORMAT: &'static [SampleFormat] = &[SampleFormat::IEEEFP; 2];
+}
+
+
+// Write a vector canvas as a 32bit float tiff of two samples in every pixel, x and y, as
+// they are, for tools that draw streamlines or arrows
+pub fn write_vector_tiff(path: &Path, canvas: &Canvas<Vector2<f64>>) -> Result<(), String>
+{
+    let write_error = |e: &dyn std::fmt::Display| format!("Could not write image {}: {}", path.display(), e);
+    let f = File::create(path).map_err(|e| write_error(&e))?;
+    let mut encoder = tiff::encoder::TiffEncoder::new(f).map_err(|e| write_error(&e))?;
+    let mut image = encoder.new_image::<Vector32Float>(canvas.width as u32, canvas.height as u32)
+        .map_err(|e| write_error(&e))?;
+    // The second sample is not alpha, 0 is an extra sample of no given meaning
+    image.encoder().write_tag(Tag::ExtraSamples, 0u16).map_err(|e| write_error(&e))?;
+    let values: Vec<f32> = canvas.iter().flat_map(|v| [v.x as f32, v.y as f32]).collect();
+    image.write_data(&values).map_err(|e| write_error(&e))
+}
+
+
+// Write an 8 or 16 bit grayscale png, for tools that do not read 32bit tiffs
+pub fn write_png(path: &Path, canvas: &Canvas<u32>, bits: u8) -> Result<(), String>
+{
+    let (width, height) = (canvas.width as u32, canvas.height as u32);
+    let saved = match bits {
+        8 => image::GrayImage::from_raw(width, height, canvas.iter().map(|v| (v >> 24) as u8).collect())
+            .map(|image| image.save(path)),
+        _ => image::ImageBuffer::<image::Luma<u16>, Vec<u16>>::from_raw(width, height, canvas.iter().map(|v| (v >> 16) as u16).collect())
+            .map(|image| image.save(path)),
+    };
+    saved.ok_or("canvas does not match its size")?.map_err(|e| format!("Could not write image {}: {}", path.display(), e))
+}
+
+
+// Write a 32bit color tiff of the normalized channels
+pub fn write_color_tiff(path: &Path, channels: &[Canvas<u32>; 3])
+{
+    let pixels: Vec<u32> = (0..channels[0].data.len()).flat_map(|i| channels.iter().map(move |c| c.data[i])).collect();
+    let f = File::create(path).unwrap();
+    let mut encoder = tiff::encoder::TiffEncoder::new(f).unwrap();
+    encoder.write_image::<colortype::RGB32>(channels[0].width as u32,
+                                            channels[0].height as u32,
+                                            &pixels).unwrap();
+}
+
+
+// Write an 8 or 16 bit color png of the normalized channels
+pub fn write_color_png(path: &Path, channels: &[Canvas<u32>; 3], bits: u8) -> Result<(), String>
+{
+    let (width, height) = (channels[0].width as u32, channels[0].height as u32);
+    let values = (0..channels[0].data.len()).flat_map(|i| channels.iter().map(move |c| c.data[i]));
+    let saved = match bits {
+        8 => image::RgbImage::from_raw(width, height, values.map(|v| (v >> 24) as u8).collect())
+            .map(|image| image.save(path)),
+        _ => image::ImageBuffer::<image::Rgb<u16>, Vec<u16>>::from_raw(width, height, values.map(|v| (v >> 16) as u16).collect())
+            .map(|image| image.save(path)),
+    };
+    saved.ok_or("canvas does not match its size")?.map_err(|e| format!("Could not write image {}: {}", path.display(), e))
+}
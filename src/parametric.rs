@@ -0,0 +1,102 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+
+// Closed curves the outline of a parametric arena is sampled from. Written in a
+// config file as, for example, curve = { rose = { petals = 5, amplitude = 0.3 } }
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Curve {
+    // |x|^exponent + |y / aspect_ratio|^exponent = 1, 2 is an ellipse, larger exponents get squarer
+    Superellipse { exponent: f64, aspect_ratio: f64 },
+    // radius 1 + amplitude * cos(petals * angle)
+    Rose { petals: usize, amplitude: f64 },
+    // radius 1 + the sum of a_k * cos(k * angle) + b_k * sin(k * angle), for the
+    // coefficients [a_k, b_k] of k = 1, 2, ...
+    Fourier { coefficients: Vec<[f64; 2]> },
+}
+
+
+impl Curve {
+    // The point at parameter t, from 0 to 1 for the whole curve
+    fn point(&self, t: f64) -> [f64; 2]
+    {
+        let angle = 2.0 * PI * t;
+        let (sin, cos) = angle.sin_cos();
+
+        match self {
+            Curve::Superellipse { exponent, aspect_ratio } => {
+                let power = |v: f64| v.signum() * v.abs().powf(2.0 / exponent);
+                [power(cos), aspect_ratio * power(sin)]
+            }
+            Curve::Rose { petals, amplitude } => {
+                let r = 1.0 + amplitude * (*petals as f64 * angle).cos();
+                [r * cos, r * sin]
+            }
+            Curve::Fourier { coefficients } => {
+                let r = fourier_radius(coefficients, angle);
+                [r * cos, r * sin]
+            }
+        }
+    }
+
+    pub fn validate(&self, resolution: usize) -> Result<(), String>
+    {
+        match self {
+            Curve::Superellipse { exponent, aspect_ratio } => {
+                if !(*exponent > 0.0 && exponent.is_finite()) {
+                    return Err(format!("superellipse exponent must be larger than 0, got {}", exponent));
+                }
+                if !(*aspect_ratio > 0.0 && aspect_ratio.is_finite()) {
+                    return Err(format!("superellipse aspect ratio must be larger than 0, got {}", aspect_ratio));
+                }
+            }
+            Curve::Rose { petals, amplitude } => {
+                if *petals == 0 {
+                    return Err("a rose needs at least 1 petal".to_string());
+                }
+                if !(0.0..1.0).contains(amplitude) {
+                    return Err(format!("rose amplitude must be in [0, 1), got {}", amplitude));
+                }
+            }
+            Curve::Fourier { coefficients } => {
+                if !coefficients.iter().flatten().all(|c| c.is_finite()) {
+                    return Err("fourier coefficients must be finite".to_string());
+                }
+                // The samples are all that become walls
+                if (0..resolution).any(|i| fourier_radius(coefficients, 2.0 * PI * i as f64 / resolution as f64) <= 0.0) {
+                    return Err("the radius of the fourier curve has to stay larger than 0".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String
+    {
+        match self {
+            Curve::Superellipse { exponent, aspect_ratio } => format!("superellipse, exponent {}, aspect ratio {}", exponent, aspect_ratio),
+            Curve::Rose { petals, amplitude } => format!("rose, {} petals, amplitude {}", petals, amplitude),
+            Curve::Fourier { coefficients } => format!("fourier curve, {} coefficients", coefficients.len()),
+        }
+    }
+}
+
+
+fn fourier_radius(coefficients: &[[f64; 2]], angle: f64) -> f64
+{
+    let terms = coefficients.iter().enumerate().map(|(i, [a, b])| {
+        let k = (i + 1) as f64;
+        a * (k * angle).cos() + b * (k * angle).sin()
+    });
+    1.0 + terms.sum::<f64>()
+}
+
+
+// `resolution` points along the curve, for the vertices of the outline. They are at
+// equal steps of the parameter, not of the length of the curve.
+pub fn curve_outline(curve: &Curve, resolution: usize) -> Vec<[f64; 2]>
+{
+    (0..resolution).map(|i| curve.point(i as f64 / resolution as f64)).collect()
+}
@@ -0,0 +1,85 @@
+use crate::config::{RunConfig, ToneMap};
+
+
+// A curated set of run parameters, applied on top of the defaults. Config files
+// and command line arguments can still override everything a preset sets.
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    apply: fn(&mut RunConfig),
+}
+
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "pentagon-classic",
+        description: "The pentagon from the paper, path length weighted, log tone mapped",
+        apply: |config| {
+            config.width = 1024;
+            config.height = 1024;
+            config.sims = 50_000_000;
+            config.shader = "path-length".to_string();
+            config.arena.edges = 5;
+            config.output.tone_map = ToneMap::Log;
+        },
+    },
+    Preset {
+        name: "dense-hexagon",
+        description: "Plain trap density of a hexagon at high resolution",
+        apply: |config| {
+            config.width = 2048;
+            config.height = 2048;
+            config.sims = 200_000_000;
+            config.shader = "hit-count".to_string();
+            config.arena.edges = 6;
+            config.output.tone_map = ToneMap::Sqrt;
+        },
+    },
+    Preset {
+        name: "triangle-bounces",
+        description: "Triangle weighted by the number of bounces before getting trapped",
+        apply: |config| {
+            config.width = 1024;
+            config.height = 1024;
+            config.sims = 50_000_000;
+            config.shader = "bounce-count".to_string();
+            config.arena.edges = 3;
+            config.output.tone_map = ToneMap::Log;
+        },
+    },
+    Preset {
+        name: "quick-preview",
+        description: "Small and fast render to get a first impression",
+        apply: |config| {
+            config.width = 256;
+            config.height = 256;
+            config.sims = 1_000_000;
+            config.shader = "path-length".to_string();
+            config.output.tone_map = ToneMap::Log;
+        },
+    },
+];
+
+
+impl Preset {
+    pub fn config(&self) -> RunConfig
+    {
+        let mut config = RunConfig::default();
+        self.apply_to(&mut config);
+        config
+    }
+
+    pub fn apply_to(&self, config: &mut RunConfig)
+    {
+        (self.apply)(config);
+    }
+}
+
+
+pub fn preset_by_name(name: &str) -> Result<&'static Preset, String>
+{
+    PRESETS.iter().find(|p| p.name == name).ok_or_else(|| {
+        let names: Vec<&str> = PRESETS.iter().map(|p| p.name).collect();
+        format!("unknown preset '{}', available presets are: {}", name, names.join(", "))
+    })
+}
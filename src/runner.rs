@@ -0,0 +1,739 @@
+use std::iter::zip;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time;
+use std::time::Duration;
+
+use cgmath::num_traits::Zero;
+use cgmath::{Vector2, Vector3, Vector4};
+use geo::coord;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use simple_canvas::Canvas;
+
+use crate::config::{ArenaConfig, Arrival, CornerRule, Deposit, DepositAt, Dynamics, Material, OnCycle, Reduction, RunConfig, SphereProjection, TrailFull, TrailSides};
+use crate::arena::{arena_seed, initial_arena};
+use crate::flight::{Flight, Potential, Wind};
+use crate::geodesic::{chart_scene, geodesic_simulation, geometry};
+use crate::multi::multi_simulation;
+use crate::outer::{outer_simulation, table};
+use crate::output::{bin_colors, bin_hues, bin_sum, channel, channel_sum, phase_image, reduced_values};
+use crate::solid::{Solid, SolidConfig, solid_simulation};
+use crate::shaders::{build_binned_shader, build_color_shader, build_layered_shader, build_phase_shader, build_reduced_shader, build_shader, Shader};
+use crate::simulation::{add_direction, add_exit, Bins, count_hit, first_arrival, Reduced, single_simulation, Tally, Trajectory, Tumble, Weight};
+use crate::symmetry::arena_symmetry;
+use crate::runner::FromThreadMsg::Report;
+use crate::runner::ToThreadMsg::{Accumulate, Start, Stop, Work};
+
+
+// Everything a worker thread needs to know to contribute to a run
+struct Job {
+    arena: ArenaConfig,
+    dynamics: Dynamics,
+    sphere_projection: SphereProjection,
+    table_scale: f64,
+    solid: SolidConfig,
+    shader: Arc<dyn Shader<f64>>,
+    color: Option<Box<dyn Shader<Vector3<f64>>>>,   // it weighs the balls instead, when the run is in color
+    layers: Option<Box<dyn Shader<Vector4<f64>>>>,  // or together with the extra shaders, when it has them
+    phase: Option<Box<dyn Shader<Vector2<f64>>>>,   // or as complex numbers, of phase runs
+    bins: Option<Box<dyn Shader<Bins>>>,            // or in the bins of their directions or bounce counts
+    reduced: Option<Box<dyn Shader<Reduced>>>,      // or to be combined other than by summing, when the run does
+    extra_shaders: Vec<String>,
+    first_arrival: Option<Arrival>,
+    deposit: Deposit,
+    deposit_at: DepositAt,
+    bounce_exponent: f64,
+    seed: u64,
+    resample_starts: bool,
+    balls: usize,
+    branching: f64,
+    max_branches: usize,
+    trail_length: Option<usize>,
+    trail_full: TrailFull,
+    max_bounces: Option<usize>,
+    max_path_length: Option<f64>,
+    return_radius: Option<f64>,
+    trail_memory: Option<usize>,
+    trail_window: Option<usize>,
+    trail_material: Material,
+    trail_sides: TrailSides,
+    corner_rule: CornerRule,
+    corner_tolerance: f64,
+    reflection_offset: f64,
+    robust_predicates: bool,
+    on_cycle: Option<OnCycle>,
+    cycle_tolerance: f64,
+    cycle_window: usize,
+    tiles: [usize; 2],
+    fold_symmetry: bool,
+    reflection_jitter: f64,
+    tumble: Option<Tumble>,
+    gravity: [f64; 2],
+    larmor_radius: Option<f64>,
+    wind: Option<Wind>,
+    potential: Option<Potential>,
+    frame_rotation: f64,
+    frame_center: [f64; 2],
+    restitution: f64,
+    trail_restitution: Option<f64>,
+    min_energy: f64,
+    deceleration: f64,
+    bounce_speedup: f64,
+    ball_radius: f64,
+    trail_width: f64,
+    flight_step: f64,
+    result_canvas: Mutex<Canvas<f64>>,
+    escape_canvas: Option<Mutex<Canvas<f64>>>,  // where balls escaped through openings, when asked for
+    color_canvas: Option<Mutex<Canvas<Vector3<f64>>>>,  // of color runs, they leave the other canvases empty
+    layer_canvas: Option<Mutex<Canvas<Vector4<f64>>>>,  // of runs with extra shaders, a layer for every shader
+    layer_escapes: Option<Mutex<Canvas<Vector4<f64>>>>,
+    phase_canvas: Option<Mutex<Canvas<Vector2<f64>>>>,  // of phase runs, the complex sum of the weights
+    bin_canvas: Option<Mutex<Canvas<Bins>>>,            // of direction and band runs
+    reduced_canvas: Option<Mutex<Canvas<Reduced>>>,     // of runs that do not sum the weights in a pixel
+    reduced_escapes: Option<Mutex<Canvas<Reduced>>>,
+    arrival_canvas: Option<Mutex<Canvas<f64>>>,         // the first arrival in every pixel, infinite before any
+    hit_canvas: Option<Mutex<Canvas<f64>>>,             // how many balls ended in every pixel
+    vector_canvas: Option<Mutex<Canvas<Vector2<f64>>>>, // the sum of the directions they ended in
+    exit_canvas: Option<Mutex<Canvas<f64>>>,            // how many escaped where across the openings, in which direction
+    tally: Mutex<Tally>,
+}
+
+
+// The accumulated canvases of a run, the number of simulations that went into them,
+// and how those ended. Of a color run, the canvas is the sum of its channels, of a
+// phase run what its phase image shows of the complex sums, and of a direction run the
+// sum of its bins, its colors are those of the directions then.
+pub struct RunResult {
+    pub canvas: Canvas<f64>,
+    pub escapes: Option<Canvas<f64>>,
+    pub colors: Option<Canvas<Vector3<f64>>>,
+    pub phases: Option<Canvas<Vector2<f64>>>,
+    pub bins: Option<Canvas<Bins>>,
+    pub arrivals: Option<Canvas<f64>>,  // the first arrival in every pixel, 0 where no ball ended
+    pub hits: Option<Canvas<f64>>,      // how many balls ended in every pixel
+    pub vectors: Option<Canvas<Vector2<f64>>>,  // the sum of the directions they ended in
+    pub exits: Option<Canvas<f64>>,     // how many escaped where across the openings, in which direction
+    pub extras: Vec<ExtraCanvas>,   // of the extra shaders
+    pub simulations: usize,
+    pub tally: Tally,
+}
+
+
+// The canvases of one of the extra shaders of a run
+pub struct ExtraCanvas {
+    pub shader: String,
+    pub canvas: Canvas<f64>,
+    pub escapes: Option<Canvas<f64>>,
+}
+
+
+enum ToThreadMsg {
+    Start(Arc<Job>, u64),              // job, and the index of the thread within the job
+    Work(usize),                       // do this many more simulations for the current job
+    Accumulate,
+    Stop
+}
+
+
+enum FromThreadMsg {
+    Report(usize)
+}
+
+
+// Worker threads live as long as the pool, and wait for a job in between runs
+fn worker_thread(rx: mpsc::Receiver<ToThreadMsg>, tx: mpsc::Sender<FromThreadMsg>)
+{
+    loop {
+        match rx.recv() {
+            Ok(Start(job, thread_idx)) => match (&job.color, &job.color_canvas, &job.layers, &job.layer_canvas) {
+                (Some(color), Some(canvas), _, _) => sim_job(&rx, &tx, &job, thread_idx, &**color, canvas, None),
+                (_, _, Some(layers), Some(canvas)) => {
+                    sim_job(&rx, &tx, &job, thread_idx, &**layers, canvas, job.layer_escapes.as_ref())
+                }
+                _ => match (&job.phase, &job.phase_canvas, &job.bins, &job.bin_canvas) {
+                    (Some(phase), Some(canvas), _, _) => sim_job(&rx, &tx, &job, thread_idx, &**phase, canvas, None),
+                    (_, _, Some(bins), Some(canvas)) => sim_job(&rx, &tx, &job, thread_idx, &**bins, canvas, None),
+                    _ => match (&job.reduced, &job.reduced_canvas) {
+                        (Some(reduced), Some(canvas)) => {
+                            sim_job(&rx, &tx, &job, thread_idx, &**reduced, canvas, job.reduced_escapes.as_ref())
+                        }
+                        _ => sim_job(&rx, &tx, &job, thread_idx, &*job.shader, &job.result_canvas, job.escape_canvas.as_ref()),
+                    },
+                },
+            },
+            Ok(_) => {}
+            Err(_) => return    // pool is dropped
+        }
+    }
+}
+
+
+// Simulate the work quotas the main thread hands out, until it says stop. The balls
+// are weighed with `shader`, and accumulated in `result_canvas` and `escape_canvas`.
+fn sim_job<T: Weight + Zero>(rx: &mpsc::Receiver<ToThreadMsg>,
+                             tx: &mpsc::Sender<FromThreadMsg>,
+                             job: &Job,
+                             thread_idx: u64,
+                             shader: &dyn Shader<T>,
+                             result_canvas: &Mutex<Canvas<T>>,
+                             escape_canvas: Option<&Mutex<Canvas<T>>>)
+{
+    const THREAD_REPORT_INTERVAL: Duration = Duration::from_millis(50);
+    const SIM_BATCH_SIZE: usize = 100;
+
+    let width = result_canvas.lock().unwrap().width;
+    let height = result_canvas.lock().unwrap().height;
+
+    // Every tile has its own canvas, the simulations go round the tiles
+    let [columns, rows] = job.tiles;
+    let new_tiles = || vec![Canvas::new(width / columns, height / rows, T::zero()); columns * rows];
+    let mut thread_canvases: Vec<Canvas<T>> = new_tiles();
+    let mut thread_escapes: Option<Vec<Canvas<T>>> = escape_canvas.map(|_| new_tiles());
+    let mut thread_arrivals: Option<Vec<Canvas<f64>>> = job.arrival_canvas.as_ref()
+        .map(|_| vec![Canvas::new(width / columns, height / rows, f64::INFINITY); columns * rows]);
+    let mut thread_hits: Option<Vec<Canvas<f64>>> = job.hit_canvas.as_ref()
+        .map(|_| vec![Canvas::new(width / columns, height / rows, 0.0); columns * rows]);
+    let mut thread_vectors: Option<Vec<Canvas<Vector2<f64>>>> = job.vector_canvas.as_ref()
+        .map(|_| vec![Canvas::new(width / columns, height / rows, Vector2::zero()); columns * rows]);
+    let mut thread_exits: Option<Vec<Canvas<f64>>> = job.exit_canvas.as_ref()
+        .map(|_| vec![Canvas::new(width / columns, height / rows, 0.0); columns * rows]);
+    let mut tile = 0;
+    let mut tally = Tally::default();
+    let mut scene = initial_arena(&job.arena, arena_seed(&job.arena, job.seed, thread_idx));
+    scene.center = coord! {x: job.arena.center[0], y: job.arena.center[1]};
+    let geometry = geometry(job.dynamics, job.sphere_projection);
+    if let Some(geometry) = &geometry {
+        scene = chart_scene(&scene, geometry.as_ref());
+    }
+    if let Some(trail_length) = job.trail_length {
+        scene.max_trail = trail_length;
+    }
+    if job.fold_symmetry {
+        scene.fold = arena_symmetry(&job.arena).ok();
+    }
+    scene.jitter = job.reflection_jitter.to_radians();
+    scene.tumble = job.tumble.clone();
+    scene.flight = Flight::new(job.gravity, job.larmor_radius, job.wind.clone(), job.potential.clone(), job.frame_rotation, job.frame_center,
+                               job.flight_step, &scene);
+    scene.restitution = job.restitution;
+    scene.trail_restitution = job.trail_restitution;
+    scene.min_energy = job.min_energy;
+    scene.deceleration = job.deceleration;
+    scene.bounce_speedup = job.bounce_speedup;
+    scene.ball_radius = job.ball_radius;
+    scene.trail_width = job.trail_width;
+    scene.trail_full = job.trail_full;
+    if job.trail_full == TrailFull::Forget {
+        scene.trail.limit = Some(scene.max_trail);
+    }
+    scene.max_bounces = job.max_bounces;
+    scene.max_path_length = job.max_path_length;
+    scene.return_radius = job.return_radius;
+    scene.branching = job.branching;
+    scene.max_branches = job.max_branches;
+    scene.trail.memory = job.trail_memory;
+    scene.trail.window = job.trail_window;
+    scene.trail_material = job.trail_material;
+    scene.trail_sides = job.trail_sides;
+    scene.corner_rule = job.corner_rule;
+    scene.corner_tolerance = job.corner_tolerance;
+    scene.find_corners();
+    scene.reflection_offset = job.reflection_offset;
+    scene.robust = job.robust_predicates;
+    scene.on_cycle = job.on_cycle;
+    scene.cycle_tolerance = job.cycle_tolerance;
+    scene.cycle_window = job.cycle_window;
+    scene.record_bounces = shader.uses_bounce_points();
+    scene.count_crossings = shader.uses_crossings();
+    scene.deposit = job.deposit;
+    scene.deposit_at = job.deposit_at;
+    scene.bounce_exponent = job.bounce_exponent;
+    scene.record_bounces |= job.deposit == Deposit::Bounces;
+    let outer_table = (job.dynamics == Dynamics::Outer).then(|| table(&scene, job.table_scale));
+    let mut solid = (job.dynamics == Dynamics::Solid).then(|| Solid::new(&job.solid));
+    let mut rng = StdRng::seed_from_u64(job.seed.wrapping_add(thread_idx));
+    let mut last_report_t = time::Instant::now();
+
+    loop {
+        match rx.recv() {
+            Ok(Work(quota)) => {
+                let mut no_simulations_to_report = 0;
+                let mut remaining = quota;
+
+                while remaining > 0 {
+                    let batch = remaining.min(SIM_BATCH_SIZE);
+                    for _ in 0..batch {
+                        let escapes = thread_escapes.as_mut().map(|e| &mut e[tile]);
+                        let canvas = &mut thread_canvases[tile];
+                        let ended: Vec<Trajectory> = match &outer_table {
+                            Some(table) => outer_simulation(canvas, escapes, &mut scene, table,
+                                                            &mut rng, shader, job.resample_starts).into_iter().collect(),
+                            None if solid.is_some() => {
+                                solid_simulation(canvas, solid.as_mut().unwrap(), &scene, &mut rng, shader).into_iter().collect()
+                            }
+                            None if geometry.is_some() => {
+                                geodesic_simulation(canvas, &mut scene, geometry.as_deref().unwrap(), &mut rng,
+                                                    shader, job.resample_starts).into_iter().collect()
+                            }
+                            None if job.balls > 1 || job.branching > 0.0 => multi_simulation(canvas, escapes, &mut scene, &mut rng,
+                                                                                              shader, job.resample_starts, job.balls),
+                            None => single_simulation(canvas, escapes,
+                                                      &mut scene, &mut rng, shader, job.resample_starts).into_iter().collect(),
+                        };
+                        if let (Some(arrivals), Some(arrival)) = (thread_arrivals.as_mut(), job.first_arrival) {
+                            for trajectory in &ended {
+                                first_arrival(&mut arrivals[tile], &scene, trajectory, arrival);
+                            }
+                        }
+                        if let Some(hits) = thread_hits.as_mut() {
+                            for trajectory in &ended {
+                                count_hit(&mut hits[tile], &scene, trajectory);
+                            }
+                        }
+                        if let Some(vectors) = thread_vectors.as_mut() {
+                            for trajectory in &ended {
+                                add_direction(&mut vectors[tile], &scene, trajectory);
+                            }
+                        }
+                        if let Some(exits) = thread_exits.as_mut() {
+                            for trajectory in &ended {
+                                add_exit(&mut exits[tile], &scene, trajectory);
+                            }
+                        }
+                        tally.extend(ended);
+                        tile = (tile + 1) % thread_canvases.len();
+                    }
+                    remaining -= batch;
+                    no_simulations_to_report += batch;
+
+                    let now = time::Instant::now();
+                    if (now - last_report_t) > THREAD_REPORT_INTERVAL {
+                        last_report_t = now;
+                        tx.send(Report(no_simulations_to_report)).unwrap();
+                        no_simulations_to_report = 0;
+                    }
+                }
+
+                tx.send(Report(no_simulations_to_report)).unwrap();
+            }
+            Ok(Accumulate) => {
+                combine_tiles(&thread_canvases, columns, &mut result_canvas.lock().unwrap(), |sum, v| *sum += v);
+                if let (Some(escapes), Some(thread_escapes)) = (escape_canvas, &thread_escapes) {
+                    combine_tiles(thread_escapes, columns, &mut escapes.lock().unwrap(), |sum, v| *sum += v);
+                }
+                if let (Some(arrivals), Some(thread_arrivals)) = (&job.arrival_canvas, &thread_arrivals) {
+                    combine_tiles(thread_arrivals, columns, &mut arrivals.lock().unwrap(), |first, v| *first = first.min(v));
+                }
+                if let (Some(hits), Some(thread_hits)) = (&job.hit_canvas, &thread_hits) {
+                    combine_tiles(thread_hits, columns, &mut hits.lock().unwrap(), |sum, v| *sum += v);
+                }
+                if let (Some(vectors), Some(thread_vectors)) = (&job.vector_canvas, &thread_vectors) {
+                    combine_tiles(thread_vectors, columns, &mut vectors.lock().unwrap(), |sum, v| *sum += v);
+                }
+                if let (Some(exits), Some(thread_exits)) = (&job.exit_canvas, &thread_exits) {
+                    combine_tiles(thread_exits, columns, &mut exits.lock().unwrap(), |sum, v| *sum += v);
+                }
+                job.tally.lock().unwrap().add(&tally);
+            }
+            Ok(Stop) => {
+                return
+            }
+            Ok(Start(..)) => {
+                panic!("worker got a new job while still busy");
+            }
+            Err(_) => {
+                panic!();
+            }
+        }
+    }
+}
+
+
+// Combine every tile with its place in `canvas`, the tiles are in rows of `columns`.
+// Most canvases add them up, the first arrivals keep the smallest.
+fn combine_tiles<T: Copy>(tiles: &[Canvas<T>], columns: usize, canvas: &mut Canvas<T>, combine: fn(&mut T, T))
+{
+    for (i, tile) in tiles.iter().enumerate() {
+        let (left, top) = ((i % columns) * tile.width, (i / columns) * tile.height);
+        for y in 0..tile.height {
+            let row_out = &mut canvas.data[left + (top + y) * canvas.width..][..tile.width];
+            for (p_in, p_out) in zip(&tile.data[y * tile.width..][..tile.width], row_out) {
+                combine(p_out, *p_in);
+            }
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct ThreadHandle {
+    join_handle: thread::JoinHandle<()>,
+    to_thread: mpsc::Sender<ToThreadMsg>,
+    from_thread: mpsc::Receiver<FromThreadMsg>,
+}
+
+
+pub struct WorkerPool {
+    threads: Vec<ThreadHandle>,
+}
+
+
+pub fn no_threads(threads: Option<usize>) -> usize
+{
+    match threads {
+        Some(n) => n,
+        None => std::thread::available_parallelism().unwrap().into()
+    }
+}
+
+
+// Runs with a time budget count milliseconds instead of simulations
+fn progress_bar(config: &RunConfig) -> ProgressBar
+{
+    match config.duration {
+        Some(duration) => {
+            let progbar = ProgressBar::new(duration.as_millis() as u64);
+            progbar.set_style(ProgressStyle::with_template("{prefix}[{elapsed}]/[{eta} left] {bar:40.cyan/blue} {percent}% {msg}").unwrap());
+            progbar
+        }
+        None => {
+            let progbar = ProgressBar::new(config.sims as u64);
+            progbar.set_style(ProgressStyle::with_template("{prefix}[{elapsed}]/[{eta} left] {bar:40.cyan/blue} {percent}% {pos:>7}/{len:7} {per_sec}").unwrap());
+            progbar
+        }
+    }
+}
+
+
+impl WorkerPool {
+    pub fn new(no_threads: usize) -> WorkerPool
+    {
+        println!("Starting {} threads", no_threads);
+
+        let mut threads: Vec<ThreadHandle> = Vec::new();
+        for _ in 0..no_threads {
+            let (to_thread, to_thread_rx) = mpsc::channel();
+            let (from_thread_tx, from_thread) = mpsc::channel();
+
+            threads.push(ThreadHandle {
+                join_handle: thread::spawn(move || worker_thread(to_thread_rx, from_thread_tx)),
+                to_thread,
+                from_thread,
+            });
+        }
+
+        WorkerPool { threads }
+    }
+
+    pub fn size(&self) -> usize
+    {
+        self.threads.len()
+    }
+
+    // Runs all simulations of a config on all threads of the pool
+    pub fn simulate(&self, config: &RunConfig) -> Result<RunResult, String>
+    {
+        simulate_on(&self.threads, config, progress_bar(config))
+    }
+
+    // Runs a list of configs, `parallel` of them at the same time, each on an equal
+    // share of the threads. `done` is called with the result of every finished run.
+    pub fn simulate_all<F>(&mut self, configs: &[RunConfig], parallel: usize, done: F) -> Result<(), String>
+        where F: Fn(usize, RunResult) -> Result<(), String> + Sync
+    {
+        let parallel = parallel.clamp(1, self.threads.len());
+        let threads_per_run = self.threads.len() / parallel;
+
+        let next_run = &Mutex::new(0);
+        let multi_progress = &MultiProgress::new();
+        let done = &done;
+
+        thread::scope(|s| {
+            let runners: Vec<_> = self.threads.chunks_exact_mut(threads_per_run)
+                .map(|threads| s.spawn(move || -> Result<(), String> {
+                    loop {
+                        let run_idx = {
+                            let mut next = next_run.lock().unwrap();
+                            *next += 1;
+                            *next - 1
+                        };
+                        if run_idx >= configs.len() {
+                            return Ok(());
+                        }
+
+                        let progbar = multi_progress.add(progress_bar(&configs[run_idx]));
+                        progbar.set_prefix(format!("run {}/{} ", run_idx + 1, configs.len()));
+
+                        done(run_idx, simulate_on(threads, &configs[run_idx], progbar)?)?;
+                    }
+                }))
+                .collect();
+
+            runners.into_iter().try_for_each(|r| r.join().unwrap())
+        })
+    }
+}
+
+
+fn simulate_on(threads: &[ThreadHandle], config: &RunConfig, progbar: ProgressBar) -> Result<RunResult, String>
+{
+    // Color runs only fill the color canvas, runs with extra shaders the layered one,
+    // phase runs the phase canvas, direction runs the bin canvas and runs that do not
+    // sum the weights the reduced one
+    let layered = config.color.is_none() && !config.extra_shaders.is_empty();
+    let phased = config.wavenumber.is_some();
+    let binned = config.bins().is_some();
+    let reduced = config.reduction != Reduction::Sum;
+    let (width, height) = if config.color.is_some() || layered || phased || binned || reduced {
+        (0, 0)
+    } else {
+        (config.width, config.height)
+    };
+    let escape_map = config.output.escape_map && !layered && !reduced;
+    let shader = build_shader(config)?;
+    let job = Arc::new(Job {
+        arena: config.arena.clone(),
+        dynamics: config.dynamics,
+        sphere_projection: config.sphere_projection,
+        table_scale: config.table_scale,
+        solid: config.solid.clone(),
+        color: build_color_shader(config, &shader)?,
+        layers: build_layered_shader(config, &shader)?,
+        phase: build_phase_shader(config, &shader)?,
+        bins: build_binned_shader(config, &shader)?,
+        reduced: build_reduced_shader(config, &shader)?,
+        shader,
+        extra_shaders: config.extra_shaders.clone(),
+        first_arrival: config.first_arrival,
+        deposit: config.deposit,
+        deposit_at: config.deposit_at,
+        bounce_exponent: config.bounce_exponent,
+        seed: config.seed.unwrap_or_else(random),
+        resample_starts: config.resample_starts,
+        balls: config.balls,
+        branching: config.branching,
+        max_branches: config.max_branches,
+        trail_length: config.trail_length,
+        trail_full: config.trail_full,
+        max_bounces: config.max_bounces,
+        max_path_length: config.max_path_length,
+        return_radius: config.return_radius,
+        trail_memory: config.trail_memory,
+        trail_window: config.trail_window,
+        trail_material: config.trail_material,
+        trail_sides: config.trail_sides,
+        corner_rule: config.corner_rule,
+        corner_tolerance: config.corner_tolerance,
+        reflection_offset: config.reflection_offset,
+        robust_predicates: config.robust_predicates,
+        on_cycle: config.on_cycle,
+        cycle_tolerance: config.cycle_tolerance,
+        cycle_window: config.cycle_window,
+        tiles: config.tiles,
+        fold_symmetry: config.fold_symmetry,
+        reflection_jitter: config.reflection_jitter,
+        tumble: config.tumble.clone(),
+        gravity: config.gravity,
+        larmor_radius: config.larmor_radius,
+        wind: config.wind.clone(),
+        potential: config.potential.clone(),
+        frame_rotation: config.frame_rotation,
+        frame_center: config.frame_center,
+        restitution: config.restitution,
+        trail_restitution: config.trail_restitution,
+        min_energy: config.min_energy,
+        deceleration: config.deceleration,
+        bounce_speedup: config.bounce_speedup,
+        ball_radius: config.ball_radius,
+        trail_width: config.trail_width,
+        flight_step: config.flight_step,
+        result_canvas: Mutex::new(Canvas::new(width, height, 0.0)),
+        escape_canvas: escape_map.then(|| Mutex::new(Canvas::new(config.width, config.height, 0.0))),
+        color_canvas: config.color.is_some().then(|| Mutex::new(Canvas::new(config.width, config.height, Vector3::zero()))),
+        layer_canvas: layered.then(|| Mutex::new(Canvas::new(config.width, config.height, Vector4::zero()))),
+        layer_escapes: (layered && config.output.escape_map).then(|| Mutex::new(Canvas::new(config.width, config.height, Vector4::zero()))),
+        phase_canvas: phased.then(|| Mutex::new(Canvas::new(config.width, config.height, Vector2::zero()))),
+        bin_canvas: binned.then(|| Mutex::new(Canvas::new(config.width, config.height, Bins::zero()))),
+        reduced_canvas: reduced.then(|| Mutex::new(Canvas::new(config.width, config.height, Reduced::zero()))),
+        reduced_escapes: (reduced && config.output.escape_map).then(|| Mutex::new(Canvas::new(config.width, config.height, Reduced::zero()))),
+        arrival_canvas: config.first_arrival.map(|_| Mutex::new(Canvas::new(config.width, config.height, f64::INFINITY))),
+        hit_canvas: config.output.hit_counts.then(|| Mutex::new(Canvas::new(config.width, config.height, 0.0))),
+        vector_canvas: config.output.vector_field.then(|| Mutex::new(Canvas::new(config.width, config.height, Vector2::zero()))),
+        exit_canvas: config.output.exit_map.then(|| Mutex::new(Canvas::new(config.width, config.height, 0.0))),
+        tally: Mutex::new(Tally::default()),
+    });
+
+    for (i, thread) in threads.iter().enumerate() {
+        thread.to_thread.send(Start(job.clone(), i as u64)).unwrap();
+    }
+
+    // Hand out work in small quotas, so the run ends with exactly the requested
+    // number of simulations, or shortly after the time budget ran out. Every
+    // thread gets a second quota queued, so it never has to wait for the next.
+    let start_t = time::Instant::now();
+    let mut handed_out: usize = 0;
+    let mut simulations_done: usize = 0;
+    let mut outstanding: Vec<usize> = vec![0; threads.len()];
+
+    loop {
+        let quota = quota_size(simulations_done, start_t.elapsed(), threads.len());
+
+        for (thread, outstanding) in zip(threads, outstanding.iter_mut()) {
+            while *outstanding < 2 * quota {
+                let n = match config.duration {
+                    Some(duration) if start_t.elapsed() < duration => quota,
+                    Some(_) => 0,
+                    None => quota.min(config.sims - handed_out)
+                };
+                if n == 0 {
+                    break;
+                }
+
+                thread.to_thread.send(Work(n)).unwrap();
+                *outstanding += n;
+                handed_out += n;
+            }
+        }
+
+        if simulations_done == handed_out {
+            break;
+        }
+
+        // Keep track of the progress of all threads and report with a nice progress bar
+        for (thread, outstanding) in zip(threads, outstanding.iter_mut()) {
+            assert!(!thread.join_handle.is_finished());
+
+            match thread.from_thread.recv_timeout(Duration::from_millis(1)) {
+                Ok(Report(n)) => {
+                    simulations_done += n;
+                    *outstanding -= n;
+                    match config.duration {
+                        Some(_) => {
+                            progbar.set_position(start_t.elapsed().as_millis() as u64);
+                            progbar.set_message(format!("{} simulations, {:.0}/s", simulations_done,
+                                                        simulations_done as f64 / start_t.elapsed().as_secs_f64()));
+                        }
+                        None => progbar.inc(n as u64)
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    panic!();
+                }
+
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+    progbar.finish();
+
+
+    // Asks all threads to accumulate in the shared canvas and ask them to stop working
+    for thread in threads {
+        thread.to_thread.send(Accumulate).unwrap();
+        thread.to_thread.send(Stop).unwrap();
+    }
+
+    // Wait until every thread let go of the job
+    while Arc::strong_count(&job) > 1 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let mut colors = job.color_canvas.as_ref().map(|colors| colors.lock().unwrap().clone());
+    let phases = job.phase_canvas.as_ref().map(|phases| phases.lock().unwrap().clone());
+    let bins = job.bin_canvas.as_ref().map(|bins| bins.lock().unwrap().clone());
+    let mut canvas = match (&colors, &phases, &bins) {
+        (Some(colors), _, _) => channel_sum(colors),
+        (_, Some(phases), _) => phase_image(phases, config.output.phase_image),
+        (_, _, Some(bins)) => bin_sum(bins),
+        _ => job.result_canvas.lock().unwrap().clone(),
+    };
+    if let Some(bins) = &bins {
+        colors = Some(bin_colors(bins, &bin_hues(config)));
+    }
+    let mut escapes = job.escape_canvas.as_ref().map(|escapes| escapes.lock().unwrap().clone());
+    if let Some(reduced) = &job.reduced_canvas {
+        canvas = reduced_values(&reduced.lock().unwrap());
+        escapes = job.reduced_escapes.as_ref().map(|escapes| reduced_values(&escapes.lock().unwrap()));
+    }
+    let tally = job.tally.lock().unwrap().clone();
+
+    // The first layer is the shader of the run, the others those of the extra shaders
+    let mut extras = Vec::new();
+    if let Some(layers) = &job.layer_canvas {
+        let layers = layers.lock().unwrap();
+        let layer_escapes = job.layer_escapes.as_ref().map(|escapes| escapes.lock().unwrap());
+        let layer = |i: usize| (channel(&layers, i), layer_escapes.as_ref().map(|escapes| channel(escapes, i)));
+        (canvas, escapes) = layer(0);
+        for (i, shader) in job.extra_shaders.iter().enumerate() {
+            let (canvas, escapes) = layer(i + 1);
+            extras.push(ExtraCanvas { shader: shader.clone(), canvas, escapes });
+        }
+    }
+    let arrivals = job.arrival_canvas.as_ref().map(|arrivals| {
+        let arrivals = arrivals.lock().unwrap();
+        Canvas { data: arrivals.data.iter().map(|v| if v.is_finite() { *v } else { 0.0 }).collect(),
+                 width: arrivals.width, height: arrivals.height }
+    });
+    let hits = job.hit_canvas.as_ref().map(|hits| hits.lock().unwrap().clone());
+    let vectors = job.vector_canvas.as_ref().map(|vectors| vectors.lock().unwrap().clone());
+    let exits = job.exit_canvas.as_ref().map(|exits| exits.lock().unwrap().clone());
+    Ok(RunResult { canvas, escapes, colors, phases, bins, arrivals, hits, vectors, exits, extras, simulations: simulations_done, tally })
+}
+
+
+// Quotas of about 100ms of work per thread, based on the speed so far
+fn quota_size(simulations_done: usize, elapsed: Duration, no_threads: usize) -> usize
+{
+    const QUOTA_DURATION: f64 = 0.1;
+
+    if simulations_done == 0 {
+        return 100;
+    }
+    let rate_per_thread = simulations_done as f64 / elapsed.as_secs_f64() / no_threads as f64;
+    (rate_per_thread * QUOTA_DURATION).clamp(100.0, 1_000_000.0) as usize
+}
+
+
+impl Drop for WorkerPool {
+    // Closing the channels makes the workers return
+    fn drop(&mut self)
+    {
+        while let Some(handle) = self.threads.pop() {
+            drop(handle.to_thread);
+            handle.join_handle.join().unwrap();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotas_are_100ms_of_work_per_thread()
+    {
+        assert_eq!(quota_size(0, Duration::ZERO, 4), 100);
+        assert_eq!(quota_size(20_000, Duration::from_secs(1), 2), 1000);
+        assert_eq!(quota_size(300_000, Duration::from_millis(500), 3), 20_000);
+        // Never smaller than 100, or larger than a million
+        assert_eq!(quota_size(500, Duration::from_secs(1), 1), 100);
+        assert_eq!(quota_size(1_000_000_000, Duration::from_secs(1), 1), 1_000_000);
+    }
+
+    #[test]
+    fn runs_simulate_exactly_the_requested_number()
+    {
+        let pool = WorkerPool::new(3);
+        for sims in [1, 99, 1234] {
+            let config = RunConfig { sims, width: 16, height: 16, seed: Some(1), ..RunConfig::default() };
+            let result = pool.simulate(&config).unwrap();
+            assert_eq!(result.simulations, sims);
+            assert!(result.tally.balls() <= sims);
+        }
+    }
+}
@@ -0,0 +1,991 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use geo::{Coord, coord, EuclideanDistance, Kernel, Line, Orientation, Point, Vector2DOps};
+use geo::kernels::RobustKernel;
+use geo::line_intersection::{line_intersection, LineIntersection};
+
+use crate::config::{CornerRule, Deposit, DepositAt, Material, OnCycle, TrailFull, TrailSides};
+use crate::flight::Flight;
+use crate::moving::MovingWall;
+use crate::shape::outline_edges;
+use crate::simulation::{angle, Tumble};
+use crate::symmetry::Symmetry;
+
+// Originally the walls and the trail had to share room for this many segments.
+// Without an explicit trail length, the trail still gets what the walls leave of it,
+// but at least half.
+pub const MAX_NO_OBSTACLES: usize = 200;
+
+pub const MAX_NO_WALLS: usize = 100_000;
+
+// With more walls than this, they are looked up in a grid instead of tested one by one
+const GRID_THRESHOLD: usize = 32;
+
+// A ball that leaves a curved wall hits it again only further than this along its flight
+const LEAVING_TOLERANCE: f64 = 1e-9;
+
+
+// The primitives walls can be made of
+#[derive(Debug, Clone, Copy)]
+pub enum Shape {
+    Segment(Line),
+    Circle { center: Coord, radius: f64 },
+    Ellipse { center: Coord, radii: Coord, angle: f64 },    // rotated by `angle` from axis aligned
+    Arc { center: Coord, radii: Coord, angle: f64, start: f64, sweep: f64 },    // part of such an ellipse, counter
+                                                                                // clockwise from parametric angle `start`
+}
+
+
+// A wall, and what it does to the balls that hit it
+#[derive(Debug, Clone, Copy)]
+pub struct Wall {
+    pub shape: Shape,
+    pub material: Material,
+}
+
+
+pub struct WallHit {
+    pub point: Coord,
+    pub distance: f64,          // from the start of the ball
+    pub tangent: Coord,         // direction of the wall at the hit point, not normalized
+    pub opening: bool,          // the wall is removed here, the ball escapes
+    pub interface: bool,        // the edge of a region of another medium, the ball refracts
+    pub material: Material,     // of the wall, or the trail, that was hit
+    pub surface: Option<Surface>,   // that was hit
+    pub wall: Option<usize>,        // the index of the wall that was hit, of any shape, the moving walls after the others
+}
+
+
+impl WallHit {
+    // What the ball ran into, when it is a wall or the trail
+    pub fn contact(&self) -> Option<Contact>
+    {
+        match self.surface {
+            Some(Surface::Trail(n)) => Some(Contact::Trail(n)),
+            _ => self.wall.map(Contact::Wall),
+        }
+    }
+}
+
+
+// A part of the scene the ball can hit. A ball that leaves it can not hit it again
+// right away, so its next flight leaves it out, instead of starting a little bit away
+// from it. A curved wall can still be hit again further along, only not where it left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Surface {
+    Wall(usize),                // by index in the walls
+    Trail(usize),               // by the number of segments that were laid before it
+    Edge(usize),                // of a region, by index in the edges of all regions
+}
+
+
+// What a ball ended against, when it ended where it hit something
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Contact {
+    Wall(usize),                // by index in the walls, the moving walls after the others
+    Trail(usize),               // by the number of segments that were laid before it
+}
+
+
+// Where two straight walls meet, and the directions they leave it in
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    point: Coord,
+    sides: [Coord; 2],          // unit vectors
+}
+
+
+// What happens to the ball at the edge of the scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    Walls,                      // there is no edge, the walls keep the ball inside
+    Periodic,                   // the ball wraps around the edges of the unit square
+    Viewport(Coord, Coord),     // the ball escapes when it leaves this box, from its lower to upper corner
+}
+
+
+// The static walls, and the trail the ball leaves behind during a simulation
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub walls: Vec<Wall>,
+    pub no_closed_walls: usize,         // the first walls form closed outlines, the others are loose segments
+    pub trail: Trail,
+    pub max_trail: usize,               // the length of the trail, in segments the ball laid
+    pub trail_full: TrailFull,          // what happens when it laid that many
+    pub max_bounces: Option<usize>,     // the ball stops after this many
+    pub max_path_length: Option<f64>,   // or when it flew this far
+    pub return_radius: Option<f64>,     // or when it came back this close to its start, after a bounce
+    pub branching: f64,                 // chance that a bounce splits the ball, with several balls
+    pub max_branches: usize,
+    pub openings: Vec<(Coord, f64)>,    // center and radius of the holes in the walls
+    pub regions: Vec<(Vec<Coord>, f64)>,    // outline and refractive index of regions of other media
+    pub moving_walls: Vec<MovingWall>,  // they are where they are when the ball gets to them
+    pub boundary: Boundary,
+    pub fold: Option<Symmetry>,         // where balls end is folded into one wedge of this symmetry
+    pub jitter: f64,                    // standard deviation of the random turn of every reflection, in radians
+    pub flight: Option<Flight>,         // how forces bend the path of the ball, it flies straight without them
+    pub restitution: f64,               // the energy of the ball is multiplied with this at every bounce
+    pub trail_restitution: Option<f64>, // instead, at bounces off the trail. None means: the same
+    pub min_energy: f64,                // the ball stops when its energy gets below this
+    pub deceleration: f64,              // of the ball along its path, it stops when its speed gets to 0
+    pub bounce_speedup: f64,            // the speed of the ball is multiplied with this at every bounce
+    pub ball_radius: f64,               // the ball hits walls and its trail when its center gets this close
+    pub trail_width: f64,               // the trail is a band of this width around the path of the ball
+    pub trail_material: Material,
+    pub trail_sides: TrailSides,
+    pub corner_rule: CornerRule,        // how the ball reflects where it hits two walls at once
+    pub corner_tolerance: f64,          // it does when it hits this close to the corner between them
+    pub reflection_offset: f64,         // the ball starts this far from where it bounced
+    pub robust: bool,                   // thin straight walls and trail segments are hit by exact tests
+    pub on_cycle: Option<OnCycle>,      // what happens to a ball caught in a periodic orbit, if it is looked for
+    pub cycle_tolerance: f64,           // bounces at points and in directions this close are the same
+    pub cycle_window: usize,            // number of recent bounces a bounce is compared with
+    pub tumble: Option<Tumble>,         // the ball turns on its own, after these free paths
+    pub record_bounces: bool,           // balls keep the points they bounced at, for the shader
+    pub count_crossings: bool,          // balls count how often their path crossed itself, for the shader
+    pub deposit: Deposit,               // where the weight of a ball goes in the canvas
+    pub deposit_at: DepositAt,          // and of end deposits, which of its points
+    pub bounce_exponent: f64,           // at the k-th bounce, it is weighted with k to this power
+    pub center: Coord,                  // of the arena, balls count how often they went around it
+    corners: Vec<Corner>,               // sorted by x, found by find_corners()
+    grid: Option<WallGrid>,
+}
+
+
+// The segments the ball left behind. With a memory, they are forgotten again that
+// many bounces after they were laid, the oldest first. With a window, only the most
+// recent segments block the ball.
+#[derive(Debug, Clone)]
+pub struct Trail {
+    segments: VecDeque<(Line, usize)>,  // with the number of bounces before it was laid
+    pub memory: Option<usize>,          // None means: never forget
+    pub window: Option<usize>,          // None means: all segments block
+    pub limit: Option<usize>,           // the oldest segment is forgotten when there are more than this
+    bounces: usize,
+    laid: usize,                        // all segments, also the forgotten ones
+}
+
+
+// Uniform grid over the bounding box of the walls, every cell lists the walls whose
+// bounding box overlaps it
+#[derive(Debug, Clone)]
+struct WallGrid {
+    min: Coord,
+    cell_size: Coord,
+    cells_per_side: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+
+impl Shape {
+    // The first point where the ball crosses this wall
+    pub fn intersect(&self, ball: Line) -> Option<WallHit>
+    {
+        match self {
+            Shape::Segment(line) => segment_hit(*line, ball),
+            Shape::Circle { center, radius } => ellipse_hit(*center, coord! {x: *radius, y: *radius}, 0.0, ball, |_| true),
+            Shape::Ellipse { center, radii, angle } => ellipse_hit(*center, *radii, *angle, ball, |_| true),
+            Shape::Arc { center, radii, angle, start, sweep } => {
+                ellipse_hit(*center, *radii, *angle, ball, |p| on_arc(p, *radii, *start, *sweep))
+            }
+        }
+    }
+
+    // Lower and upper corner of a box around the wall
+    pub fn bounding_box(&self) -> (Coord, Coord)
+    {
+        match self {
+            Shape::Segment(line) => (coord! {x: line.start.x.min(line.end.x), y: line.start.y.min(line.end.y)},
+                                     coord! {x: line.start.x.max(line.end.x), y: line.start.y.max(line.end.y)}),
+            Shape::Circle { center, radius } => {
+                let r = coord! {x: *radius, y: *radius};
+                (*center - r, *center + r)
+            }
+            Shape::Ellipse { center, radii, angle } | Shape::Arc { center, radii, angle, .. } => {
+                let (sin, cos) = angle.sin_cos();
+                let half = coord! {x: (radii.x * cos).hypot(radii.y * sin), y: (radii.x * sin).hypot(radii.y * cos)};
+                (*center - half, *center + half)
+            }
+        }
+    }
+
+    // `n` + 1 points along the wall, from its start to its end, the first and last
+    // are the same for closed curves. Segments only have their ends.
+    pub fn points(&self, n: usize) -> Vec<Coord>
+    {
+        let along = |center: Coord, radii: Coord, angle: f64, start: f64, sweep: f64| -> Vec<Coord> {
+            (0..=n).map(|i| {
+                let t = start + sweep * i as f64 / n as f64;
+                center + rotate(coord! {x: radii.x * t.cos(), y: radii.y * t.sin()}, angle)
+            }).collect()
+        };
+
+        match *self {
+            Shape::Segment(line) => vec![line.start, line.end],
+            Shape::Circle { center, radius } => along(center, coord! {x: radius, y: radius}, 0.0, 0.0, 2.0 * PI),
+            Shape::Ellipse { center, radii, angle } => along(center, radii, angle, 0.0, 2.0 * PI),
+            Shape::Arc { center, radii, angle, start, sweep } => along(center, radii, angle, start, sweep),
+        }
+    }
+
+    // Length of the wall. Ellipses use Ramanujan's approximation, arcs are integrated numerically.
+    pub fn length(&self) -> f64
+    {
+        match self {
+            Shape::Segment(line) => line.start.euclidean_distance(&line.end),
+            Shape::Circle { radius, .. } => 2.0 * PI * radius,
+            Shape::Ellipse { radii, .. } => {
+                let (a, b) = (radii.x, radii.y);
+                let h = ((a - b) / (a + b)).powi(2);
+                PI * (a + b) * (1.0 + 3.0 * h / (10.0 + (4.0 - 3.0 * h).sqrt()))
+            }
+            Shape::Arc { radii, start, sweep, .. } => {
+                const STEPS: usize = 256;
+                let dt = sweep / STEPS as f64;
+                (0..STEPS).map(|i| {
+                    let t = start + (i as f64 + 0.5) * dt;
+                    (radii.x * t.sin()).hypot(radii.y * t.cos()) * dt.abs()
+                }).sum()
+            }
+        }
+    }
+
+    // The first point where a ball with radius `r` touches this wall, where its center
+    // gets within `r` of it. A wall the ball already touches at its start is ignored,
+    // after a bounce it is moving away from it. Ellipses are thickened to the ellipses
+    // with radii `r` larger and smaller, that is close for radii much larger than `r`.
+    pub fn intersect_thick(&self, ball: Line, r: f64) -> Option<WallHit>
+    {
+        if self.touches(ball.start, r) {
+            return None;
+        }
+
+        let disc = |center: Coord| ellipse_hit(center, coord! {x: r, y: r}, 0.0, ball, |_| true);
+        let hits: heapless::Vec<WallHit, 4> = match *self {
+            Shape::Segment(line) => match line.delta().try_normalize() {
+                Some(t) => {
+                    let n = coord! {x: -t.y, y: t.x} * r;
+                    [segment_hit(Line::new(line.start + n, line.end + n), ball),
+                     segment_hit(Line::new(line.start - n, line.end - n), ball),
+                     disc(line.start), disc(line.end)].into_iter().flatten().collect()
+                }
+                None => disc(line.start).into_iter().collect(),
+            },
+            Shape::Circle { center, radius } => band_hits(center, coord! {x: radius, y: radius}, 0.0, r, ball, |_, _| true),
+            Shape::Ellipse { center, radii, angle } => band_hits(center, radii, angle, r, ball, |_, _| true),
+            Shape::Arc { center, radii, angle, start, sweep } => {
+                let mut hits = band_hits(center, radii, angle, r, ball, |p, radii| on_arc(p, radii, start, sweep));
+                let ends = self.points(1);
+                hits.extend([disc(ends[0]), disc(ends[1])].into_iter().flatten());
+                hits
+            }
+        };
+        hits.into_iter().min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    // Whether the point is within `r` of the wall, for ellipses as they are thickened
+    pub fn touches(&self, p: Coord, r: f64) -> bool
+    {
+        // Of the point relative to an ellipse, below 1 inside
+        let level = |center: Coord, radii: Coord, angle: f64| {
+            let q = rotate(p - center, -angle);
+            (q.x / radii.x).powi(2) + (q.y / radii.y).powi(2)
+        };
+        let in_band = |center: Coord, radii: Coord, angle: f64| {
+            let offset = coord! {x: r, y: r};
+            let inner = radii - offset;
+            level(center, radii + offset, angle) <= 1.0 && (inner.x <= 0.0 || inner.y <= 0.0 || level(center, inner, angle) >= 1.0)
+        };
+
+        match *self {
+            Shape::Segment(line) => Point::from(p).euclidean_distance(&line) <= r,
+            Shape::Circle { center, radius } => (p.euclidean_distance(&center) - radius).abs() <= r,
+            Shape::Ellipse { center, radii, angle } => in_band(center, radii, angle),
+            Shape::Arc { center, radii, angle, start, sweep } => {
+                let ends = self.points(1);
+                (in_band(center, radii, angle) && on_arc(rotate(p - center, -angle), radii, start, sweep))
+                    || ends.iter().any(|end| p.euclidean_distance(end) <= r)
+            }
+        }
+    }
+
+    // How many times the ball crosses this wall
+    pub fn crossings(&self, ball: Line) -> usize
+    {
+        match self {
+            Shape::Segment(line) => segment_hit(*line, ball).is_some() as usize,
+            Shape::Circle { center, radius } => ellipse_crossings(*center, coord! {x: *radius, y: *radius}, 0.0, ball, |_| true).len(),
+            Shape::Ellipse { center, radii, angle } => ellipse_crossings(*center, *radii, *angle, ball, |_| true).len(),
+            Shape::Arc { center, radii, angle, start, sweep } => {
+                ellipse_crossings(*center, *radii, *angle, ball, |p| on_arc(p, *radii, *start, *sweep)).len()
+            }
+        }
+    }
+}
+
+
+impl Wall {
+    pub fn new(shape: Shape, material: Material) -> Wall
+    {
+        Wall { shape, material }
+    }
+
+    // The hit of a ball with radius `r` on the wall with index `i`, by the exact tests
+    // when it is `robust`. A straight wall the ball is `leaving` is left out, a curved
+    // one only where the ball left it, it can hit it again further along.
+    fn intersect_leaving(&self, ball: Line, r: f64, i: usize, robust: bool, leaving: &[Option<Surface>]) -> Option<WallHit>
+    {
+        let left = leaving.contains(&Some(Surface::Wall(i)));
+        if left && matches!(self.shape, Shape::Segment(_)) {
+            return None;
+        }
+        let skipped = if left { LEAVING_TOLERANCE } else { 0.0 };
+        let ball = match left {
+            true => Line::new(ball.start + ball.delta().try_normalize()? * skipped, ball.end),
+            false => ball,
+        };
+        let hit = match self.shape {
+            _ if r > 0.0 => self.shape.intersect_thick(ball, r),
+            Shape::Segment(line) if robust => robust_segment_hit(line, ball),
+            _ => self.shape.intersect(ball),
+        }?;
+        Some(WallHit { material: self.material, surface: Some(Surface::Wall(i)), wall: Some(i), distance: hit.distance + skipped, ..hit })
+    }
+}
+
+
+impl Scene {
+    pub fn new(closed_walls: Vec<Wall>, loose_walls: Vec<Wall>, openings: Vec<(Coord, f64)>) -> Scene
+    {
+        let no_closed_walls = closed_walls.len();
+        let mut walls = closed_walls;
+        walls.extend(loose_walls);
+
+        let max_trail = MAX_NO_OBSTACLES.saturating_sub(walls.len()).max(MAX_NO_OBSTACLES / 2);
+        let grid = if walls.len() > GRID_THRESHOLD { Some(WallGrid::new(&walls)) } else { None };
+
+        Scene { walls, no_closed_walls, trail: Trail::with_capacity(max_trail), max_trail,
+                trail_full: TrailFull::Trap, max_bounces: None, max_path_length: None,
+                return_radius: None, branching: 0.0, max_branches: 0, openings,
+                regions: Vec::new(), moving_walls: Vec::new(), boundary: Boundary::Walls, fold: None, jitter: 0.0, flight: None,
+                restitution: 1.0, trail_restitution: None, min_energy: 0.0, deceleration: 0.0, bounce_speedup: 1.0, ball_radius: 0.0, trail_width: 0.0,
+                trail_material: Material::Reflect, trail_sides: TrailSides::Both, corner_rule: CornerRule::Nearest,
+                corner_tolerance: 1e-6, reflection_offset: 0.0001, robust: false, on_cycle: None, cycle_tolerance: 1e-6, cycle_window: 64, tumble: None,
+                record_bounces: false, count_crossings: false, deposit: Deposit::End, deposit_at: DepositAt::Termination, bounce_exponent: 0.0,
+                center: coord! {x: 0.5, y: 0.5}, corners: Vec::new(), grid }
+    }
+
+    // Find the corners the corner rule applies to: the ends of two straight walls
+    // within the corner tolerance of each other, that no third wall ends at
+    pub fn find_corners(&mut self)
+    {
+        self.corners.clear();
+        if self.corner_rule == CornerRule::Nearest {
+            return;
+        }
+        let mut ends: Vec<(Coord, Coord, usize)> = self.walls.iter().enumerate()
+            .filter_map(|(i, wall)| match wall.shape {
+                Shape::Segment(line) => Some((line, i)),
+                _ => None,
+            })
+            .filter_map(|(line, i)| line.delta().try_normalize().map(|dir| [(line.start, dir, i), (line.end, -dir, i)]))
+            .flatten()
+            .collect();
+        ends.sort_by(|a, b| a.0.x.total_cmp(&b.0.x));
+
+        let tolerance = self.corner_tolerance;
+        let mut partners: Vec<Vec<usize>> = vec![Vec::new(); ends.len()];
+        for i in 0..ends.len() {
+            for j in (i + 1)..ends.len() {
+                if ends[j].0.x - ends[i].0.x > tolerance {
+                    break;
+                }
+                if ends[i].2 != ends[j].2 && ends[i].0.euclidean_distance(&ends[j].0) <= tolerance {
+                    partners[i].push(j);
+                    partners[j].push(i);
+                }
+            }
+        }
+        for (i, p) in partners.iter().enumerate() {
+            if let [j] = p[..] {
+                if i < j && partners[j].len() == 1 {
+                    self.corners.push(Corner { point: (ends[i].0 + ends[j].0) * 0.5, sides: [ends[i].1, ends[j].1] });
+                }
+            }
+        }
+        self.corners.sort_by(|a, b| a.point.x.total_cmp(&b.point.x));
+    }
+
+    // The direction of the wall the ball reflects off, when it hits within the corner
+    // tolerance of a corner. Reversing is reflecting off a wall across its direction,
+    // the bisector rule reflects off the wall across the bisector of the corner, that
+    // cuts it off.
+    fn corner_tangent(&self, ball: Line, point: Coord) -> Option<Coord>
+    {
+        let tolerance = self.corner_tolerance;
+        let first = self.corners.partition_point(|corner| corner.point.x < point.x - tolerance);
+        let corner = self.corners[first..].iter()
+            .take_while(|corner| corner.point.x <= point.x + tolerance)
+            .filter(|corner| corner.point.euclidean_distance(&point) <= tolerance)
+            .min_by(|a, b| a.point.euclidean_distance(&point).total_cmp(&b.point.euclidean_distance(&point)))?;
+
+        let across = |d: Coord| coord! {x: -d.y, y: d.x};
+        match self.corner_rule {
+            CornerRule::Nearest => None,
+            CornerRule::Reverse => Some(across(ball.delta())),
+            // Walls that go on straight have no bisector, the ball reflects off them
+            CornerRule::Bisector => Some(across(corner.sides[0] + corner.sides[1]))
+                .filter(|tangent| tangent.magnitude() > 1e-9)
+                .or(Some(corner.sides[0])),
+        }
+    }
+
+    // Whether the point is inside the arena, and not inside an obstacle. A ray from
+    // the point crosses the closed walls an odd number of times then. The odd direction
+    // of the ray makes it unlikely to pass exactly through a corner. Openings are
+    // ignored here, the arena still has the shape of its walls. Without enclosing
+    // walls, the scene is its whole box, and only the obstacles are crossed, an even
+    // number of times.
+    pub fn contains(&self, point: Coord) -> bool
+    {
+        let ray = Line::new(point, point + angle(0.7261) * 100.0);
+        let crossings: usize = self.walls[..self.no_closed_walls].iter().map(|wall| wall.shape.crossings(ray)).sum();
+        match self.boundary {
+            Boundary::Walls => crossings % 2 == 1,
+            Boundary::Periodic | Boundary::Viewport(..) => {
+                let (lo, hi) = self.edge_box().unwrap();
+                (lo.x..hi.x).contains(&point.x) && (lo.y..hi.y).contains(&point.y) && crossings.is_multiple_of(2)
+            }
+        }
+    }
+
+    // Lower and upper corner of the edge of the scene, if it has one
+    pub fn edge_box(&self) -> Option<(Coord, Coord)>
+    {
+        match self.boundary {
+            Boundary::Walls => None,
+            Boundary::Periodic => Some((coord! {x: 0.0, y: 0.0}, coord! {x: 1.0, y: 1.0})),
+            Boundary::Viewport(lo, hi) => Some((lo, hi)),
+        }
+    }
+
+    // The refractive index at the point, of the last region it is in
+    pub fn index_at(&self, point: Coord) -> f64
+    {
+        let ray = Line::new(point, point + angle(0.7261) * 100.0);
+        self.regions.iter().rev()
+            .find(|(outline, _)| outline_edges(outline).filter(|edge| segment_hit(*edge, ray).is_some()).count() % 2 == 1)
+            .map_or(1.0, |(_, index)| *index)
+    }
+
+    // Whether the ball fits at the point without touching a wall
+    pub fn has_room(&self, point: Coord) -> bool
+    {
+        self.ball_radius == 0.0 || !self.walls.iter().any(|wall| wall.shape.touches(point, self.ball_radius))
+    }
+
+    // Whether the trail segment blocks a ball coming from `from`. With y pointing
+    // down in the image, left of the segment is where the wedge product is negative.
+    pub fn blocks_from(&self, segment: Line, from: Coord) -> bool
+    {
+        let side = if self.robust {
+            match RobustKernel::orient2d(segment.start, segment.end, from) {
+                Orientation::CounterClockwise => 1.0,
+                Orientation::Clockwise => -1.0,
+                Orientation::Collinear => 0.0,
+            }
+        } else {
+            segment.delta().wedge_product(from - segment.start)
+        };
+        match self.trail_sides {
+            TrailSides::Both => true,
+            TrailSides::Left => side < 0.0,
+            TrailSides::Right => side > 0.0,
+        }
+    }
+
+    // What the energy of the ball is multiplied with when it bounces off a wall, or the trail
+    pub fn restitution_off(&self, trail: bool) -> f64
+    {
+        match trail {
+            true => self.trail_restitution.unwrap_or(self.restitution),
+            false => self.restitution,
+        }
+    }
+
+    // Whether the ball is trapped by the length of its trail
+    pub fn is_full(&self) -> bool
+    {
+        self.trail_full == TrailFull::Trap && self.trail.laid() >= self.max_trail
+    }
+
+    // The closest wall, trail segment or edge of a region the ball hits. Hits on a
+    // wall inside an opening are marked, and so are those on the edge of a region.
+    pub fn test_ball(&self, ball: Line) -> Option<WallHit>
+    {
+        self.test_ball_leaving(ball, &[])
+    }
+
+    // The closest hit of a ball that leaves the surfaces, they are left out
+    pub fn test_ball_leaving(&self, ball: Line, leaving: &[Option<Surface>]) -> Option<WallHit>
+    {
+        let r = self.ball_radius;
+        let wall_hit = match &self.grid {
+            // The grid has the walls in the cells of their outline, thick walls reach further
+            Some(grid) if r == 0.0 => grid.test_ball(&self.walls, ball, leaving, self.robust),
+            _ => self.walls.iter().enumerate()
+                .filter_map(|(i, wall)| wall.intersect_leaving(ball, r, i, self.robust, leaving))
+                .min_by(|a, b| a.distance.total_cmp(&b.distance)),
+        };
+        let wall_hit = wall_hit.map(|hit| WallHit {
+            opening: self.openings.iter().any(|(center, radius)| hit.point.euclidean_distance(center) <= *radius),
+            tangent: self.corner_tangent(ball, hit.point).unwrap_or(hit.tangent),
+            ..hit
+        });
+
+        // The ball touches a thick trail when it gets within half its width of the line,
+        // on top of the radius of the ball. In a periodic scene it reaches over the edges
+        // too, to the copies of the trail on the other side.
+        let trail_r = r + self.trail_width / 2.0;
+        let trail_hits = self.trail.numbered()
+            .filter(|(n, _)| !leaving.contains(&Some(Surface::Trail(*n))))
+            .filter(|(_, line)| self.blocks_from(**line, ball.start))
+            .filter_map(|(n, line)| {
+                let hit = match trail_r > 0.0 {
+                    true => Shape::Segment(*line).intersect_thick(ball, trail_r),
+                    false if self.robust => robust_segment_hit(*line, ball),
+                    false => segment_hit(*line, ball),
+                };
+                hit.map(|hit| WallHit { material: self.trail_material, surface: Some(Surface::Trail(n)), ..hit })
+            });
+        let wrapped_hits = (self.boundary == Boundary::Periodic && trail_r > 0.0).then(|| {
+            self.trail.numbered()
+                .filter(|(n, _)| !leaving.contains(&Some(Surface::Trail(*n))))
+                .flat_map(|(n, line)| periodic_images(*line, trail_r).into_iter().map(move |image| (n, image)))
+                .filter(|(_, image)| self.blocks_from(*image, ball.start))
+                .filter_map(|(n, image)| {
+                    let hit = Shape::Segment(image).intersect_thick(ball, trail_r);
+                    hit.map(|hit| WallHit { material: self.trail_material, surface: Some(Surface::Trail(n)), ..hit })
+                })
+        });
+        let region_hits = self.regions.iter()
+            .flat_map(|(outline, _)| outline_edges(outline))
+            .enumerate()
+            .filter(|(i, _)| !leaving.contains(&Some(Surface::Edge(*i))))
+            .filter_map(|(i, edge)| if self.robust { robust_segment_hit(edge, ball) } else { segment_hit(edge, ball) }.map(|hit| WallHit { interface: true, surface: Some(Surface::Edge(i)), ..hit }));
+        wall_hit.into_iter().chain(trail_hits).chain(wrapped_hits.into_iter().flatten()).chain(region_hits).min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+
+impl Trail {
+    pub fn with_capacity(capacity: usize) -> Trail
+    {
+        Trail { segments: VecDeque::with_capacity(capacity), memory: None, window: None, limit: None, bounces: 0, laid: 0 }
+    }
+
+    pub fn push(&mut self, line: Line)
+    {
+        self.segments.push_back((line, self.bounces));
+        self.laid += 1;
+        if self.limit.is_some_and(|limit| self.segments.len() > limit) {
+            self.segments.pop_front();
+        }
+    }
+
+    // The ball bounced, forget the segments that are too old now
+    pub fn bounce(&mut self)
+    {
+        self.bounces += 1;
+        if let Some(memory) = self.memory {
+            while self.segments.front().is_some_and(|(_, laid_at)| self.bounces - laid_at > memory) {
+                self.segments.pop_front();
+            }
+        }
+    }
+
+    // The segments that block the ball, with the number of segments laid before them
+    pub fn numbered(&self) -> impl Iterator<Item = (usize, &Line)>
+    {
+        let first = self.laid - self.blocking().len();
+        self.blocking().enumerate().map(move |(k, line)| (first + k, line))
+    }
+
+    // The segments that block the ball, the oldest first
+    pub fn blocking(&self) -> impl ExactSizeIterator<Item = &Line>
+    {
+        let older = self.window.map_or(0, |window| self.segments.len().saturating_sub(window));
+        self.segments.iter().skip(older).map(|(line, _)| line)
+    }
+
+    // All segments it remembers, the oldest first, also those outside the window
+    pub fn segments(&self) -> impl Iterator<Item = &Line>
+    {
+        self.segments.iter().map(|(line, _)| line)
+    }
+
+    pub fn laid(&self) -> usize
+    {
+        self.laid
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.segments.clear();
+        self.bounces = 0;
+        self.laid = 0;
+    }
+}
+
+
+impl Extend<Line> for Trail {
+    fn extend<I: IntoIterator<Item = Line>>(&mut self, lines: I)
+    {
+        for line in lines {
+            self.push(line);
+        }
+    }
+}
+
+
+impl WallGrid {
+    fn new(walls: &[Wall]) -> WallGrid
+    {
+        let boxes: Vec<(Coord, Coord)> = walls.iter().map(|wall| wall.shape.bounding_box()).collect();
+        let min = boxes.iter().fold(coord! {x: f64::MAX, y: f64::MAX}, |m, (lo, _)| coord! {x: m.x.min(lo.x), y: m.y.min(lo.y)});
+        let max = boxes.iter().fold(coord! {x: f64::MIN, y: f64::MIN}, |m, (_, hi)| coord! {x: m.x.max(hi.x), y: m.y.max(hi.y)});
+
+        // About one wall per cell, walls that are not axis aligned overlap several
+        let cells_per_side = ((walls.len() as f64).sqrt().ceil() as usize).clamp(1, 256);
+        let extent = max - min;
+        let cell_size = coord! {x: extent.x.max(1e-9) / cells_per_side as f64,
+                                y: extent.y.max(1e-9) / cells_per_side as f64};
+
+        let mut grid = WallGrid { min, cell_size, cells_per_side, cells: vec![Vec::new(); cells_per_side * cells_per_side] };
+        for (i, (lo, hi)) in boxes.iter().enumerate() {
+            let (x0, y0) = grid.cell_of(*lo);
+            let (x1, y1) = grid.cell_of(*hi);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    grid.cells[x + y * cells_per_side].push(i);
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell_of(&self, point: Coord) -> (usize, usize)
+    {
+        let last = self.cells_per_side as f64 - 1.0;
+        (((point.x - self.min.x) / self.cell_size.x).floor().clamp(0.0, last) as usize,
+         ((point.y - self.min.y) / self.cell_size.y).floor().clamp(0.0, last) as usize)
+    }
+
+    // Walk the cells the ball passes through, in order, until a cell contains a hit
+    // that is not further away than where the ball leaves that cell
+    fn test_ball(&self, walls: &[Wall], ball: Line, leaving: &[Option<Surface>], robust: bool) -> Option<WallHit>
+    {
+        let d = ball.delta();
+        let length = d.magnitude();
+        let max = self.min + self.cell_size * self.cells_per_side as f64;
+
+        // The part of the ball inside the grid, as fractions of the ball line
+        let mut t_enter: f64 = 0.0;
+        let mut t_leave: f64 = 1.0;
+        for (start, delta, lo, hi) in [(ball.start.x, d.x, self.min.x, max.x), (ball.start.y, d.y, self.min.y, max.y)] {
+            if delta == 0.0 {
+                if start < lo || start > hi {
+                    return None;
+                }
+            } else {
+                let (t0, t1) = ((lo - start) / delta, (hi - start) / delta);
+                t_enter = t_enter.max(t0.min(t1));
+                t_leave = t_leave.min(t0.max(t1));
+            }
+        }
+        if t_enter > t_leave {
+            return None;
+        }
+
+        let (mut x, mut y) = self.cell_of(ball.start + d * t_enter);
+        let step_x: isize = if d.x > 0.0 { 1 } else { -1 };
+        let step_y: isize = if d.y > 0.0 { 1 } else { -1 };
+        let boundary_t = |cell: usize, step: isize, start: f64, delta: f64, min: f64, size: f64| -> f64 {
+            if delta == 0.0 {
+                return f64::INFINITY;
+            }
+            let boundary = min + size * (cell as f64 + if step > 0 { 1.0 } else { 0.0 });
+            (boundary - start) / delta
+        };
+        let mut t_next_x = boundary_t(x, step_x, ball.start.x, d.x, self.min.x, self.cell_size.x);
+        let mut t_next_y = boundary_t(y, step_y, ball.start.y, d.y, self.min.y, self.cell_size.y);
+        let t_delta_x = (self.cell_size.x / d.x).abs();
+        let t_delta_y = (self.cell_size.y / d.y).abs();
+
+        let mut best: Option<WallHit> = None;
+        loop {
+            for &i in &self.cells[x + y * self.cells_per_side] {
+                if let Some(hit) = walls[i].intersect_leaving(ball, 0.0, i, robust, leaving) {
+                    if best.as_ref().is_none_or(|b| hit.distance < b.distance) {
+                        best = Some(hit);
+                    }
+                }
+            }
+
+            let t_cell_leave = t_next_x.min(t_next_y);
+            if best.as_ref().is_some_and(|b| b.distance <= t_cell_leave * length) || t_cell_leave > t_leave {
+                return best;
+            }
+
+            if t_next_x < t_next_y {
+                match x.checked_add_signed(step_x) {
+                    Some(next) if next < self.cells_per_side => x = next,
+                    _ => return best,
+                }
+                t_next_x += t_delta_x;
+            } else {
+                match y.checked_add_signed(step_y) {
+                    Some(next) if next < self.cells_per_side => y = next,
+                    _ => return best,
+                }
+                t_next_y += t_delta_y;
+            }
+        }
+    }
+}
+
+
+// The copies of the segment one square over, in a periodic scene, that come within `r`
+// of the other side of the edges of the unit square. The trail is split at the edges,
+// so its segments lie within the square, and their copies outside it.
+fn periodic_images(line: Line, r: f64) -> heapless::Vec<Line, 8>
+{
+    let shifts = |a: f64, b: f64| -> heapless::Vec<f64, 3> {
+        [(0.0, true), (1.0, a.min(b) < r), (-1.0, a.max(b) > 1.0 - r)].into_iter()
+            .filter_map(|(shift, near)| near.then_some(shift))
+            .collect()
+    };
+    let mut images = heapless::Vec::new();
+    for dx in shifts(line.start.x, line.end.x) {
+        for dy in shifts(line.start.y, line.end.y).into_iter().filter(|dy| dx != 0.0 || *dy != 0.0) {
+            let offset = coord! {x: dx, y: dy};
+            let _ = images.push(Line::new(line.start + offset, line.end + offset));
+        }
+    }
+    images
+}
+
+
+pub fn segment_hit(line: Line, ball: Line) -> Option<WallHit>
+{
+    match line_intersection(line, ball) {
+        Some(LineIntersection::SinglePoint{intersection: pt, is_proper: _is_proper}) => {
+            Some(WallHit { point: pt, distance: pt.euclidean_distance(&ball.start), tangent: line.delta(), opening: false,
+                     interface: false, material: Material::Reflect, surface: None, wall: None })
+        }
+        _ => None
+    }
+}
+
+
+// Like segment_hit, but with exact orientation tests, by adaptive precision. The
+// degenerate cases are hits too: a ball that touches the segment with an end, or that
+// flies along it, hits it where it first touches it. The point is taken from the
+// tests where they can tell it, and otherwise clamped to the ball, so it is never NaN.
+pub fn robust_segment_hit(line: Line, ball: Line) -> Option<WallHit>
+{
+    let collinear = Orientation::Collinear;
+    let (a, b) = (RobustKernel::orient2d(ball.start, ball.end, line.start), RobustKernel::orient2d(ball.start, ball.end, line.end));
+    let (c, d) = (RobustKernel::orient2d(line.start, line.end, ball.start), RobustKernel::orient2d(line.start, line.end, ball.end));
+
+    let point = if a == collinear && b == collinear {
+        // Along the ball, the first point of the segment
+        let along = |p: Coord| (p - ball.start).dot_product(ball.delta()) / ball.delta().magnitude_squared();
+        let (t0, t1) = (along(line.start), along(line.end));
+        if t0.max(t1) < 0.0 || t0.min(t1) > 1.0 {
+            return None;
+        }
+        ball.start + ball.delta() * t0.min(t1).max(0.0)
+    } else if a == b || (c == d && c != collinear) {
+        // Both ends of one are on the same side of the other
+        return None;
+    } else if a == collinear {
+        line.start
+    } else if b == collinear {
+        line.end
+    } else if c == collinear {
+        ball.start
+    } else if d == collinear {
+        ball.end
+    } else {
+        let t = (line.start - ball.start).wedge_product(line.delta()) / ball.delta().wedge_product(line.delta());
+        ball.start + ball.delta() * t.clamp(0.0, 1.0)
+    };
+    Some(WallHit { point, distance: point.euclidean_distance(&ball.start), tangent: line.delta(), opening: false,
+                   interface: false, material: Material::Reflect, surface: None, wall: None })
+}
+
+
+// Whether `p`, relative to the center in the frame of the ellipse, is on the arc.
+// For circles the parametric angle is the angle of the point itself.
+fn on_arc(p: Coord, radii: Coord, start: f64, sweep: f64) -> bool
+{
+    let t = if radii.x == radii.y { p.y.atan2(p.x) } else { (p.y / radii.y).atan2(p.x / radii.x) };
+    (t - start).rem_euclid(2.0 * PI) <= sweep
+}
+
+
+pub fn rotate(c: Coord, angle: f64) -> Coord
+{
+    let (sin, cos) = angle.sin_cos();
+    coord! {x: c.x * cos - c.y * sin, y: c.x * sin + c.y * cos}
+}
+
+
+// Rotates and scales the ellipse to the unit circle, and solves |start + s * delta| = 1
+// there, for s in [0, 1]. That does not change s. Only the points for which `on_wall`
+// is true count, it gets them relative to the center, rotated back to axis aligned.
+// The solutions are in increasing order.
+fn ellipse_crossings(center: Coord, radii: Coord, angle: f64, ball: Line, on_wall: impl Fn(Coord) -> bool) -> heapless::Vec<f64, 2>
+{
+    let scale = |c: Coord| coord! {x: c.x / radii.x, y: c.y / radii.y};
+    let d = scale(rotate(ball.delta(), -angle));
+    let f = scale(rotate(ball.start - center, -angle));
+
+    let a = d.dot_product(d);
+    let b = 2.0 * f.dot_product(d);
+    let c = f.dot_product(f) - 1.0;
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 || a == 0.0 {
+        return heapless::Vec::new();
+    }
+
+    let sqrt_discriminant = discriminant.sqrt();
+    let s0 = (-b - sqrt_discriminant) / (2.0 * a);
+    let s1 = (-b + sqrt_discriminant) / (2.0 * a);
+    [s0, s1].into_iter()
+        .filter(|s| (0.0..=1.0).contains(s) && on_wall(rotate(ball.start + ball.delta() * *s - center, -angle)))
+        .collect()
+}
+
+
+// The hits of a ball with radius `r` on an ellipse, as far as `on_wall` allows. Its
+// center hits the ellipses with radii `r` larger and smaller. `on_wall` gets the point
+// relative to the center, rotated back to axis aligned, and the radii it is on.
+fn band_hits(center: Coord, radii: Coord, angle: f64, r: f64, ball: Line,
+             on_wall: impl Fn(Coord, Coord) -> bool) -> heapless::Vec<WallHit, 4>
+{
+    let offset = coord! {x: r, y: r};
+    let mut hits = heapless::Vec::new();
+    for radii in [radii + offset, radii - offset] {
+        if radii.x > 0.0 && radii.y > 0.0 {
+            hits.extend(ellipse_hit(center, radii, angle, ball, |p| on_wall(p, radii)));
+        }
+    }
+    hits
+}
+
+
+fn ellipse_hit(center: Coord, radii: Coord, angle: f64, ball: Line, on_wall: impl Fn(Coord) -> bool) -> Option<WallHit>
+{
+    let s = *ellipse_crossings(center, radii, angle, ball, on_wall).first()?;
+
+    // The normal of (x/rx)^2 + (y/ry)^2 = 1 is (x/rx^2, y/ry^2), in the axis aligned frame
+    let point = ball.start + ball.delta() * s;
+    let p = rotate(point - center, -angle);
+    let normal = coord! {x: p.x / (radii.x * radii.x), y: p.y / (radii.y * radii.y)};
+    Some(WallHit {
+        point,
+        distance: s * ball.delta().magnitude(),
+        tangent: rotate(coord! {x: -normal.y, y: normal.x}, angle),
+        opening: false,
+        interface: false,
+        material: Material::Reflect,
+        surface: None,
+        wall: None,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn assert_near(a: Coord, b: Coord)
+    {
+        assert!(a.euclidean_distance(&b) < EPSILON, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn circle_is_hit_where_the_ball_leaves_it()
+    {
+        let circle = Shape::Circle { center: coord! {x: 0.5, y: 0.5}, radius: 0.25 };
+        let hit = circle.intersect(Line::new(coord! {x: 0.5, y: 0.5}, coord! {x: 1.5, y: 0.5})).unwrap();
+        assert_near(hit.point, coord! {x: 0.75, y: 0.5});
+        assert!((hit.distance - 0.25).abs() < EPSILON);
+        assert!(hit.tangent.x.abs() < EPSILON && hit.tangent.y != 0.0);
+    }
+
+    #[test]
+    fn ellipse_is_hit_first_where_the_ball_enters_it()
+    {
+        // Turned a quarter, its short radius is along x
+        let ellipse = Shape::Ellipse { center: coord! {x: 0.0, y: 0.0}, radii: coord! {x: 2.0, y: 1.0}, angle: PI / 2.0 };
+        let hit = ellipse.intersect(Line::new(coord! {x: -3.0, y: 0.0}, coord! {x: 3.0, y: 0.0})).unwrap();
+        assert_near(hit.point, coord! {x: -1.0, y: 0.0});
+        assert!((hit.distance - 2.0).abs() < EPSILON);
+        assert!(ellipse.intersect(Line::new(coord! {x: -3.0, y: 2.5}, coord! {x: 3.0, y: 2.5})).is_none());
+        assert_eq!(ellipse.crossings(Line::new(coord! {x: -3.0, y: 0.0}, coord! {x: 3.0, y: 0.0})), 2);
+    }
+
+    #[test]
+    fn arc_is_only_hit_along_its_sweep()
+    {
+        // The upper half of the unit circle
+        let arc = Shape::Arc { center: coord! {x: 0.0, y: 0.0}, radii: coord! {x: 1.0, y: 1.0}, angle: 0.0, start: 0.0, sweep: PI };
+        let up = arc.intersect(Line::new(coord! {x: 0.0, y: 0.0}, coord! {x: 0.0, y: 2.0})).unwrap();
+        assert_near(up.point, coord! {x: 0.0, y: 1.0});
+        assert!(arc.intersect(Line::new(coord! {x: 0.0, y: 0.0}, coord! {x: 0.0, y: -2.0})).is_none());
+
+        // Through the lower half first, so only where it leaves the upper half
+        let through = arc.intersect(Line::new(coord! {x: 0.0, y: -2.0}, coord! {x: 0.0, y: 2.0})).unwrap();
+        assert_near(through.point, coord! {x: 0.0, y: 1.0});
+        assert!((through.distance - 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn thick_ball_hits_a_segment_a_radius_early()
+    {
+        let segment = Shape::Segment(Line::new(coord! {x: 0.0, y: 1.0}, coord! {x: 1.0, y: 1.0}));
+        let hit = segment.intersect_thick(Line::new(coord! {x: 0.5, y: 0.0}, coord! {x: 0.5, y: 2.0}), 0.1).unwrap();
+        assert_near(hit.point, coord! {x: 0.5, y: 0.9});
+        assert!((hit.distance - 0.9).abs() < EPSILON);
+
+        // Past the end, on the disc around it
+        let hit = segment.intersect_thick(Line::new(coord! {x: 1.05, y: 0.0}, coord! {x: 1.05, y: 2.0}), 0.1).unwrap();
+        assert!((hit.point.y - (1.0 - 0.0075_f64.sqrt())).abs() < EPSILON);
+        assert!(segment.intersect_thick(Line::new(coord! {x: 1.15, y: 0.0}, coord! {x: 1.15, y: 2.0}), 0.1).is_none());
+    }
+
+    #[test]
+    fn thick_ball_ignores_the_wall_it_starts_against()
+    {
+        let segment = Shape::Segment(Line::new(coord! {x: 0.0, y: 1.0}, coord! {x: 1.0, y: 1.0}));
+        assert!(segment.touches(coord! {x: 0.5, y: 0.95}, 0.1));
+        assert!(segment.intersect_thick(Line::new(coord! {x: 0.5, y: 0.95}, coord! {x: 0.5, y: 0.0}), 0.1).is_none());
+    }
+
+    #[test]
+    fn thick_ball_hits_a_circle_from_inside_and_outside()
+    {
+        let circle = Shape::Circle { center: coord! {x: 0.0, y: 0.0}, radius: 1.0 };
+        let inside = circle.intersect_thick(Line::new(coord! {x: 0.0, y: 0.0}, coord! {x: 2.0, y: 0.0}), 0.1).unwrap();
+        assert_near(inside.point, coord! {x: 0.9, y: 0.0});
+        let outside = circle.intersect_thick(Line::new(coord! {x: 2.0, y: 0.0}, coord! {x: 0.0, y: 0.0}), 0.1).unwrap();
+        assert_near(outside.point, coord! {x: 1.1, y: 0.0});
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs;
+use std::path::Path;
+
+use geo::{Coord, coord, Line};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Material, Opening, Region};
+use crate::moving::MovingWall;
+use crate::scene::{Boundary, Scene, Shape, Wall};
+use crate::shape;
+
+
+// The walls of a scene, exactly as they were generated, so a random arena can be
+// simulated again later. Angles are in radians here, as the scene uses them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile {
+    pub arena: String,                  // description of the arena the scene was made from
+    pub boundary: SceneBoundary,
+    pub closed_walls: Vec<SceneWall>,   // form closed outlines, the inside of the arena is within them
+    pub loose_walls: Vec<SceneWall>,
+    pub openings: Vec<Opening>,
+    #[serde(default)]
+    pub regions: Vec<Region>,           // not in scene files of older versions
+    #[serde(default)]
+    pub moving_walls: Vec<MovingWall>,  // neither
+}
+
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum SceneBoundary {
+    Walls,
+    Periodic,
+    Viewport([[f64; 2]; 2]),            // lower and upper corner
+}
+
+
+// Written as { segment = [[x0, y0], [x1, y1]], material = "reflect" } and the like
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SceneWall {
+    #[serde(flatten)]
+    pub shape: SceneShape,
+    pub material: Material,
+}
+
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum SceneShape {
+    Segment([[f64; 2]; 2]),
+    Circle { center: [f64; 2], radius: f64 },
+    Ellipse { center: [f64; 2], radii: [f64; 2], angle: f64 },
+    Arc { center: [f64; 2], radii: [f64; 2], angle: f64, start: f64, sweep: f64 },
+}
+
+
+pub fn save_scene(path: &Path, scene: &Scene, arena: &str) -> Result<(), String>
+{
+    let scene_file = SceneFile::from_scene(scene, arena);
+    let text = toml::to_string(&scene_file).map_err(|e| e.to_string())?;
+    fs::write(path, text).map_err(|e| format!("Could not write scene file {}: {}", path.display(), e))
+}
+
+
+pub fn load_scene(path: &Path) -> Result<SceneFile, String>
+{
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read scene file {}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("Could not parse scene file {}: {}", path.display(), e))
+}
+
+
+fn point(c: Coord) -> [f64; 2]
+{
+    [c.x, c.y]
+}
+
+
+fn coord(p: [f64; 2]) -> Coord
+{
+    coord! {x: p[0], y: p[1]}
+}
+
+
+impl SceneFile {
+    pub fn from_scene(scene: &Scene, arena: &str) -> SceneFile
+    {
+        let walls = |walls: &[Wall]| -> Vec<SceneWall> {
+            walls.iter().map(|wall| SceneWall { shape: SceneShape::from_shape(wall.shape), material: wall.material }).collect()
+        };
+
+        SceneFile {
+            arena: arena.to_string(),
+            boundary: match scene.boundary {
+                Boundary::Walls => SceneBoundary::Walls,
+                Boundary::Periodic => SceneBoundary::Periodic,
+                Boundary::Viewport(lo, hi) => SceneBoundary::Viewport([point(lo), point(hi)]),
+            },
+            closed_walls: walls(&scene.walls[..scene.no_closed_walls]),
+            loose_walls: walls(&scene.walls[scene.no_closed_walls..]),
+            openings: scene.openings.iter().map(|(center, radius)| Opening { center: point(*center), radius: *radius }).collect(),
+            regions: scene.regions.iter()
+                .map(|(outline, index)| Region { polygon: outline.iter().map(|v| point(*v)).collect(), index: *index })
+                .collect(),
+            moving_walls: scene.moving_walls.clone(),
+        }
+    }
+
+    pub fn to_scene(&self) -> Scene
+    {
+        let walls = |walls: &[SceneWall]| -> Vec<Wall> {
+            walls.iter().map(|wall| Wall::new(wall.shape.to_shape(), wall.material)).collect()
+        };
+        let openings = self.openings.iter().map(|opening| (coord(opening.center), opening.radius)).collect();
+
+        let mut scene = Scene::new(walls(&self.closed_walls), walls(&self.loose_walls), openings);
+        scene.boundary = match self.boundary {
+            SceneBoundary::Walls => Boundary::Walls,
+            SceneBoundary::Periodic => Boundary::Periodic,
+            SceneBoundary::Viewport([lo, hi]) => Boundary::Viewport(coord(lo), coord(hi)),
+        };
+        scene.regions = self.regions.iter()
+            .map(|region| (region.polygon.iter().map(|v| coord(*v)).collect(), region.index))
+            .collect();
+        scene.moving_walls = self.moving_walls.clone();
+        scene
+    }
+
+    pub fn no_walls(&self) -> usize
+    {
+        self.closed_walls.len() + self.loose_walls.len()
+    }
+
+    // A scene file can be edited by hand, so check everything a scene can not simulate
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if let SceneBoundary::Viewport([lo, hi]) = self.boundary {
+            if !lo.iter().chain(&hi).all(|c| c.is_finite()) || lo[0] >= hi[0] || lo[1] >= hi[1] {
+                return Err(format!("scene viewport must go from its lower to its upper corner, got {:?}", [lo, hi]));
+            }
+        }
+        for (i, wall) in self.closed_walls.iter().chain(&self.loose_walls).enumerate() {
+            let invalid = |e: &str| Err(format!("scene wall {}: {}", i + 1, e));
+            let (points, radii): (Vec<[f64; 2]>, [f64; 2]) = match wall.shape {
+                SceneShape::Segment([start, end]) => (vec![start, end], [1.0, 1.0]),
+                SceneShape::Circle { center, radius } => (vec![center], [radius, radius]),
+                SceneShape::Ellipse { center, radii, angle } => (vec![center, [angle, 0.0]], radii),
+                SceneShape::Arc { center, radii, angle, start, sweep } => (vec![center, [angle, start], [sweep, 0.0]], radii),
+            };
+            if !points.iter().flatten().all(|c| c.is_finite()) {
+                return invalid("coordinates and angles must be finite");
+            }
+            if !radii.iter().all(|r| *r > 0.0 && r.is_finite()) {
+                return invalid("radii must be larger than 0");
+            }
+            if let Material::Transmit(probability) = wall.material {
+                if !(0.0..=1.0).contains(&probability) {
+                    return invalid("transmit probability must be from 0 to 1");
+                }
+            }
+            if let Material::Rough(roughness) = wall.material {
+                if !(0.0..=1.0).contains(&roughness) {
+                    return invalid("roughness must be from 0 to 1");
+                }
+            }
+            if let Material::Sticky(distance) = wall.material {
+                if !(distance > 0.0 && distance.is_finite()) {
+                    return invalid("sticky slide distance must be larger than 0");
+                }
+            }
+        }
+        for opening in &self.openings {
+            if !(opening.center.iter().all(|c| c.is_finite()) && opening.radius > 0.0 && opening.radius.is_finite()) {
+                return Err(format!("scene opening must have a finite center and a radius larger than 0, got {:?}", opening));
+            }
+        }
+        for region in &self.regions {
+            shape::validate_outline(&region.polygon)?;
+            if !(region.index > 0.0 && region.index.is_finite()) {
+                return Err(format!("scene region refractive index must be larger than 0, got {}", region.index));
+            }
+        }
+        for wall in &self.moving_walls {
+            wall.validate()?;
+        }
+        Ok(())
+    }
+}
+
+
+impl SceneShape {
+    fn from_shape(shape: Shape) -> SceneShape
+    {
+        match shape {
+            Shape::Segment(line) => SceneShape::Segment([point(line.start), point(line.end)]),
+            Shape::Circle { center, radius } => SceneShape::Circle { center: point(center), radius },
+            Shape::Ellipse { center, radii, angle } => SceneShape::Ellipse { center: point(center), radii: point(radii), angle },
+            Shape::Arc { center, radii, angle, start, sweep } => {
+                SceneShape::Arc { center: point(center), radii: point(radii), angle, start, sweep }
+            }
+        }
+    }
+
+    fn to_shape(&self) -> Shape
+    {
+        match *self {
+            SceneShape::Segment([start, end]) => Shape::Segment(Line::new(coord(start), coord(end))),
+            SceneShape::Circle { center, radius } => Shape::Circle { center: coord(center), radius },
+            SceneShape::Ellipse { center, radii, angle } => Shape::Ellipse { center: coord(center), radii: coord(radii), angle },
+            SceneShape::Arc { center, radii, angle, start, sweep } => {
+                Shape::Arc { center: coord(center), radii: coord(radii), angle, start, sweep }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn scene_survives_saving_and_loading()
+    {
+        let corners = [[0.1, 0.1], [0.9, 0.1], [0.9, 0.9], [0.1, 0.9]].map(coord);
+        let materials = [Material::Reflect, Material::Absorb, Material::Transmit(0.25), Material::Sticky(0.1)];
+        let closed_walls = (0..4)
+            .map(|i| Wall::new(Shape::Segment(Line::new(corners[i], corners[(i + 1) % 4])), materials[i]))
+            .collect();
+        let loose_walls = vec![
+            Wall::new(Shape::Circle { center: coord([0.3, 0.3]), radius: 0.05 }, Material::Rough(0.5)),
+            Wall::new(Shape::Ellipse { center: coord([0.7, 0.3]), radii: coord([0.1, 0.05]), angle: PI / 7.0 }, Material::Reflect),
+            Wall::new(Shape::Arc { center: coord([0.5, 0.7]), radii: coord([0.1, 0.1]), angle: 0.0, start: 1.0 / 3.0, sweep: PI },
+                      Material::Reflect),
+        ];
+        let mut scene = Scene::new(closed_walls, loose_walls, vec![(coord([0.5, 0.1]), 0.05)]);
+        scene.boundary = Boundary::Viewport(coord([-0.5, -0.5]), coord([1.5, 1.5]));
+        scene.regions = vec![(vec![coord([0.4, 0.4]), coord([0.6, 0.4]), coord([0.5, 0.6])], 1.5)];
+
+        let path = env::temp_dir().join(format!("scene-round-trip-{}.toml", std::process::id()));
+        save_scene(&path, &scene, "square").unwrap();
+        let loaded = load_scene(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        loaded.validate().unwrap();
+        assert_eq!(loaded.arena, "square");
+        assert_eq!(loaded.no_walls(), 7);
+
+        let reloaded = loaded.to_scene();
+        assert_eq!(reloaded.no_closed_walls, scene.no_closed_walls);
+        assert_eq!(format!("{:?}", reloaded.walls), format!("{:?}", scene.walls));
+        assert_eq!(reloaded.openings, scene.openings);
+        assert_eq!(reloaded.regions, scene.regions);
+        assert_eq!(reloaded.boundary, scene.boundary);
+    }
+
+    #[test]
+    fn edited_scene_files_are_checked()
+    {
+        let scene_file: SceneFile = toml::from_str(r#"
+            arena = "by hand"
+            boundary = "walls"
+            closed_walls = [{ circle = { center = [0.5, 0.5], radius = 0.0 }, material = "reflect" }]
+            loose_walls = []
+            openings = []
+        "#).unwrap();
+        assert_eq!(scene_file.validate().unwrap_err(), "scene wall 1: radii must be larger than 0");
+    }
+}
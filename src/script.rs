@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::Path;
+
+use geo::coord;
+use rhai::{AST, Dynamic, Engine, Scope};
+
+use crate::expression::VARIABLES;
+use crate::shaders::Shader;
+use crate::simulation::{Termination, Trajectory, Trap};
+
+// A script that runs this many operations for one ball is stopped, it weighs nothing
+const MAX_OPERATIONS: u64 = 100_000;
+
+
+// A shader written in Rhai, in a file that is read and compiled when a run starts, so
+// it can be changed in between runs. The script gets the variables of shader
+// expressions as constants, and how the ball ended as `termination`, like "self-hit"
+// or "escaped". The value of its last statement is what the ball is weighed with, a
+// ball the script fails on weighs nothing.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    uses_crossings: bool,
+}
+
+
+impl Script {
+    fn eval(&self, trajectory: &Trajectory) -> Result<f64, String>
+    {
+        let mut scope = Scope::new();
+        for (name, variable) in VARIABLES {
+            scope.push_constant(*name, variable(trajectory));
+        }
+        scope.push_constant("termination", termination_name(trajectory.termination));
+
+        let weight: Dynamic = self.engine.eval_ast_with_scope(&mut scope, &self.ast).map_err(|e| e.to_string())?;
+        if let Ok(x) = weight.as_float() {
+            Ok(x)
+        } else if let Ok(n) = weight.as_int() {
+            Ok(n as f64)
+        } else if let Ok(b) = weight.as_bool() {
+            Ok(b as usize as f64)
+        } else {
+            Err(format!("the script has to end in a number to weigh the ball with, not a {}", weight.type_name()))
+        }
+    }
+}
+
+
+impl Shader<f64> for Script {
+    fn shade(&self, trajectory: &Trajectory) -> f64
+    {
+        self.eval(trajectory).unwrap_or(0.0)
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        self.uses_crossings
+    }
+}
+
+
+// Read and compile a shader script. It is tried on a ball first, so a script that
+// does not end in a number, or uses a variable that does not exist, is found here.
+pub fn load_script(path: &Path) -> Result<Script, String>
+{
+    let in_script = |e: String| format!("shader script {}: {}", path.display(), e);
+    let source = fs::read_to_string(path).map_err(|e| format!("Could not read shader script {}: {}", path.display(), e))?;
+
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let ast = engine.compile(&source).map_err(|e| in_script(e.to_string()))?;
+    let uses_crossings = source.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).any(|word| word == "crossings");
+    let script = Script { engine, ast, uses_crossings };
+    script.eval(&sample()).map_err(in_script)?;
+    Ok(script)
+}
+
+
+// The name a script gets for how the ball ended
+fn termination_name(termination: Termination) -> &'static str
+{
+    match termination {
+        Termination::Trapped(Trap::SelfHit) => "self-hit",
+        Termination::Trapped(Trap::TrailFull) => "trail-full",
+        Termination::Trapped(Trap::Degenerate) => "degenerate",
+        Termination::Escaped => "escaped",
+        Termination::Absorbed => "absorbed",
+        Termination::Stopped => "stopped",
+        Termination::BounceLimit => "bounce-limit",
+        Termination::PathLimit => "path-limit",
+        Termination::Returned => "returned",
+        Termination::Periodic => "periodic",
+    }
+}
+
+
+// A ball to try a script on
+fn sample() -> Trajectory
+{
+    let center = coord! {x: 0.5, y: 0.5};
+    Trajectory { start_pos: center, end_pos: center, end_dir: coord! {x: 1.0, y: 0.0}, path_length: 1.0, travel_time: 1.0,
+                 no_bounces: 1, energy: 1.0, termination: Termination::Trapped(Trap::SelfHit), bounce_points: Vec::new(),
+                 winding: 0.0, crossings: 0, displacement: 0.0, contact: None, exit: None }
+}
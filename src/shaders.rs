@@ -0,0 +1,565 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use cgmath::{Vector2, Vector3, Vector4};
+use cgmath::num_traits::Zero;
+use geo::{Area, BoundingRect, ConvexHull, Coord, coord, EuclideanDistance, MultiPoint, Vector2DOps};
+
+use crate::config::{Reduction, RunConfig};
+use crate::expression;
+use crate::script::load_script;
+use crate::scene::Contact;
+use crate::simulation::{Bins, Reduced, Termination, Trajectory, Trap};
+
+
+// Weighs every ball that ended with what it tells about it. Shaders are shared by the
+// worker threads of a run, they can have parameters from the config, or tables they
+// made up front, but they do not change while they weigh.
+pub trait Shader<T>: Send + Sync {
+    fn shade(&self, trajectory: &Trajectory) -> T;
+
+    // Whether it looks at the bounce points of the trajectory, the balls only record
+    // them for shaders that do
+    fn uses_bounce_points(&self) -> bool
+    {
+        false
+    }
+
+    // Whether it looks at how often the path of the ball crossed itself, the balls
+    // only count that for shaders that do
+    fn uses_crossings(&self) -> bool
+    {
+        false
+    }
+}
+
+
+// Plain functions of the trajectory are shaders without parameters
+impl<T, F: Fn(&Trajectory) -> T + Send + Sync> Shader<T> for F {
+    fn shade(&self, trajectory: &Trajectory) -> T
+    {
+        self(trajectory)
+    }
+}
+
+
+// The path length to the power of the shader exponent, below 1 for a softer image,
+// above 1 to bring out the longest paths
+struct PathPower {
+    exponent: f64,
+}
+
+
+impl Shader<f64> for PathPower {
+    fn shade(&self, trajectory: &Trajectory) -> f64
+    {
+        trajectory.path_length.powf(self.exponent)
+    }
+}
+
+
+// A function of the points the ball went through: where it started, its bounces and
+// where it ended, in the image
+struct OfBouncePoints(fn(&[Coord]) -> f64);
+
+
+impl Shader<f64> for OfBouncePoints {
+    fn shade(&self, trajectory: &Trajectory) -> f64
+    {
+        (self.0)(&trajectory.bounce_points)
+    }
+
+    fn uses_bounce_points(&self) -> bool
+    {
+        true
+    }
+}
+
+
+// How often the path of the ball crossed itself
+struct CrossingCount;
+
+
+impl Shader<f64> for CrossingCount {
+    fn shade(&self, trajectory: &Trajectory) -> f64
+    {
+        trajectory.crossings as f64
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        true
+    }
+}
+
+
+// The area of the convex hull of the points
+fn hull_area(points: &[Coord]) -> f64
+{
+    MultiPoint::from(points.to_vec()).convex_hull().unsigned_area()
+}
+
+
+// The diagonal of the bounding box of the points
+fn extent(points: &[Coord]) -> f64
+{
+    MultiPoint::from(points.to_vec()).bounding_rect().map_or(0.0, |rect| rect.max().euclidean_distance(&rect.min()))
+}
+
+
+// How far the path through the points turns in total, in radians, whichever way
+fn turning(points: &[Coord]) -> f64
+{
+    points.windows(3)
+        .map(|p| {
+            let (a, b) = (p[1] - p[0], p[2] - p[1]);
+            a.wedge_product(b).atan2(a.dot_product(b)).abs()
+        })
+        .sum()
+}
+
+
+// A built-in shader, made from the config of the run
+pub struct BuiltinShader {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub build: fn(&RunConfig) -> Box<dyn Shader<f64>>,
+}
+
+
+pub const SHADERS: &[BuiltinShader] = &[
+    BuiltinShader {
+        name: "path-length",
+        description: "How far the ball flew",
+        build: |_| Box::new(|trajectory: &Trajectory| trajectory.path_length),
+    },
+    BuiltinShader {
+        name: "path-power",
+        description: "The path length to the power of the shader exponent",
+        build: |config| Box::new(PathPower { exponent: config.shader_exponent }),
+    },
+    BuiltinShader {
+        name: "bounce-count",
+        description: "How often the ball bounced",
+        build: |_| Box::new(|trajectory: &Trajectory| trajectory.no_bounces as f64),
+    },
+    BuiltinShader {
+        name: "log-bounces",
+        description: "The natural logarithm of one more than the bounce count, for a softer image",
+        build: |_| Box::new(|trajectory: &Trajectory| (trajectory.no_bounces as f64).ln_1p()),
+    },
+    BuiltinShader {
+        name: "hit-count",
+        description: "1 for every ball, the density of where they ended",
+        build: |_| Box::new(|_trajectory: &Trajectory| 1.0),
+    },
+    BuiltinShader {
+        name: "constant-hit-density",
+        description: "The same as hit-count",
+        build: |_| Box::new(|_trajectory: &Trajectory| 1.0),
+    },
+    BuiltinShader {
+        name: "displacement",
+        description: "How far the ball got from its start, through periodic edges, along the geodesic, or through space",
+        build: |_| Box::new(|trajectory: &Trajectory| trajectory.displacement),
+    },
+    BuiltinShader {
+        name: "straightness",
+        description: "How far the ball got from its start, as ratio of how far it flew",
+        // The path length leaves out the small step every bounce starts with, near the rim of
+        // the hyperbolic disk those add up to more than the displacement knows of
+        build: |_| Box::new(|trajectory: &Trajectory| {
+            if trajectory.path_length > 0.0 { (trajectory.displacement / trajectory.path_length).min(1.0) } else { 0.0 }
+        }),
+    },
+    BuiltinShader {
+        name: "escape-count",
+        description: "1 for every ball that escaped through an opening, 0 for the others",
+        build: |_| Box::new(|trajectory: &Trajectory| (trajectory.termination == Termination::Escaped) as usize as f64),
+    },
+    BuiltinShader {
+        name: "energy",
+        description: "What is left of the energy of the ball, that started at 1",
+        build: |_| Box::new(|trajectory: &Trajectory| trajectory.energy),
+    },
+    BuiltinShader {
+        name: "return-length",
+        description: "The path length of balls that returned to their start, 0 for the others",
+        build: |_| Box::new(|trajectory: &Trajectory| {
+            if trajectory.termination == Termination::Returned { trajectory.path_length } else { 0.0 }
+        }),
+    },
+    BuiltinShader {
+        name: "hull-area",
+        description: "The area of the convex hull of the bounce points of the ball",
+        build: |_| Box::new(OfBouncePoints(hull_area)),
+    },
+    BuiltinShader {
+        name: "extent",
+        description: "The diagonal of the bounding box of the bounce points of the ball",
+        build: |_| Box::new(OfBouncePoints(extent)),
+    },
+    BuiltinShader {
+        name: "turning",
+        description: "How far the path of the ball turned in total at its bounces, in radians",
+        build: |_| Box::new(OfBouncePoints(turning)),
+    },
+    BuiltinShader {
+        name: "travel-time",
+        description: "How long the ball flew, it starts with speed 1",
+        build: |_| Box::new(|trajectory: &Trajectory| trajectory.travel_time),
+    },
+    BuiltinShader {
+        name: "winding",
+        description: "How many turns the path of the ball went around the center of the arena, whichever way",
+        build: |_| Box::new(|trajectory: &Trajectory| trajectory.winding.abs()),
+    },
+    BuiltinShader {
+        name: "crossings",
+        description: "How often the path of the ball crossed itself, through its trail, or where it forgot it",
+        build: |_| Box::new(CrossingCount),
+    },
+];
+
+
+// A color shader tints the weight of every ball, from the shader of the run, with a
+// color of what else its trajectory tells. The canvas adds up every channel on its own.
+pub struct ColorShader {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub tint: fn(&RunConfig) -> Box<dyn Shader<Vector3<f64>>>,
+}
+
+
+struct Tinted {
+    weight: Arc<dyn Shader<f64>>,
+    tint: Box<dyn Shader<Vector3<f64>>>,
+}
+
+
+impl Shader<Vector3<f64>> for Tinted {
+    fn shade(&self, trajectory: &Trajectory) -> Vector3<f64>
+    {
+        self.tint.shade(trajectory) * self.weight.shade(trajectory)
+    }
+
+    fn uses_bounce_points(&self) -> bool
+    {
+        self.weight.uses_bounce_points()
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        self.weight.uses_crossings()
+    }
+}
+
+
+// The fully saturated color of `hue`, in turns from red over green and blue back to red
+pub fn hue(hue: f64) -> Vector3<f64>
+{
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let channel = |v: f64| v.clamp(0.0, 1.0);
+    Vector3::new(channel((h - 3.0).abs() - 1.0), channel(2.0 - (h - 2.0).abs()), channel(2.0 - (h - 4.0).abs()))
+}
+
+
+// The hue of the direction of the start of the ball, seen from the center of the arena
+struct StartAngle {
+    center: Coord,
+}
+
+
+impl Shader<Vector3<f64>> for StartAngle {
+    fn shade(&self, trajectory: &Trajectory) -> Vector3<f64>
+    {
+        let d = trajectory.start_pos - self.center;
+        hue(d.y.atan2(d.x) / (PI * 2.0))
+    }
+}
+
+
+pub const COLOR_SHADERS: &[ColorShader] = &[
+    ColorShader {
+        name: "ending",
+        description: "A color for every way the ball can end, and for every way it got trapped",
+        tint: |_| Box::new(|trajectory: &Trajectory| match trajectory.termination {
+            Termination::Trapped(Trap::SelfHit) => Vector3::new(1.0, 0.4, 0.1),
+            Termination::Trapped(Trap::TrailFull) => Vector3::new(0.2, 0.5, 1.0),
+            Termination::Trapped(Trap::Degenerate) => Vector3::new(1.0, 1.0, 1.0),
+            Termination::Escaped => Vector3::new(0.3, 1.0, 0.3),
+            Termination::Absorbed => Vector3::new(0.6, 0.3, 1.0),
+            Termination::Stopped => Vector3::new(1.0, 0.85, 0.2),
+            Termination::BounceLimit => Vector3::new(0.2, 0.9, 0.9),
+            Termination::PathLimit => Vector3::new(1.0, 0.3, 0.7),
+            Termination::Returned => Vector3::new(0.7, 0.7, 0.7),
+            Termination::Periodic => Vector3::new(0.4, 0.4, 0.4),
+        }),
+    },
+    ColorShader {
+        name: "direction",
+        description: "The hue of the direction the ball flew in last",
+        tint: |_| Box::new(|trajectory: &Trajectory| hue(trajectory.end_dir.y.atan2(trajectory.end_dir.x) / (PI * 2.0))),
+    },
+    ColorShader {
+        name: "start",
+        description: "The hue of the direction of the start of the ball, seen from the center of the arena",
+        tint: |config| Box::new(StartAngle { center: coord! {x: config.arena.center[0], y: config.arena.center[1]} }),
+    },
+    ColorShader {
+        name: "start-xy",
+        description: "More red the further right the ball started, more green the further down, blue in the top left",
+        tint: |_| Box::new(|trajectory: &Trajectory| {
+            let (x, y) = (trajectory.start_pos.x.clamp(0.0, 1.0), trajectory.start_pos.y.clamp(0.0, 1.0));
+            Vector3::new(x, y, 1.0 - (x + y) / 2.0)
+        }),
+    },
+    ColorShader {
+        name: "wall",
+        description: "A hue for every wall the ball ended against, white for its trail, dark gray for neither",
+        tint: |_| Box::new(|trajectory: &Trajectory| match trajectory.contact {
+            // Golden ratio steps keep the hues of neighbouring walls apart
+            Some(Contact::Wall(i)) => hue(i as f64 * 0.618034),
+            Some(Contact::Trail(_)) => Vector3::new(1.0, 1.0, 1.0),
+            None => Vector3::new(0.2, 0.2, 0.2),
+        }),
+    },
+    ColorShader {
+        name: "chirality",
+        description: "Orange for balls that went around the center of the arena from x towards y, blue the other way, gray for neither",
+        tint: |_| Box::new(|trajectory: &Trajectory| match trajectory.winding {
+            w if w >= 0.5 => Vector3::new(1.0, 0.5, 0.1),
+            w if w <= -0.5 => Vector3::new(0.1, 0.4, 1.0),
+            _ => Vector3::new(0.5, 0.5, 0.5),
+        }),
+    },
+];
+
+
+// The color shader of the run, if it has one, tinting its shader
+pub fn build_color_shader(config: &RunConfig, weight: &Arc<dyn Shader<f64>>) -> Result<Option<Box<dyn Shader<Vector3<f64>>>>, String>
+{
+    let Some(name) = &config.color else {
+        return Ok(None);
+    };
+    let color = COLOR_SHADERS.iter().find(|s| s.name == name).ok_or_else(|| {
+        let names: Vec<&str> = COLOR_SHADERS.iter().map(|s| s.name).collect();
+        format!("unknown color shader '{}', available color shaders are: {}", name, names.join(", "))
+    })?;
+    Ok(Some(Box::new(Tinted { weight: weight.clone(), tint: (color.tint)(config) })))
+}
+
+
+// The weight of every ball, from the shader of the run, as a complex number, turned
+// by the wavenumber times its path length. Balls that end in the same pixel interfere.
+struct Phased {
+    weight: Arc<dyn Shader<f64>>,
+    wavenumber: f64,
+}
+
+
+impl Shader<Vector2<f64>> for Phased {
+    fn shade(&self, trajectory: &Trajectory) -> Vector2<f64>
+    {
+        let (sin, cos) = (self.wavenumber * trajectory.path_length).sin_cos();
+        Vector2::new(cos, sin) * self.weight.shade(trajectory)
+    }
+
+    fn uses_bounce_points(&self) -> bool
+    {
+        self.weight.uses_bounce_points()
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        self.weight.uses_crossings()
+    }
+}
+
+
+// The shader of a phase run, if it is one
+pub fn build_phase_shader(config: &RunConfig, weight: &Arc<dyn Shader<f64>>) -> Result<Option<Box<dyn Shader<Vector2<f64>>>>, String>
+{
+    let Some(wavenumber) = config.wavenumber else {
+        return Ok(None);
+    };
+    Ok(Some(Box::new(Phased { weight: weight.clone(), wavenumber })))
+}
+
+
+// The weight of every ball, from the shader of the run, in the bin of the direction it
+// flew in last. The first bin starts at the x axis, they go on towards the y axis.
+struct Binned {
+    weight: Arc<dyn Shader<f64>>,
+    bins: usize,
+}
+
+
+impl Shader<Bins> for Binned {
+    fn shade(&self, trajectory: &Trajectory) -> Bins
+    {
+        let turns = trajectory.end_dir.y.atan2(trajectory.end_dir.x) / (PI * 2.0);
+        let mut bins = Bins::zero();
+        bins.0[(turns.rem_euclid(1.0) * self.bins as f64) as usize % self.bins] = self.weight.shade(trajectory);
+        bins
+    }
+
+    fn uses_bounce_points(&self) -> bool
+    {
+        self.weight.uses_bounce_points()
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        self.weight.uses_crossings()
+    }
+}
+
+
+// The weight of every ball, from the shader of the run, in the band of its bounce count.
+// Every band holds the balls with at most its bounces, the last band all others.
+struct Banded {
+    weight: Arc<dyn Shader<f64>>,
+    bands: Vec<usize>,
+}
+
+
+impl Shader<Bins> for Banded {
+    fn shade(&self, trajectory: &Trajectory) -> Bins
+    {
+        let mut bins = Bins::zero();
+        bins.0[self.bands.partition_point(|most| *most < trajectory.no_bounces)] = self.weight.shade(trajectory);
+        bins
+    }
+
+    fn uses_bounce_points(&self) -> bool
+    {
+        self.weight.uses_bounce_points()
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        self.weight.uses_crossings()
+    }
+}
+
+
+// The shader of a direction or band run, if it is one
+pub fn build_binned_shader(config: &RunConfig, weight: &Arc<dyn Shader<f64>>) -> Result<Option<Box<dyn Shader<Bins>>>, String>
+{
+    if let Some(bins) = config.direction_bins {
+        return Ok(Some(Box::new(Binned { weight: weight.clone(), bins })));
+    }
+    if !config.bounce_bands.is_empty() {
+        return Ok(Some(Box::new(Banded { weight: weight.clone(), bands: config.bounce_bands.clone() })));
+    }
+    Ok(None)
+}
+
+
+// The weight of every ball, from the shader of the run, to be combined with the others
+// in its pixel by the reduction of the run
+struct Reducing {
+    weight: Arc<dyn Shader<f64>>,
+    reduction: Reduction,
+}
+
+
+impl Shader<Reduced> for Reducing {
+    fn shade(&self, trajectory: &Trajectory) -> Reduced
+    {
+        Reduced::new(self.weight.shade(trajectory), self.reduction)
+    }
+
+    fn uses_bounce_points(&self) -> bool
+    {
+        self.weight.uses_bounce_points()
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        self.weight.uses_crossings()
+    }
+}
+
+
+// The shader of a run that combines the weights in a pixel other than by summing them,
+// if it does
+pub fn build_reduced_shader(config: &RunConfig, weight: &Arc<dyn Shader<f64>>) -> Result<Option<Box<dyn Shader<Reduced>>>, String>
+{
+    if config.reduction == Reduction::Sum {
+        return Ok(None);
+    }
+    Ok(Some(Box::new(Reducing { weight: weight.clone(), reduction: config.reduction })))
+}
+
+
+// The number of shaders one pass can weigh the balls with, the shader of the run and its extra shaders
+pub const MAX_LAYERS: usize = 4;
+
+
+// Several shaders at once, for the same balls, the unused layers stay 0
+struct Layered {
+    shaders: Vec<Arc<dyn Shader<f64>>>,
+}
+
+
+impl Shader<Vector4<f64>> for Layered {
+    fn shade(&self, trajectory: &Trajectory) -> Vector4<f64>
+    {
+        let mut layers = Vector4::new(0.0, 0.0, 0.0, 0.0);
+        for (i, shader) in self.shaders.iter().enumerate() {
+            layers[i] = shader.shade(trajectory);
+        }
+        layers
+    }
+
+    fn uses_bounce_points(&self) -> bool
+    {
+        self.shaders.iter().any(|shader| shader.uses_bounce_points())
+    }
+
+    fn uses_crossings(&self) -> bool
+    {
+        self.shaders.iter().any(|shader| shader.uses_crossings())
+    }
+}
+
+
+// The shader of the run with its extra shaders, one layer each, if it has any
+pub fn build_layered_shader(config: &RunConfig, weight: &Arc<dyn Shader<f64>>) -> Result<Option<Box<dyn Shader<Vector4<f64>>>>, String>
+{
+    if config.extra_shaders.is_empty() {
+        return Ok(None);
+    }
+    if config.extra_shaders.len() >= MAX_LAYERS {
+        return Err(format!("a run can have at most {} extra shaders, got {}", MAX_LAYERS - 1, config.extra_shaders.len()));
+    }
+    let mut shaders = vec![weight.clone()];
+    for name in &config.extra_shaders {
+        shaders.push((shader_by_name(name)?.build)(config).into());
+    }
+    Ok(Some(Box::new(Layered { shaders })))
+}
+
+
+// The shader of the run, its script or expression when it has one. The script is read
+// every time, it can change in between runs, so a run builds it once, and the shaders
+// that weigh with it share it.
+pub fn build_shader(config: &RunConfig) -> Result<Arc<dyn Shader<f64>>, String>
+{
+    match (&config.shader_script, &config.shader_expr) {
+        (Some(path), _) => Ok(Arc::new(load_script(path)?)),
+        (None, Some(source)) => Ok(Arc::new(expression::parse(source)?)),
+        (None, None) => Ok((shader_by_name(&config.shader)?.build)(config).into()),
+    }
+}
+
+
+pub fn shader_by_name(name: &str) -> Result<&'static BuiltinShader, String>
+{
+    SHADERS.iter().find(|s| s.name == name).ok_or_else(|| {
+        let names: Vec<&str> = SHADERS.iter().map(|s| s.name).collect();
+        format!("unknown shader '{}', available shaders are: {}", name, names.join(", "))
+    })
+}
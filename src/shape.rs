@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::Path;
+
+use geo::{Coord, Geometry, Line, Polygon};
+use geo::line_intersection::{line_intersection, LineIntersection};
+use geojson::GeoJson;
+use wkt::TryFromWkt;
+
+
+// Read the outline of a custom arena from a WKT or GeoJSON file, the format is
+// picked by the extension. The file has to contain a single polygon, without holes.
+// The vertices are returned in file coordinates, without repeating the first one.
+pub fn load_shape(path: &Path) -> Result<Vec<[f64; 2]>, String>
+{
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read shape file {}: {}", path.display(), e))?;
+    let invalid = |e: String| format!("Shape file {}: {}", path.display(), e);
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let geometry = match extension.as_str() {
+        "wkt" => Geometry::<f64>::try_from_wkt_str(&text).map_err(|e| invalid(e.to_string()))?,
+        "geojson" | "json" => {
+            let geojson: GeoJson = text.parse().map_err(|e: geojson::Error| invalid(e.to_string()))?;
+            Geometry::<f64>::try_from(geojson).map_err(|e| invalid(e.to_string()))?
+        }
+        _ => return Err(invalid("unknown format, use a .wkt, .geojson or .json file".to_string()))
+    };
+
+    let polygon = single_polygon(geometry).map_err(invalid)?;
+    if !polygon.interiors().is_empty() {
+        return Err(invalid("polygons with holes are not supported".to_string()));
+    }
+
+    // geo closes the ring, so the last point is the first one again
+    let ring = &polygon.exterior().0;
+    let vertices: Vec<[f64; 2]> = ring[..ring.len().saturating_sub(1)].iter().map(|c| [c.x, c.y]).collect();
+    validate_outline(&vertices).map_err(invalid)?;
+
+    Ok(vertices)
+}
+
+
+fn single_polygon(geometry: Geometry) -> Result<Polygon, String>
+{
+    match geometry {
+        Geometry::Polygon(polygon) => Ok(polygon),
+        Geometry::MultiPolygon(multi) if multi.0.len() == 1 => Ok(multi.0.into_iter().next().unwrap()),
+        Geometry::GeometryCollection(collection) if collection.0.len() == 1 => {
+            single_polygon(collection.0.into_iter().next().unwrap())
+        }
+        _ => Err("the file must contain exactly one polygon".to_string())
+    }
+}
+
+
+// The edges from every vertex to the next one, and from the last to the first
+pub fn outline_edges(vertices: &[Coord]) -> impl Iterator<Item = Line> + '_
+{
+    (0..vertices.len()).map(|i| Line::new(vertices[i], vertices[(i + 1) % vertices.len()]))
+}
+
+
+// A closed outline needs at least 3 vertices, and its edges may only touch their
+// neighbours, in the vertex they share
+pub fn validate_outline(vertices: &[[f64; 2]]) -> Result<(), String>
+{
+    if vertices.len() < 3 {
+        return Err(format!("an outline needs at least 3 vertices, got {}", vertices.len()));
+    }
+    if !vertices.iter().flatten().all(|c| c.is_finite()) {
+        return Err("outline vertices must be finite".to_string());
+    }
+
+    let coords: Vec<Coord> = vertices.iter().map(|v| Coord { x: v[0], y: v[1] }).collect();
+    let edges: Vec<Line> = outline_edges(&coords).collect();
+    let n = edges.len();
+
+    for i in 0..n {
+        if edges[i].start == edges[i].end {
+            return Err(format!("vertex {} is repeated", i + 1));
+        }
+        for j in (i + 1)..n {
+            let neighbours = j == i + 1 || (i == 0 && j == n - 1);
+            match line_intersection(edges[i], edges[j]) {
+                None => {}
+                Some(LineIntersection::SinglePoint { .. }) if neighbours => {}
+                Some(_) => return Err(format!("the outline intersects itself, at edges {} and {}", i + 1, j + 1))
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1308 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::ops::{Add, AddAssign, Index, Mul};
+
+use cgmath::num_traits::{clamp, Zero};
+use cgmath::Vector2;
+use geo::{Coord, coord, EuclideanDistance, Line, Vector2DOps};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use simple_canvas::Canvas;
+
+use crate::config::{Arrival, Deposit, DepositAt, Material, OnCycle, Reduction};
+use crate::scene::{Boundary, Contact, Scene, Surface, WallHit};
+use crate::shaders::Shader;
+
+const MAX_START_ATTEMPTS: usize = 1000;
+
+// Closer than this, a ball is at what it hit: it is trapped when that is where it left,
+// geodesic flights start this far from the wall they bounce off, and the media on both
+// sides of an edge are told apart this far from it
+pub const CONTACT_DISTANCE: f64 = 0.0001;
+
+// How a ball ended up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Termination {
+    Trapped(Trap),  // by its own trail
+    Escaped,        // through an opening in the walls, or out of the viewport
+    Absorbed,       // by an absorbing wall
+    Stopped,        // its energy, or its speed, ran out
+    BounceLimit,    // it bounced the maximum number of times
+    PathLimit,      // it flew the maximum path length
+    Returned,       // near its start
+    Periodic,       // caught in a periodic orbit
+}
+
+
+// How a trapped ball got trapped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trap {
+    SelfHit,        // it hit its trail, or a wall, right where it left, or crossed its trail in outer billiards
+    TrailFull,      // it laid the whole trail
+    Degenerate,     // there was no way to reflect it, like in a corner, or on a sticky wall
+}
+
+
+// How a ball that hits something `distance` from where it left is trapped, if it is
+pub fn trap(distance: f64, scene: &Scene) -> Option<Trap>
+{
+    if distance < CONTACT_DISTANCE {
+        Some(Trap::SelfHit)
+    } else if scene.is_full() {
+        Some(Trap::TrailFull)
+    } else {
+        None
+    }
+}
+
+
+// How far the ball flies before it tumbles, and turns into a random direction, on
+// its own. Written in a config file as, for example,
+// tumble = { exponential = { mean = 0.2 } }
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Tumble {
+    // Exponentially distributed free paths with this mean, run and tumble
+    Exponential { mean: f64 },
+    // Pareto distributed free paths of at least `min`, with tail exponent `alpha`,
+    // Lévy flights. Below 2 their variance is infinite, below 1 their mean too.
+    PowerLaw { min: f64, alpha: f64 },
+}
+
+
+impl Tumble {
+    pub fn free_path<R: Rng>(&self, rng: &mut R) -> f64
+    {
+        let u: f64 = 1.0 - rng.gen::<f64>();    // in (0, 1]
+        match self {
+            Tumble::Exponential { mean } => -mean * u.ln(),
+            Tumble::PowerLaw { min, alpha } => min * u.powf(-1.0 / alpha),
+        }
+    }
+
+    pub fn describe(&self) -> String
+    {
+        match self {
+            Tumble::Exponential { mean } => format!("exponential free paths, mean {}", mean),
+            Tumble::PowerLaw { min, alpha } => format!("power law free paths from {}, exponent {}", min, alpha),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String>
+    {
+        match self {
+            Tumble::Exponential { mean } => {
+                if !(*mean > 0.0 && mean.is_finite()) {
+                    return Err(format!("tumble mean must be larger than 0, got {}", mean));
+                }
+            }
+            Tumble::PowerLaw { min, alpha } => {
+                if !(*min > 0.0 && min.is_finite() && *alpha > 0.0 && alpha.is_finite()) {
+                    return Err(format!("tumble min and alpha must be larger than 0, got {} and {}", min, alpha));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// What a canvas adds up: the values of a shader, colors, layers of values, or bins
+pub trait Weight: AddAssign + Mul<f64, Output = Self> + Copy {}
+
+
+impl<T: AddAssign + Mul<f64, Output = T> + Copy> Weight for T {}
+
+
+// The most directions a run can bin the balls by
+pub const MAX_BINS: usize = 8;
+
+
+// The weight of the balls in every bin of the directions they flew in last, the bins a
+// run does not use stay 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bins(pub [f64; MAX_BINS]);
+
+
+impl Add for Bins {
+    type Output = Bins;
+
+    fn add(mut self, other: Bins) -> Bins
+    {
+        self += other;
+        self
+    }
+}
+
+
+impl AddAssign for Bins {
+    fn add_assign(&mut self, other: Bins)
+    {
+        for (bin, v) in self.0.iter_mut().zip(other.0) {
+            *bin += v;
+        }
+    }
+}
+
+
+impl Mul<f64> for Bins {
+    type Output = Bins;
+
+    fn mul(self, factor: f64) -> Bins
+    {
+        Bins(self.0.map(|v| v * factor))
+    }
+}
+
+
+impl Zero for Bins {
+    fn zero() -> Bins
+    {
+        Bins([0.0; MAX_BINS])
+    }
+
+    fn is_zero(&self) -> bool
+    {
+        self.0.iter().all(|v| *v == 0.0)
+    }
+}
+
+
+impl Index<usize> for Bins {
+    type Output = f64;
+
+    fn index(&self, i: usize) -> &f64
+    {
+        &self.0[i]
+    }
+}
+
+
+// The weights that went in a pixel, combined by a reduction other than summing. Its
+// count is how many went in, of trail and bounce deposits weighted like the weights
+// themselves, so the mean stays a mean of the weights. Nothing went in while it is 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reduced {
+    pub value: f64,
+    pub count: f64,
+    pub reduction: Reduction,
+}
+
+
+impl Reduced {
+    pub fn new(weight: f64, reduction: Reduction) -> Reduced
+    {
+        Reduced { value: weight, count: 1.0, reduction }
+    }
+
+    // What ends up in the image, 0 where nothing went in
+    pub fn result(&self) -> f64
+    {
+        match self.reduction {
+            _ if self.count == 0.0 => 0.0,
+            Reduction::Mean => self.value / self.count,
+            _ => self.value,
+        }
+    }
+}
+
+
+impl Add for Reduced {
+    type Output = Reduced;
+
+    fn add(mut self, other: Reduced) -> Reduced
+    {
+        self += other;
+        self
+    }
+}
+
+
+impl AddAssign for Reduced {
+    fn add_assign(&mut self, other: Reduced)
+    {
+        if other.count == 0.0 {
+            return;
+        }
+        if self.count == 0.0 {
+            *self = other;
+            return;
+        }
+        self.value = match other.reduction {
+            Reduction::Sum | Reduction::Mean => self.value + other.value,
+            Reduction::Min => self.value.min(other.value),
+            Reduction::Max => self.value.max(other.value),
+        };
+        self.count += other.count;
+    }
+}
+
+
+impl Mul<f64> for Reduced {
+    type Output = Reduced;
+
+    fn mul(self, factor: f64) -> Reduced
+    {
+        let count = if self.reduction == Reduction::Mean { self.count * factor } else { self.count };
+        Reduced { value: self.value * factor, count, reduction: self.reduction }
+    }
+}
+
+
+impl Zero for Reduced {
+    fn zero() -> Reduced
+    {
+        Reduced { value: 0.0, count: 0.0, reduction: Reduction::Sum }
+    }
+
+    fn is_zero(&self) -> bool
+    {
+        self.count == 0.0
+    }
+}
+
+
+// Everything a shader gets to know about one ball
+pub struct Trajectory {
+    pub start_pos: Coord,
+    pub end_pos: Coord,
+    pub end_dir: Coord,             // the direction it flew in last, 0 when it has none
+    pub path_length: f64,
+    pub travel_time: f64,           // the ball starts with speed 1, it is the path length at that speed
+    pub no_bounces: usize,
+    pub energy: f64,                // what is left of it, it starts at 1
+    pub termination: Termination,
+    pub bounce_points: Vec<Coord>,  // where it started, bounced and ended, only when the scene records them
+    pub winding: f64,               // how many turns it went around the center of the arena, from x towards y
+    pub crossings: usize,           // how often its path crossed itself, only when the scene counts them
+    pub displacement: f64,          // how far it got from its start in a straight line, the geodesic or through space
+    pub contact: Option<Contact>,   // what it ended against, when it ended where it hit a wall or its trail
+    pub exit: Option<Exit>,         // where it escaped, when it did through an opening
+}
+
+
+// Where a ball escaped through an opening, and in which direction, the Birkhoff
+// coordinates of the wall it left through
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exit {
+    pub opening: usize,             // the index of the opening
+    pub position: f64,              // along the wall, from -1 to 1 across the opening
+    pub sine: f64,                  // of the angle to the normal of the wall it left in, from -1 to 1
+}
+
+
+// The points of a trajectory: those recorded, if they were, with where it ended
+pub fn bounce_path(recorded: &Option<Vec<Coord>>, end_pos: Coord) -> Vec<Coord>
+{
+    recorded.as_ref().map_or(Vec::new(), |points| points.iter().copied().chain([end_pos]).collect())
+}
+
+
+// The path a ball flew so far, and how often it crossed itself on it. The trail
+// keeps it from that, but not when the ball goes through the trail, or when it left
+// the blocking window or got forgotten.
+#[derive(Debug, Clone, Default)]
+pub struct Crossings {
+    flown: Vec<Line>,
+    pub count: usize,
+}
+
+
+impl Crossings {
+    pub fn fly(&mut self, segment: Line)
+    {
+        self.count += self.flown.iter().filter(|line| crosses(**line, segment)).count();
+        self.flown.push(segment);
+    }
+}
+
+
+// Whether the segments cross, away from their ends. Where one ends on the other, like
+// at a bounce off the trail, they only touch.
+fn crosses(a: Line, b: Line) -> bool
+{
+    let denominator = a.delta().wedge_product(b.delta());
+    if denominator == 0.0 {
+        return false;
+    }
+    let along_a = (b.start - a.start).wedge_product(b.delta()) / denominator;
+    let along_b = (b.start - a.start).wedge_product(a.delta()) / denominator;
+    let inside = |t: f64| (1e-9..1.0 - 1e-9).contains(&t);
+    inside(along_a) && inside(along_b)
+}
+
+
+// How many turns a straight path from `from` to `to` goes around `center`, from x towards y
+pub fn swept(center: Coord, from: Coord, to: Coord) -> f64
+{
+    let (a, b) = (from - center, to - center);
+    a.wedge_product(b).atan2(a.dot_product(b)) / (PI * 2.0)
+}
+
+
+// How the balls of a run ended, and how often they bounced. Balls that left the arena
+// through a hole in the walls, or flew off, count as escaped, those that started
+// outside it, and were skipped, are not counted.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Tally {
+    pub trapped: usize,
+    pub escaped: usize,
+    pub absorbed: usize,
+    pub stopped: usize,
+    pub bounce_limit: usize,
+    pub path_limit: usize,
+    pub returned: usize,
+    pub periodic: usize,
+    pub bounces: usize,             // of all counted balls together
+    #[serde(default)]
+    pub wall_ends: Vec<usize>,      // how many balls ended against every wall, by index
+    #[serde(default)]
+    pub trail_ends: usize,          // and against their trail
+}
+
+
+impl Tally {
+    pub fn record(&mut self, trajectory: &Trajectory)
+    {
+        *match trajectory.termination {
+            Termination::Trapped(_) => &mut self.trapped,
+            Termination::Escaped => &mut self.escaped,
+            Termination::Absorbed => &mut self.absorbed,
+            Termination::Stopped => &mut self.stopped,
+            Termination::BounceLimit => &mut self.bounce_limit,
+            Termination::PathLimit => &mut self.path_limit,
+            Termination::Returned => &mut self.returned,
+            Termination::Periodic => &mut self.periodic,
+        } += 1;
+        self.bounces += trajectory.no_bounces;
+        match trajectory.contact {
+            Some(Contact::Wall(i)) => {
+                if self.wall_ends.len() <= i {
+                    self.wall_ends.resize(i + 1, 0);
+                }
+                self.wall_ends[i] += 1;
+            }
+            Some(Contact::Trail(_)) => self.trail_ends += 1,
+            None => {}
+        }
+    }
+
+    pub fn add(&mut self, other: &Tally)
+    {
+        self.trapped += other.trapped;
+        self.escaped += other.escaped;
+        self.absorbed += other.absorbed;
+        self.stopped += other.stopped;
+        self.bounce_limit += other.bounce_limit;
+        self.path_limit += other.path_limit;
+        self.returned += other.returned;
+        self.periodic += other.periodic;
+        self.bounces += other.bounces;
+        if self.wall_ends.len() < other.wall_ends.len() {
+            self.wall_ends.resize(other.wall_ends.len(), 0);
+        }
+        for (n, m) in self.wall_ends.iter_mut().zip(&other.wall_ends) {
+            *n += m;
+        }
+        self.trail_ends += other.trail_ends;
+    }
+
+    pub fn balls(&self) -> usize
+    {
+        self.trapped + self.escaped + self.absorbed + self.stopped + self.bounce_limit + self.path_limit + self.returned + self.periodic
+    }
+}
+
+
+impl Extend<Trajectory> for Tally {
+    fn extend<I: IntoIterator<Item = Trajectory>>(&mut self, trajectories: I)
+    {
+        for trajectory in trajectories {
+            self.record(&trajectory);
+        }
+    }
+}
+
+
+// The ball leaving `intersection` in direction `dir`. It starts `offset` away from it,
+// the wall it left and the trail segment it just laid are left out of its next
+// flight anyway, so it does not collide with them immediately.
+fn departure(intersection: Coord, dir: Coord, offset: f64) -> Line
+{
+    Line::new(intersection + dir * offset, intersection + dir * 10.0)
+}
+
+
+// Reflect the ball, that came from `ball`, off a wall with direction `tangent` at `intersection`
+fn reflection(ball: Coord, tangent: Coord, intersection: Coord, offset: f64) -> Option<Line>
+{
+    let tangent = tangent.try_normalize()?;
+    let centered_ball = ball - intersection;
+
+    let x =  tangent * (-centered_ball.dot_product(tangent));
+    let reflected_dir = (x * 2.0 + centered_ball).try_normalize()?;
+
+    Some(departure(intersection, reflected_dir, offset))
+}
+
+
+// Reflect the ball off a rough wall. The direction is blended from the mirror
+// direction and a random diffuse one, by `roughness` from 0, a mirror, to 1, fully
+// diffuse. Diffuse directions follow the cosine law around the normal of the wall.
+fn rough_reflection<R: Rng>(ball: Coord, tangent: Coord, intersection: Coord, roughness: f64, offset: f64, rng: &mut R) -> Option<Line>
+{
+    let specular = reflection(ball, tangent, intersection, offset)?.delta().try_normalize()?;
+    let tangent = tangent.try_normalize()?;
+    let normal = wall_normal(ball, tangent, intersection);
+    let sin: f64 = rng.gen_range(-1.0 .. 1.0);
+    let diffuse = normal * (1.0 - sin * sin).sqrt() + tangent * sin;
+
+    let dir = (specular * (1.0 - roughness) + diffuse * roughness).try_normalize()?;
+    Some(departure(intersection, dir, offset))
+}
+
+
+// Turn the reflected ball by a random angle, normally distributed with standard
+// deviation `sigma` in radians. Balls that turned into the wall are mirrored back
+// out of it.
+fn jitter<R: Rng>(reflected: Line, ball: Coord, tangent: Coord, intersection: Coord, sigma: f64, offset: f64, rng: &mut R)
+    -> Option<Line>
+{
+    let normal = wall_normal(ball, tangent.try_normalize()?, intersection);
+    let delta = reflected.delta();
+    let mut dir = angle(delta.y.atan2(delta.x) + gaussian(rng) * sigma);
+    if dir.dot_product(normal) < 0.0 {
+        dir = dir - normal * (2.0 * dir.dot_product(normal));
+    }
+    Some(departure(intersection, dir, offset))
+}
+
+
+// The unit normal of a wall with unit direction `tangent`, on the side of `ball`
+fn wall_normal(ball: Coord, tangent: Coord, intersection: Coord) -> Coord
+{
+    let normal = coord! {x: -tangent.y, y: tangent.x};
+    if normal.dot_product(ball - intersection) < 0.0 { -normal } else { normal }
+}
+
+
+// A standard normally distributed number, by the Box-Muller transform
+fn gaussian<R: Rng>(rng: &mut R) -> f64
+{
+    let u: f64 = 1.0 - rng.gen::<f64>();    // in (0, 1], for the logarithm
+    let v: f64 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+}
+
+
+// Refract the ball, that came from `ball`, where it crosses the edge of a region with
+// direction `tangent` at `intersection`. `ratio` is the refractive index it comes
+// from over the one it goes into. When it can not get out, it reflects totally.
+fn refraction(ball: Coord, tangent: Coord, intersection: Coord, ratio: f64, offset: f64) -> Option<Line>
+{
+    let dir = (intersection - ball).try_normalize()?;
+    let tangent = tangent.try_normalize()?;
+    let mut normal = coord! {x: -tangent.y, y: tangent.x};
+    if normal.dot_product(dir) < 0.0 {
+        normal = -normal;
+    }
+
+    // Snell's law keeps the index times the sine of the angle with the normal
+    let along = dir.dot_product(tangent) * ratio;
+    if along.abs() > 1.0 {
+        return reflection(ball, tangent, intersection, offset);
+    }
+    let refracted = tangent * along + normal * (1.0 - along * along).sqrt();
+    Some(departure(intersection, refracted, offset))
+}
+
+
+// Let the ball, that came from `ball`, pass straight through a wall at `intersection`
+fn transmission(ball: Coord, intersection: Coord, offset: f64) -> Option<Line>
+{
+    let dir = (intersection - ball).try_normalize()?;
+    Some(departure(intersection, dir, offset))
+}
+
+
+pub enum SimStepOutcome {
+    Trapped(Coord, Trap),
+    Bounced,
+    Flew,           // one step of a curved flight, without hitting anything
+    Escaped(Coord), // through an opening, or out of the viewport
+    Absorbed(Coord),
+    Stopped(Coord), // out of energy, or of speed
+    BounceLimit(Coord),
+    PathLimit(Coord),
+    Returned(Coord),
+    Periodic(Coord),
+    Lost            // no collision, probably started outside already
+}
+
+
+// A random start position and direction. Starts outside the arena, or inside an
+// obstacle, are tried again when `resample` is set, for a limited number of times.
+pub fn random_start<R: Rng>(scene: &Scene, rng: &mut R, resample: bool) -> Option<(Coord, Coord)>
+{
+    let attempts = if resample { MAX_START_ATTEMPTS } else { 1 };
+
+    for _ in 0..attempts {
+        let start_pos = coord! {x: rng.gen_range(0.0 .. 1.0),
+                                y: rng.gen_range(0.0 .. 1.0)};
+        let rand_dir =  angle(rng.gen_range(0.0 .. PI*2.0)) * 10.0;
+
+        if scene.contains(start_pos) && scene.has_room(start_pos) {
+            return Some((start_pos, rand_dir));
+        }
+    }
+    None
+}
+
+
+// One ball on its way, and what it did so far
+#[derive(Clone)]
+pub struct Ball {
+    pub start_pos: Coord,
+    pub line: Line,                     // where it flies next
+    velocity: Coord,                    // of curved flights
+    // The last free step of a curved flight joins the next one, it only goes into the
+    // trail after that, so the next step does not hit it where they meet
+    last_step: Option<Line>,
+    // What it bounced off and the trail segment it laid up to there, its next flight leaves them out
+    leaving: [Option<Surface>; 2],
+    pub tumble_in: f64,                 // how much further it flies before it tumbles
+    recent: VecDeque<[i64; 4]>,         // where and in which direction it left its last bounces, rounded
+    pub bounce_points: Option<Vec<Coord>>,  // where it started and bounced, when they are recorded
+    pub path_length: f64,
+    pub speed: f64,
+    pub time: f64,                      // it flew so far
+    pub no_bounces: usize,
+    pub energy: f64,
+    pub winding: f64,                   // turns around the center of the arena so far
+    pub crossings: Option<Crossings>,   // when they are counted
+    wrapped: Coord,                     // how far the periodic edges moved it back, to get it into the square again
+    contact: Option<Contact>,           // what it hit at the end of its last flight
+    exit: Option<Exit>,                 // where it escaped through an opening
+}
+
+
+// What the ball runs into on its next flight, and how far it gets
+pub struct Plan {
+    step: Option<(Coord, Coord, f64)>,      // end, velocity and time of the step of a curved flight
+    hit: Option<WallHit>,
+    exit: Option<(Coord, f64, Coord)>,      // through the edge of the scene, before the hit
+    reach: Option<f64>,
+    tumble: Option<f64>,                    // where it turns on its own, before that
+    halt: Option<f64>,                      // where its speed gets to 0, before that too
+    limit: Option<(Coord, f64)>,            // where it flies its maximum path length, before that too
+    back: Option<f64>,                      // where it comes back to its start, before that too
+}
+
+
+impl Plan {
+    // How far the ball gets before something happens, None when nothing ever does
+    pub fn distance(&self) -> Option<f64>
+    {
+        self.back.or(self.limit.map(|(_, rest)| rest)).or(self.halt).or(self.tumble).or(self.reach)
+    }
+
+    // Where the ball bounces, if it runs into a wall, or the trail, before anything else
+    pub fn bounce_point(&self) -> Option<Coord>
+    {
+        match (&self.hit, &self.exit, &self.tumble, &self.halt, &self.limit, &self.back) {
+            (Some(hit), None, None, None, None, None) if !hit.opening && !hit.interface => Some(hit.point),
+            _ => None,
+        }
+    }
+
+    // The plan when the ball runs into `hit` before anything else
+    pub fn hitting(self, hit: WallHit) -> Plan
+    {
+        Plan { reach: Some(hit.distance), hit: Some(hit), exit: None, tumble: None, halt: None, limit: None, back: None, ..self }
+    }
+}
+
+
+impl Ball {
+    pub fn new(start_pos: Coord, dir: Coord) -> Ball
+    {
+        Ball { start_pos, line: Line::new(start_pos, start_pos + dir), velocity: dir / 10.0, last_step: None, leaving: [None, None],
+               tumble_in: f64::INFINITY, recent: VecDeque::new(), bounce_points: None, path_length: 0.0, speed: 1.0, time: 0.0, no_bounces: 0, energy: 1.0,
+               winding: 0.0, crossings: None, wrapped: coord! {x: 0.0, y: 0.0}, contact: None,
+               exit: None }
+    }
+
+    pub fn plan(&mut self, scene: &Scene) -> Plan
+    {
+        // A curved flight goes in steps, the ball is only the next step then
+        let step = scene.flight.as_ref().map(|flight| flight.advance(self.line.start, self.velocity));
+        if let Some((end, _, _)) = step {
+            self.line = Line::new(self.line.start, end);
+        }
+        let ball = self.line;
+
+        // Moving walls are hit where they are when the ball gets there
+        let hit = scene.moving_walls.iter().enumerate()
+            .filter_map(|(j, wall)| {
+                let hit = wall.hit(ball, |distance| self.arrival(distance, scene))?;
+                Some(WallHit { wall: Some(scene.walls.len() + j), ..hit })
+            })
+            .chain(scene.test_ball_leaving(ball, &self.leaving))
+            .min_by(|a, b| a.distance.total_cmp(&b.distance));
+        // Whether the ball gets to the edge of the scene before anything else
+        let exit = scene.edge_box()
+            .and_then(|(lo, hi)| box_exit(ball, lo, hi))
+            .filter(|(_, distance, _)| hit.as_ref().is_none_or(|hit| *distance < hit.distance));
+        // Where the ball flies its maximum path length, if that comes first
+        let reach = match (&hit, &exit) {
+            (_, Some((_, distance, _))) => Some(*distance),
+            (Some(hit), None) => Some(hit.distance),
+            (None, None) => step.map(|_| ball.delta().magnitude()),
+        };
+        // Where it tumbles, if it gets no further
+        let tumble = Some(self.tumble_in).filter(|distance| reach.is_some_and(|reach| *distance < reach));
+        let reach_or_tumble = tumble.or(reach);
+        // Where a slowing ball stops, if it gets no further either
+        let halt = Some(scene.deceleration).filter(|a| *a > 0.0)
+            .map(|a| self.speed * self.speed / (2.0 * a))
+            .filter(|distance| reach_or_tumble.is_some_and(|reach| *distance < reach));
+        let limit = scene.max_path_length.zip(halt.or(reach_or_tumble))
+            .filter(|(max, reach)| self.path_length + reach > *max)
+            .map(|(max, _)| {
+                let rest = max - self.path_length;
+                (ball.start + ball.delta() * (rest / ball.delta().magnitude()), rest)
+            });
+        // Where it comes back to its start, if that comes first too
+        let back = scene.return_radius
+            .filter(|_| self.no_bounces > 0)
+            .and_then(|radius| disc_entry(ball, self.start_pos, radius))
+            .filter(|distance| halt.or(reach_or_tumble).is_some_and(|reach| *distance <= reach))
+            .filter(|distance| limit.is_none_or(|(_, rest)| *distance < rest));
+
+        Plan { step, hit, exit, reach, tumble, halt, limit, back }
+    }
+
+    // Fly the ball as planned, and bounce it off what it hit
+    pub fn advance<R: Rng>(&mut self, plan: Plan, scene: &mut Scene, rng: &mut R) -> SimStepOutcome
+    {
+        let Plan { step, hit, exit, tumble, halt, limit, back, .. } = plan;
+        let ball = self.line;
+        self.leaving = [None, None];
+        self.contact = None;
+
+        match (hit, exit) {
+
+            _ if back.is_some() => {
+                let distance = back.unwrap();
+                self.fly(distance, step, scene);
+                SimStepOutcome::Returned(ball.start + ball.delta() * (distance / ball.delta().magnitude()))
+            }
+
+            _ if halt.is_some() && limit.is_none() => {
+                let distance = halt.unwrap();
+                self.fly(distance, step, scene);
+                SimStepOutcome::Stopped(ball.start + ball.delta() * (distance / ball.delta().magnitude()))
+            }
+
+            _ if limit.is_some() => {
+                let (end_point, rest) = limit.unwrap();
+                self.fly(rest, step, scene);
+                SimStepOutcome::PathLimit(end_point)
+            }
+
+            // Tumbling is not a bounce, but it ends a trail segment
+            _ if tumble.is_some() => {
+                let distance = tumble.unwrap();
+                let point = ball.start + ball.delta() * (distance / ball.delta().magnitude());
+                self.fly(distance, step, scene);
+
+                if scene.is_full() {
+                    SimStepOutcome::Trapped(point, Trap::TrailFull) // trapped
+                } else {
+                    scene.trail.extend(self.last_step.take());
+                    scene.trail.push(Line::new(ball.start, point));
+                    self.line = departure(point, angle(rng.gen_range(0.0 .. PI * 2.0)), scene.reflection_offset);
+                    self.leaving = [None, Some(Surface::Trail(scene.trail.laid() - 1))];
+                    self.tumble_in = scene.tumble.as_ref().map_or(f64::INFINITY, |tumble| tumble.free_path(rng));
+                    SimStepOutcome::Bounced // continue in the new direction
+                }
+            }
+
+            (_, Some((exit_point, distance, _))) if matches!(scene.boundary, Boundary::Viewport(..)) => {
+                self.fly(distance, step, scene);
+                SimStepOutcome::Escaped(exit_point)
+            }
+
+            (_, Some((exit_point, distance, entry_point))) => {
+                self.fly(distance, step, scene);
+
+                if scene.is_full() {
+                    SimStepOutcome::Trapped(exit_point, Trap::TrailFull) // trapped
+                } else {
+                    // The trail is split at the edge, so every part of it lies within the square
+                    scene.trail.extend(self.last_step.take());
+                    scene.trail.push(Line::new(ball.start, exit_point));
+                    self.line = Line::new(entry_point, entry_point + ball.delta());
+                    self.wrapped = self.wrapped + (exit_point - entry_point);
+                    SimStepOutcome::Bounced // continue on the other side
+                }
+            }
+
+            (Some(hit), None) if hit.opening => {
+                self.fly(hit.distance, step, scene);
+                self.exit = opening_exit(scene, &hit, ball);
+                SimStepOutcome::Escaped(hit.point)
+            }
+
+            // Crossing into another medium, or reflecting totally at its edge, is not a bounce
+            (Some(hit), None) if hit.interface => {
+                self.fly(hit.distance, step, scene);
+
+                if let Some(trap) = trap(hit.distance, scene) {
+                    SimStepOutcome::Trapped(hit.point, trap) // trapped
+                } else {
+                    scene.trail.push(Line::new(ball.start, hit.point));
+                    let dir = ball.delta() / ball.delta().magnitude();
+                    let ratio = scene.index_at(hit.point - dir * CONTACT_DISTANCE) / scene.index_at(hit.point + dir * CONTACT_DISTANCE);
+                    match refraction(ball.start, hit.tangent, hit.point, ratio, scene.reflection_offset) {
+                        Some(b) => {
+                            self.line = b;
+                            self.leaving = [hit.surface, Some(Surface::Trail(scene.trail.laid() - 1))];
+                            SimStepOutcome::Bounced // continue in the other medium
+                        }
+                        None => SimStepOutcome::Trapped(hit.point, Trap::Degenerate),
+                    }
+                }
+            }
+
+            (Some(hit), None) => {
+                let col_point = hit.point;
+                self.fly(hit.distance, step, scene);
+                self.contact = hit.contact();
+
+                // Passing through a wall is not a bounce
+                let transmitted = matches!(hit.material, Material::Transmit(p) if rng.gen_bool(p));
+                // The flight is split where it goes through the trail, neither part crosses it
+                if let Some(crossings) = self.crossings.as_mut().filter(|_| transmitted && matches!(hit.surface, Some(Surface::Trail(_)))) {
+                    crossings.count += 1;
+                }
+                let restitution = scene.restitution_off(matches!(hit.surface, Some(Surface::Trail(_))));
+                if !transmitted {
+                    self.no_bounces += 1;
+                    self.energy *= restitution;
+                    self.speed *= scene.bounce_speedup;
+                }
+
+                if hit.material == Material::Absorb {
+                    SimStepOutcome::Absorbed(col_point)
+                } else if let Some(trap) = trap(hit.distance, scene) {
+                    SimStepOutcome::Trapped(col_point, trap) // trapped
+                } else if self.energy < scene.min_energy {
+                    SimStepOutcome::Stopped(col_point)
+                } else if scene.max_bounces.is_some_and(|max| self.no_bounces >= max) {
+                    SimStepOutcome::BounceLimit(col_point)
+                } else {
+                    scene.trail.extend(self.last_step.take());
+                    scene.trail.push(Line::new(ball.start, col_point));
+                    if !transmitted {
+                        scene.trail.bounce();
+                    }
+
+                    // A curved flight bounces with the velocity it has at the hit, somewhere in
+                    // between that at the start and the end of the step, not along the step
+                    let velocity = self.velocity;
+                    let hit_velocity = step.map(|(_, end_velocity, _)| {
+                        velocity + (end_velocity - velocity) * (hit.distance / ball.delta().magnitude())
+                    });
+                    let from = hit_velocity.map_or(ball.start, |v| col_point - v);
+
+                    let next_ball = match hit.material {
+                        _ if transmitted => transmission(from, col_point, scene.reflection_offset),
+                        Material::Rough(roughness) => rough_reflection(from, hit.tangent, col_point, roughness, scene.reflection_offset, rng),
+                        Material::Sticky(distance) => self.slide(from, &hit, distance, scene, rng),
+                        _ => reflection(from, hit.tangent, col_point, scene.reflection_offset),
+                    };
+                    let next_ball = match next_ball {
+                        Some(b) if scene.jitter > 0.0 && !transmitted && !matches!(hit.material, Material::Sticky(_)) => {
+                            jitter(b, from, hit.tangent, col_point, scene.jitter, scene.reflection_offset, rng)
+                        }
+                        b => b,
+                    };
+                    match next_ball {
+                        Some(b) => {
+                            // The speed of a curved flight goes with the square root of the energy
+                            if let Some(v) = hit_velocity {
+                                let loss = if transmitted { 1.0 } else { restitution.sqrt() };
+                                self.velocity = b.delta().try_normalize().unwrap_or(v) * (v.magnitude() * loss);
+                            }
+                            self.line = b;
+                            self.leaving = [hit.surface, Some(Surface::Trail(scene.trail.laid() - 1))];
+                            if let Some(points) = self.bounce_points.as_mut().filter(|_| !transmitted) {
+                                points.push(col_point);
+                            }
+
+                            match scene.on_cycle.filter(|_| self.revisits(col_point, scene)) {
+                                Some(OnCycle::Stop) => SimStepOutcome::Periodic(col_point),
+                                Some(OnCycle::Perturb(degrees)) => {
+                                    let turned = jitter(b, from, hit.tangent, col_point, degrees.to_radians(), scene.reflection_offset, rng);
+                                    self.line = turned.unwrap_or(b);
+                                    SimStepOutcome::Bounced // continue on another orbit
+                                }
+                                None => SimStepOutcome::Bounced, // continue bouncing
+                            }
+                        }
+
+                        // reflection calculation failed
+                        None => {
+                            SimStepOutcome::Trapped(col_point, Trap::Degenerate) // trapped
+                        }
+                    }
+                }
+            }
+
+            // the step of a curved flight is free, it goes on from its end
+            (None, None) if step.is_some() => {
+                self.fly(ball.delta().magnitude(), step, scene);
+
+                if scene.flight.as_ref().is_some_and(|flight| flight.is_lost(ball.end)) {
+                    SimStepOutcome::Lost
+                } else if scene.is_full() {
+                    SimStepOutcome::Trapped(ball.end, Trap::TrailFull)
+                } else {
+                    scene.trail.extend(self.last_step.replace(ball));
+                    self.velocity = step.unwrap().1;
+                    self.line = Line::new(ball.end, ball.end);
+                    SimStepOutcome::Flew
+                }
+            }
+
+            // no collision, it most likely started outside
+            (None, None) => {
+                SimStepOutcome::Lost
+            }
+        }
+    }
+
+    // Slide up to `distance` along the wall the ball, coming from `from`, hit, the way
+    // it was going, and lay a trail segment along it. Whatever is in the way stops it
+    // early. It detaches in a diffuse direction, away from the wall and from what
+    // stopped it, None when it is stuck in a corner, or the trail is full.
+    fn slide<R: Rng>(&mut self, from: Coord, hit: &WallHit, distance: f64, scene: &mut Scene, rng: &mut R) -> Option<Line>
+    {
+        let tangent = hit.tangent.try_normalize()?;
+        let normal = wall_normal(from, tangent, hit.point);
+        let along = if (hit.point - from).dot_product(tangent) >= 0.0 { tangent } else { -tangent };
+        let start = hit.point + normal * scene.reflection_offset;
+        let leaving = [hit.surface, Some(Surface::Trail(scene.trail.laid() - 1))];
+
+        let (end, away) = match scene.test_ball_leaving(Line::new(start, start + along * distance), &leaving) {
+            Some(block) => (block.point, Some(wall_normal(start, block.tangent.try_normalize()?, block.point))),
+            None => (start + along * distance, None),
+        };
+        self.winding += swept(scene.center, hit.point, end);
+        if let Some(crossings) = self.crossings.as_mut() {
+            crossings.fly(Line::new(hit.point, end));
+        }
+        self.travel(end.euclidean_distance(&hit.point), None, scene);
+        scene.trail.push(Line::new(hit.point, end));
+        if scene.is_full() {
+            return None;
+        }
+
+        let sin: f64 = rng.gen_range(-1.0 .. 1.0);
+        let mut dir = normal * (1.0 - sin * sin).sqrt() + tangent * sin;
+        if let Some(away) = away.filter(|away| dir.dot_product(*away) < 0.0) {
+            dir = dir - away * (2.0 * dir.dot_product(away));
+        }
+        (dir.dot_product(normal) >= 0.0).then(|| departure(end, dir, scene.reflection_offset))
+    }
+
+    // Whether the ball leaves `point` in the direction it goes in now for the second
+    // time, within the cycle window, as far as the cycle tolerance tells them apart
+    fn revisits(&mut self, point: Coord, scene: &Scene) -> bool
+    {
+        let dir = self.line.delta().try_normalize().unwrap_or(self.line.delta());
+        let key = [point.x, point.y, dir.x, dir.y].map(|v| (v / scene.cycle_tolerance).round() as i64);
+        let seen = self.recent.contains(&key);
+        if self.recent.len() >= scene.cycle_window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(key);
+        seen
+    }
+
+    // Fly `distance` along the line, at the speed of the ball, or in the time of the
+    // step of a curved flight
+    fn fly(&mut self, distance: f64, step: Option<(Coord, Coord, f64)>, scene: &Scene)
+    {
+        if let Some(dir) = self.line.delta().try_normalize() {
+            let end = self.line.start + dir * distance;
+            self.winding += swept(scene.center, self.line.start, end);
+            if let Some(crossings) = self.crossings.as_mut() {
+                crossings.fly(Line::new(self.line.start, end));
+            }
+        }
+        self.travel(distance, step, scene);
+    }
+
+    // Fly `distance` without going around the center, for flights that are not along
+    // the line, and that count how far they went around it themselves
+    fn travel(&mut self, distance: f64, step: Option<(Coord, Coord, f64)>, scene: &Scene)
+    {
+        self.path_length += distance;
+        self.tumble_in -= distance;
+        match step {
+            Some((_, _, dt)) => self.time += dt * distance / self.line.delta().magnitude(),
+            None => {
+                self.time += duration(self.speed, distance, scene.deceleration);
+                if scene.deceleration > 0.0 {
+                    self.speed = (self.speed * self.speed - 2.0 * scene.deceleration * distance).max(0.0).sqrt();
+                }
+            }
+        }
+    }
+
+    // When the ball gets `distance` further along its straight flight
+    pub fn arrival(&self, distance: f64, scene: &Scene) -> f64
+    {
+        self.time + duration(self.speed, distance, scene.deceleration)
+    }
+
+    // A ball that split off at `point`, and goes on in direction `dir`, with what
+    // this one did so far, starting as far from the point as after a bounce
+    pub fn branch(&self, point: Coord, dir: Coord, scene: &Scene) -> Ball
+    {
+        Ball { line: departure(point, dir, scene.reflection_offset), ..self.clone() }
+    }
+
+    // What it flew since it laid its last trail segment, up to `end`
+    pub fn unlaid(&self, end: Coord) -> impl Iterator<Item = Line>
+    {
+        self.last_step.into_iter().chain([Line::new(self.line.start, end)])
+    }
+
+    // How the ball ended, if the outcome ended it in the arena
+    pub fn ended(&self, outcome: SimStepOutcome) -> Option<Trajectory>
+    {
+        let (end_pos, termination) = match outcome {
+            SimStepOutcome::Trapped(pt, trap) => (pt, Termination::Trapped(trap)),
+            SimStepOutcome::Absorbed(pt) => (pt, Termination::Absorbed),
+            SimStepOutcome::Stopped(pt) => (pt, Termination::Stopped),
+            SimStepOutcome::BounceLimit(pt) => (pt, Termination::BounceLimit),
+            SimStepOutcome::PathLimit(pt) => (pt, Termination::PathLimit),
+            SimStepOutcome::Returned(pt) => (pt, Termination::Returned),
+            SimStepOutcome::Periodic(pt) => (pt, Termination::Periodic),
+            SimStepOutcome::Escaped(pt) => (pt, Termination::Escaped),
+            SimStepOutcome::Bounced | SimStepOutcome::Flew | SimStepOutcome::Lost => return None,
+        };
+        let end_dir = self.line.delta().try_normalize().unwrap_or(coord! {x: 0.0, y: 0.0});
+        Some(Trajectory { start_pos: self.start_pos, end_pos, end_dir, path_length: self.path_length,
+                          travel_time: self.time, no_bounces: self.no_bounces,
+                          energy: self.energy, termination, bounce_points: bounce_path(&self.bounce_points, end_pos),
+                          winding: self.winding, crossings: self.crossings.as_ref().map_or(0, |crossings| crossings.count),
+                          displacement: (end_pos + self.wrapped - self.start_pos).magnitude(), contact: self.contact,
+                          exit: self.exit })
+    }
+}
+
+
+// Where the ball escapes through the opening it hit: how far along the wall from the
+// center of the opening, in its radius, and the sine of the angle it leaves in
+fn opening_exit(scene: &Scene, hit: &WallHit, ball: Line) -> Option<Exit>
+{
+    let opening = scene.openings.iter().position(|(center, radius)| hit.point.euclidean_distance(center) <= *radius)?;
+    let (center, radius) = scene.openings[opening];
+    let tangent = hit.tangent.try_normalize()?;
+    let dir = ball.delta().try_normalize()?;
+    Some(Exit { opening, position: clamp((hit.point - center).dot_product(tangent) / radius, -1.0, 1.0),
+                sine: clamp(dir.dot_product(tangent), -1.0, 1.0) })
+}
+
+
+// How long a ball with `speed` takes to fly `distance`, slowing down with
+// `deceleration`. It stands still where it stops, it gets nowhere further.
+fn duration(speed: f64, distance: f64, deceleration: f64) -> f64
+{
+    if deceleration == 0.0 {
+        distance / speed
+    } else {
+        (speed - (speed * speed - 2.0 * deceleration * distance).max(0.0).sqrt()) / deceleration
+    }
+}
+
+
+// Simulate one ball. Trapped and absorbed balls are deposited in `canvas` where
+// they ended, balls that escape through an opening, or out of the viewport, in
+// `escapes`, if given, where they escaped. Returns how the ball ended, if it did
+// in the arena.
+pub fn single_simulation<T: Weight, R: Rng>(canvas: &mut Canvas<T>,
+                                            escapes: Option<&mut Canvas<T>>,
+                                            scene: &mut Scene,
+                                            rng: &mut R,
+                                            canvas_shader: &dyn Shader<T>,
+                                            resample_starts: bool) -> Option<Trajectory>
+{
+    // Outside the arena, or inside an obstacle, there is nothing to simulate
+    let (start_pos, rand_dir) = random_start(scene, rng, resample_starts)?;
+    let mut ball = Ball::new(start_pos, rand_dir);
+    ball.bounce_points = scene.record_bounces.then(|| vec![start_pos]);
+    ball.crossings = scene.count_crossings.then(Crossings::default);
+    if let Some(tumble) = &scene.tumble {
+        ball.tumble_in = tumble.free_path(rng);
+    }
+
+    let trajectory = loop {
+        let plan = ball.plan(scene);
+        match ball.advance(plan, scene, rng) {
+            SimStepOutcome::Bounced | SimStepOutcome::Flew => {
+                // keep looping
+            }
+            outcome => break ball.ended(outcome),
+        }
+    };
+
+    if let Some(trajectory) = &trajectory {
+        deposit_ending(canvas, escapes, scene, canvas_shader, &ball, trajectory);
+    }
+
+    // Leave the scene in state that we started with
+    scene.trail.clear();
+    trajectory
+}
+
+
+// Deposit a ball where it ended, or along its trail, in `escapes` when it escaped
+pub fn deposit_ending<T: Weight>(canvas: &mut Canvas<T>,
+                                 escapes: Option<&mut Canvas<T>>,
+                                 scene: &Scene,
+                                 canvas_shader: &dyn Shader<T>,
+                                 ball: &Ball,
+                                 trajectory: &Trajectory)
+{
+    let target = match trajectory.termination {
+        Termination::Escaped => escapes,
+        _ => Some(canvas),
+    };
+    if let Some(target) = target {
+        let value = canvas_shader.shade(trajectory);
+        match scene.deposit {
+            Deposit::End | Deposit::Bounces => deposit_weight(target, scene, trajectory, trajectory.end_pos, value),
+            Deposit::Trail => {
+                for segment in scene.trail.segments().copied().chain(ball.unlaid(trajectory.end_pos)) {
+                    draw_segment(target, segment, value);
+                }
+            }
+        }
+    }
+}
+
+
+// How far along the ball it enters the disc around `center`, if it starts outside it
+fn disc_entry(ball: Line, center: Coord, radius: f64) -> Option<f64>
+{
+    let dir = ball.delta().try_normalize()?;
+    let from_center = ball.start - center;
+    let b = from_center.dot_product(dir);
+    let c = from_center.dot_product(from_center) - radius * radius;
+    let discriminant = b * b - c;
+    if c <= 0.0 || b >= 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    Some(-b - discriminant.sqrt())
+}
+
+
+// Where the ball leaves the box from `lo` to `hi`, how far that is from its start,
+// and where it enters the box again on the opposite side
+fn box_exit(ball: Line, lo: Coord, hi: Coord) -> Option<(Coord, f64, Coord)>
+{
+    let d = ball.delta();
+    let exit_t = |start: f64, delta: f64, lo: f64, hi: f64| -> f64 {
+        if delta > 0.0 {
+            (hi - start) / delta
+        } else if delta < 0.0 {
+            (lo - start) / delta
+        } else {
+            f64::INFINITY
+        }
+    };
+
+    let (t_x, t_y) = (exit_t(ball.start.x, d.x, lo.x, hi.x), exit_t(ball.start.y, d.y, lo.y, hi.y));
+    let t = t_x.min(t_y);
+    if t > 1.0 {
+        return None;
+    }
+
+    // Through a corner, it wraps around both edges
+    let exit = ball.start + d * t;
+    let mut entry = exit;
+    if t_x <= t_y {
+        entry.x = if d.x > 0.0 { lo.x } else { hi.x };
+    }
+    if t_y <= t_x {
+        entry.y = if d.y > 0.0 { lo.y } else { hi.y };
+    }
+    Some((exit, t * d.magnitude(), entry))
+}
+
+
+// Add the weight of a ball to the pixel `pt` where it ended, or where it started, or to
+// those of its bounces, weighted by the bounce exponent, when the scene deposits there
+pub fn deposit_weight<T: Weight>(canvas: &mut Canvas<T>, scene: &Scene, trajectory: &Trajectory, pt: Coord, value: T)
+{
+    match scene.deposit {
+        Deposit::Bounces => {
+            let points = &trajectory.bounce_points;
+            for (k, point) in points.iter().enumerate().take(points.len().saturating_sub(1)).skip(1) {
+                deposit(canvas, folded(scene, *point), value * (k as f64).powf(scene.bounce_exponent));
+            }
+        }
+        _ => {
+            for site in deposit_sites(scene, trajectory, pt) {
+                deposit(canvas, folded(scene, site), value);
+            }
+        }
+    }
+}
+
+
+// The points an end deposit of a ball that ended at `pt` goes to
+fn deposit_sites(scene: &Scene, trajectory: &Trajectory, pt: Coord) -> impl Iterator<Item = Coord>
+{
+    let (at_end, at_start) = match scene.deposit_at {
+        DepositAt::Termination => (true, false),
+        DepositAt::Start => (false, true),
+        DepositAt::Both => (true, true),
+    };
+    at_end.then_some(pt).into_iter().chain(at_start.then_some(trajectory.start_pos))
+}
+
+
+// Add `value` to the pixel at `pt`
+pub fn deposit<T: AddAssign>(canvas: &mut Canvas<T>, pt: Coord, value: T)
+{
+    let idx = pixel_index(canvas, pt);
+    canvas.data[idx] += value;
+}
+
+
+// Keep the smallest `arrival` of the balls that ended in every pixel
+pub fn first_arrival(canvas: &mut Canvas<f64>, scene: &Scene, trajectory: &Trajectory, arrival: Arrival)
+{
+    let Some(idx) = ending_pixel(canvas, scene, trajectory) else {
+        return;
+    };
+    let value = match arrival {
+        Arrival::PathLength => trajectory.path_length,
+        Arrival::Bounces => trajectory.no_bounces as f64,
+        Arrival::TravelTime => trajectory.travel_time,
+    };
+    canvas.data[idx] = canvas.data[idx].min(value);
+}
+
+
+// Count the ball in the pixels its weight went to: where it ended, or started
+pub fn count_hit(canvas: &mut Canvas<f64>, scene: &Scene, trajectory: &Trajectory)
+{
+    if trajectory.termination == Termination::Escaped {
+        return;
+    }
+    for site in deposit_sites(scene, trajectory, trajectory.end_pos) {
+        let idx = pixel_index(canvas, folded(scene, site));
+        canvas.data[idx] += 1.0;
+    }
+}
+
+
+// Add the direction the ball ended in to the pixel it ended in
+pub fn add_direction(canvas: &mut Canvas<Vector2<f64>>, scene: &Scene, trajectory: &Trajectory)
+{
+    if let Some(idx) = ending_pixel(canvas, scene, trajectory) {
+        canvas.data[idx] += Vector2::new(trajectory.end_dir.x, trajectory.end_dir.y);
+    }
+}
+
+
+// Count the ball in the exit map, when it escaped through an opening. The openings are
+// side by side along x, with the position across them, and the sine of the angle it
+// left in goes from 1 at the top to -1 at the bottom.
+pub fn add_exit(canvas: &mut Canvas<f64>, scene: &Scene, trajectory: &Trajectory)
+{
+    if let Some(exit) = trajectory.exit {
+        let x = (exit.opening as f64 + (exit.position + 1.0) / 2.0) / scene.openings.len() as f64;
+        deposit(canvas, coord! {x: x, y: (1.0 - exit.sine) / 2.0}, 1.0);
+    }
+}
+
+
+// The index of the pixel the ball ended in, escaped balls did not end in one
+fn ending_pixel<T>(canvas: &Canvas<T>, scene: &Scene, trajectory: &Trajectory) -> Option<usize>
+{
+    (trajectory.termination != Termination::Escaped).then(|| pixel_index(canvas, folded(scene, trajectory.end_pos)))
+}
+
+
+// The index of the pixel at `pt`, points outside the canvas go to its border
+pub fn pixel_index<T>(canvas: &Canvas<T>, pt: Coord) -> usize
+{
+    let x = clamp(f64::round(pt.x * canvas.width as f64) as usize, 0, canvas.width - 1);
+    let y = clamp(f64::round(pt.y * canvas.height as f64) as usize, 0, canvas.height - 1);
+
+    x + canvas.width * y
+}
+
+
+// Where a ball that ended at `pt` is deposited, in the wedge when the scene is folded
+pub fn folded(scene: &Scene, pt: Coord) -> Coord
+{
+    match &scene.fold {
+        Some(symmetry) => symmetry.fold(pt),
+        None => pt,
+    }
+}
+
+
+pub fn angle(angle: f64) -> Coord
+{
+    coord! {x: f64::cos(angle), y: f64::sin(angle)}
+}
+
+
+// Add `val`, times the length of the segment in every pixel it passes, to that pixel.
+// The length is in pixels, straight across a pixel it is 1. The pixels are those
+// deposit() uses, centered on multiples of the pixel size.
+fn draw_segment<T: Weight>(canvas: &mut Canvas<T>, segment: Line, val: T)
+{
+    let (width, height) = (canvas.width as f64, canvas.height as f64);
+    let start = coord! {x: segment.start.x * width + 0.5, y: segment.start.y * height + 0.5};
+    let d = coord! {x: segment.end.x * width + 0.5, y: segment.end.y * height + 0.5} - start;
+    let length = d.magnitude();
+    if length == 0.0 {
+        return;
+    }
+
+    // From pixel edge to pixel edge, along the segment from t = 0 to 1
+    let (mut x, mut y) = (start.x.floor(), start.y.floor());
+    let first_edge = |p: f64, pixel: f64, d: f64| match d {
+        _ if d > 0.0 => (pixel + 1.0 - p) / d,
+        _ if d < 0.0 => (pixel - p) / d,
+        _ => f64::INFINITY,
+    };
+    let (mut edge_x, mut edge_y) = (first_edge(start.x, x, d.x), first_edge(start.y, y, d.y));
+    let (step_x, step_y) = (d.x.signum(), d.y.signum());
+    let mut t = 0.0;
+    while t < 1.0 {
+        let next = edge_x.min(edge_y).min(1.0);
+        if (0.0..width).contains(&x) && (0.0..height).contains(&y) {
+            canvas.data[x as usize + canvas.width * y as usize] += val * ((next - t) * length);
+        }
+        t = next;
+        if edge_x < edge_y {
+            x += step_x;
+            edge_x += 1.0 / d.x.abs();
+        } else {
+            y += step_y;
+            edge_y += 1.0 / d.y.abs();
+        }
+    }
+}
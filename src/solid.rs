@@ -0,0 +1,396 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use clap::ValueEnum;
+use geo::{Coord, coord, Vector2DOps};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use simple_canvas::Canvas;
+
+use crate::config::{Material, TrailFull};
+use crate::scene::{Contact, Scene};
+use crate::shaders::Shader;
+use crate::simulation::{bounce_path, CONTACT_DISTANCE, deposit_weight, swept, Termination, Trajectory, Trap, Weight};
+use crate::vector::{add, cross, dot, length, normalize, scale, sub, Vector};
+
+const MAX_START_ATTEMPTS: usize = 1000;
+
+const GOLDEN_RATIO: f64 = 1.618033988749895;
+
+
+// The convex polyhedra the ball of solid billiards can bounce in
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Polyhedron {
+    Tetrahedron,
+    Cube,
+    Octahedron,
+    Dodecahedron,
+    Icosahedron,
+}
+
+
+// Where the polyhedron is seen from. It is turned by the yaw around the vertical
+// axis, and then tilted towards the viewer by the pitch.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Camera {
+    pub yaw: f64,                       // in degrees
+    pub pitch: f64,                     // in degrees
+    pub distance: Option<f64>,          // of the camera from the center, for a perspective. None means: orthographic
+    pub zoom: f64,                      // 1 fits the sphere around the polyhedron in the image, without perspective
+}
+
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SolidConfig {
+    pub polyhedron: Polyhedron,         // it fits in a sphere of radius 1
+    pub camera: Camera,
+}
+
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            yaw: 30.0,
+            pitch: 20.0,
+            distance: None,
+            zoom: 0.9,
+        }
+    }
+}
+
+
+impl Default for SolidConfig {
+    fn default() -> Self {
+        SolidConfig {
+            polyhedron: Polyhedron::Cube,
+            camera: Camera::default(),
+        }
+    }
+}
+
+
+impl SolidConfig {
+    pub fn validate(&self) -> Result<(), String>
+    {
+        let camera = &self.camera;
+        if !(camera.yaw.is_finite() && camera.pitch.is_finite()) {
+            return Err("camera yaw and pitch must be finite".to_string());
+        }
+        if !(camera.zoom > 0.0 && camera.zoom.is_finite()) {
+            return Err(format!("camera zoom must be larger than 0, got {}", camera.zoom));
+        }
+        // Outside the sphere around the polyhedron
+        if let Some(distance) = camera.distance.filter(|d| !(*d > 1.0 && d.is_finite())) {
+            return Err(format!("camera distance must be larger than 1, got {}", distance));
+        }
+        Ok(())
+    }
+}
+
+
+impl Polyhedron {
+    // The directions of the faces, not normalized
+    fn face_directions(&self) -> Vec<Vector>
+    {
+        let signs = [1.0, -1.0];
+        // [0, a, b] and its cyclic permutations, with every sign
+        let cyclic = |a: f64, b: f64| -> Vec<Vector> {
+            signs.into_iter().flat_map(|s| signs.into_iter().map(move |t| (s * a, t * b)))
+                .flat_map(|(a, b)| [[0.0, a, b], [a, b, 0.0], [b, 0.0, a]])
+                .collect()
+        };
+        let corners: Vec<Vector> = signs.into_iter()
+            .flat_map(|x| signs.into_iter().flat_map(move |y| signs.into_iter().map(move |z| [x, y, z])))
+            .collect();
+
+        match self {
+            Polyhedron::Tetrahedron => corners.into_iter().filter(|c| c[0] * c[1] * c[2] > 0.0).collect(),
+            Polyhedron::Cube => signs.into_iter().flat_map(|s| [[s, 0.0, 0.0], [0.0, s, 0.0], [0.0, 0.0, s]]).collect(),
+            Polyhedron::Octahedron => corners,
+            // Their faces point to the corners of the other one
+            Polyhedron::Dodecahedron => cyclic(1.0, GOLDEN_RATIO),
+            Polyhedron::Icosahedron => corners.into_iter().chain(cyclic(1.0 / GOLDEN_RATIO, GOLDEN_RATIO)).collect(),
+        }
+    }
+}
+
+
+// Solid billiards: the ball bounces inside a polyhedron in space, and off the trail
+// of segments it leaves there. A thin ball would never hit it, so the ball has a
+// radius, the trail a width, or both. The ball touches a trail segment when its
+// center gets within their sum of it, on the capsule around the segment. Where the
+// ball ends is shown through the camera.
+pub struct Solid {
+    faces: Vec<(Vector, f64)>,          // outward normal and distance from the center
+    extent: Vector,                     // half the size of the box around the corners, along every axis
+    camera: Camera,
+    trail: VecDeque<(Vector, Vector)>,
+}
+
+
+// The point of the segment from `a` to `b` that is closest to `p`
+fn closest_point(p: Vector, a: Vector, b: Vector) -> Vector
+{
+    let ba = sub(b, a);
+    add(a, scale(ba, (dot(sub(p, a), ba) / dot(ba, ba)).clamp(0.0, 1.0)))
+}
+
+
+// How far the ball flies from `start` in direction `dir`, of length 1, before it gets
+// within `radius` of the segment from `a` to `b`. 0 when it is that close already, and
+// not on its way out.
+fn capsule_hit(start: Vector, dir: Vector, a: Vector, b: Vector, radius: f64) -> Option<f64>
+{
+    let (ba, oa) = (sub(b, a), sub(start, a));
+    let (baba, bard, baoa, rdoa, oaoa) = (dot(ba, ba), dot(ba, dir), dot(ba, oa), dot(dir, oa), dot(oa, oa));
+
+    let off = sub(start, closest_point(start, a, b));
+    if length(off) < radius {
+        return (dot(dir, off) < 0.0).then_some(0.0);
+    }
+
+    // The cylinder around the segment
+    let a2 = baba - bard * bard;
+    let b2 = baba * rdoa - baoa * bard;
+    let c2 = baba * oaoa - baoa * baoa - radius * radius * baba;
+    let h = b2 * b2 - a2 * c2;
+    if h < 0.0 {
+        return None;
+    }
+    if a2 > 1e-12 {
+        let t = (-b2 - h.sqrt()) / a2;
+        let y = baoa + t * bard;
+        if y > 0.0 && y < baba {
+            return Some(t).filter(|t| *t >= 0.0);
+        }
+    }
+    // The spheres around its ends
+    [a, b].iter()
+        .filter_map(|end| {
+            let oc = sub(start, *end);
+            let (b3, c3) = (dot(dir, oc), dot(oc, oc) - radius * radius);
+            let h = b3 * b3 - c3;
+            (h >= 0.0).then(|| -b3 - h.sqrt())
+        })
+        .filter(|t| *t >= 0.0)
+        .min_by(|a, b| a.total_cmp(b))
+}
+
+
+impl Solid {
+    pub fn new(config: &SolidConfig) -> Solid
+    {
+        let normals: Vec<Vector> = config.polyhedron.face_directions().into_iter().map(normalize).collect();
+
+        // The corners are where three faces at distance 1 meet, inside all others.
+        // The polyhedron is scaled so the farthest one is at distance 1.
+        let mut radius: f64 = 0.0;
+        let mut extent = [0.0; 3];
+        for (i, a) in normals.iter().enumerate() {
+            for (j, b) in normals.iter().enumerate().skip(i + 1) {
+                for c in &normals[j + 1..] {
+                    let det = dot(*a, cross(*b, *c));
+                    if det.abs() < 1e-9 {
+                        continue;
+                    }
+                    let corner = scale(add(add(cross(*b, *c), cross(*c, *a)), cross(*a, *b)), 1.0 / det);
+                    if normals.iter().all(|n| dot(*n, corner) <= 1.0 + 1e-9) {
+                        radius = radius.max(length(corner));
+                        extent = [0, 1, 2].map(|k| f64::max(extent[k], corner[k].abs()));
+                    }
+                }
+            }
+        }
+        let faces = normals.into_iter().map(|n| (n, 1.0 / radius)).collect();
+
+        Solid { faces, extent: scale(extent, 1.0 / radius), camera: config.camera.clone(), trail: VecDeque::new() }
+    }
+
+    // Where the point in space is in the image
+    pub fn project(&self, p: Vector) -> Coord
+    {
+        let (sin_yaw, cos_yaw) = self.camera.yaw.to_radians().sin_cos();
+        let (sin_pitch, cos_pitch) = self.camera.pitch.to_radians().sin_cos();
+        let (x, z) = (p[0] * cos_yaw + p[2] * sin_yaw, p[2] * cos_yaw - p[0] * sin_yaw);
+        let (y, z) = (p[1] * cos_pitch - z * sin_pitch, p[1] * sin_pitch + z * cos_pitch);
+
+        // The camera looks along -z, towards the center
+        let perspective = self.camera.distance.map_or(1.0, |distance| distance / (distance - z));
+        let s = perspective * self.camera.zoom / 2.0;
+        coord! {x: 0.5 + x * s, y: 0.5 - y * s}
+    }
+
+    // The radius of the sphere that touches the faces
+    pub fn inradius(&self) -> f64
+    {
+        self.faces[0].1
+    }
+
+    // Whether the center of a ball with `radius` can be at the point
+    fn contains(&self, p: Vector, radius: f64) -> bool
+    {
+        self.faces.iter().all(|(n, d)| dot(*n, p) < d - radius)
+    }
+}
+
+
+// Simulate one ball of solid billiards. Of the scene only its parameters count: the
+// trail length and what happens when it is full, the trail material, the limits of
+// bounces and path length, the restitution, the ball radius and the trail width.
+// The ball starts anywhere in the solid, a start outside it is always picked again,
+// the box it is picked in is no arena in the image. Returns how the ball ended, if it
+// could start.
+pub fn solid_simulation<T: Weight, R: Rng>(canvas: &mut Canvas<T>,
+                                           solid: &mut Solid,
+                                           scene: &Scene,
+                                           rng: &mut R,
+                                           canvas_shader: &dyn Shader<T>) -> Option<Trajectory>
+{
+    let r = scene.ball_radius;
+    let extent = solid.extent;
+    let mut pos = (0..MAX_START_ATTEMPTS)
+        .map(|_| [0, 1, 2].map(|k| rng.gen_range(-extent[k] ..= extent[k])))
+        .find(|p| solid.contains(*p, r))?;
+    // Uniform over all directions in space
+    let (z, phi): (f64, f64) = (rng.gen_range(-1.0 .. 1.0), rng.gen_range(0.0 .. PI * 2.0));
+    let mut dir = [(1.0 - z * z).sqrt() * phi.cos(), (1.0 - z * z).sqrt() * phi.sin(), z];
+    let start_pos = solid.project(pos);
+    let start = pos;
+
+    let touch = r + scene.trail_width / 2.0;
+    let mut path_length: f64 = 0.0;
+    let mut no_bounces: usize = 0;
+    let mut energy: f64 = 1.0;
+    let mut winding: f64 = 0.0;
+    let mut bounce_points = scene.record_bounces.then(|| vec![start_pos]);
+    // The trail segments that were forgotten, the others are numbered after them
+    let mut forgotten: usize = 0;
+
+    let (end, termination, contact) = loop {
+        // The face the ball flies to, it always is in one
+        let Some((distance, normal, face)) = solid.faces.iter().enumerate()
+            .filter(|(_, (n, _))| dot(*n, dir) > 0.0)
+            .map(|(i, (n, d))| ((d - r - dot(*n, pos)) / dot(*n, dir), *n, i))
+            .min_by(|a, b| a.0.total_cmp(&b.0)) else {
+            break (pos, Termination::Trapped(Trap::Degenerate), None);
+        };
+        // The last segment ends where the ball starts
+        let previous = solid.trail.len().saturating_sub(1);
+        let trail_hit = solid.trail.iter().enumerate().take(previous)
+            .filter_map(|(k, (a, b))| capsule_hit(pos, dir, *a, *b, touch).map(|t| (t, *a, *b, k)))
+            .filter(|(t, _, _, _)| *t < distance)
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        let reach = trail_hit.map_or(distance, |(t, _, _, _)| t);
+
+        if let Some(max) = scene.max_path_length.filter(|max| path_length + reach > *max) {
+            let end = add(pos, scale(dir, max - path_length));
+            path_length = max;
+            break (end, Termination::PathLimit, None);
+        }
+        path_length += reach;
+        let point = add(pos, scale(dir, reach));
+
+        // The ball bounces off the capsule around the trail segment, like off a wall. The
+        // faces of the solid are its walls.
+        let normal = match trail_hit {
+            Some((_, a, b, _)) => normalize(sub(closest_point(point, a, b), point)),
+            None => normal,
+        };
+        let contact = Some(trail_hit.map_or(Contact::Wall(face), |(_, _, _, k)| Contact::Trail(forgotten + k)));
+        if trail_hit.is_some() && scene.trail_material == Material::Absorb {
+            no_bounces += 1;
+            break (point, Termination::Absorbed, contact);
+        }
+        let full = scene.trail_full == TrailFull::Trap && no_bounces >= scene.max_trail;
+        if reach < CONTACT_DISTANCE {
+            break (point, Termination::Trapped(Trap::SelfHit), contact);
+        }
+        if full {
+            break (point, Termination::Trapped(Trap::TrailFull), contact);
+        }
+        no_bounces += 1;
+        energy *= scene.restitution_off(trail_hit.is_some());
+        if energy < scene.min_energy {
+            break (point, Termination::Stopped, contact);
+        }
+        if scene.max_bounces.is_some_and(|max| no_bounces >= max) {
+            break (point, Termination::BounceLimit, contact);
+        }
+
+        if let Some(points) = bounce_points.as_mut() {
+            points.push(solid.project(point));
+        }
+        winding += swept(scene.center, solid.project(pos), solid.project(point));
+        solid.trail.push_back((pos, point));
+        if scene.trail_full == TrailFull::Forget && solid.trail.len() > scene.max_trail {
+            solid.trail.pop_front();
+            forgotten += 1;
+        }
+        dir = sub(dir, scale(normal, 2.0 * dot(dir, normal)));
+        pos = point;
+    };
+
+    // Leave the solid in state that we started with
+    solid.trail.clear();
+
+    let end_pos = solid.project(end);
+    let end_dir = (end_pos - solid.project(pos)).try_normalize().unwrap_or(coord! {x: 0.0, y: 0.0});
+    let trajectory = Trajectory { start_pos, end_pos, end_dir, path_length, travel_time: path_length, no_bounces, energy, termination,
+                                  bounce_points: bounce_path(&bounce_points, end_pos),
+                                  winding: winding + swept(scene.center, solid.project(pos), end_pos),
+                                  crossings: 0,     // paths in space do not cross
+                                  displacement: length(sub(end, start)), contact, exit: None };
+    deposit_weight(canvas, scene, &trajectory, end_pos, canvas_shader.shade(&trajectory));
+    Some(trajectory)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn assert_hit(hit: Option<f64>, distance: f64)
+    {
+        assert!(hit.is_some_and(|t| (t - distance).abs() < EPSILON), "{:?} is not {}", hit, distance);
+    }
+
+    #[test]
+    fn capsule_is_hit_on_its_side()
+    {
+        let (a, b) = ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        assert_hit(capsule_hit([0.5, 2.0, 0.0], [0.0, -1.0, 0.0], a, b, 0.5), 1.5);
+        // At an angle, the ball reaches the cylinder where it is 0.5 from the axis
+        let dir = normalize([0.0, -1.0, -1.0]);
+        assert_hit(capsule_hit([0.5, 2.0, 2.0], dir, a, b, 0.5), 8.0_f64.sqrt() - 0.5);
+    }
+
+    #[test]
+    fn capsule_is_hit_on_its_ends()
+    {
+        let (a, b) = ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        assert_hit(capsule_hit([-2.0, 0.0, 0.0], [1.0, 0.0, 0.0], a, b, 0.5), 1.5);
+        assert_hit(capsule_hit([3.0, 0.0, 0.0], [-1.0, 0.0, 0.0], a, b, 0.5), 1.5);
+    }
+
+    #[test]
+    fn capsule_is_missed_beside_and_behind_the_ball()
+    {
+        let (a, b) = ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        assert_eq!(capsule_hit([0.5, 2.0, 0.0], [0.0, 0.0, 1.0], a, b, 0.5), None);
+        assert_eq!(capsule_hit([0.5, 2.0, 0.0], [0.0, 1.0, 0.0], a, b, 0.5), None);
+        assert_eq!(capsule_hit([-2.0, 0.0, 0.0], [-1.0, 0.0, 0.0], a, b, 0.5), None);
+    }
+
+    #[test]
+    fn capsule_the_ball_is_in_is_only_hit_on_its_way_in()
+    {
+        let (a, b) = ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        assert_eq!(capsule_hit([0.5, 0.2, 0.0], [0.0, -1.0, 0.0], a, b, 0.5), Some(0.0));
+        assert_eq!(capsule_hit([0.5, 0.2, 0.0], [0.0, 1.0, 0.0], a, b, 0.5), None);
+    }
+}
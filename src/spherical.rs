@@ -0,0 +1,104 @@
+use std::f64::consts::PI;
+
+use geo::{Coord, coord, Line, Vector2DOps};
+
+use crate::config::SphereProjection;
+use crate::geodesic::{flight, Geometry};
+use crate::vector::{add, cross, dot, length, normalize, scale, sub, Vector};
+
+// The chart reaches this far from its center, about 89 degrees around the sphere
+const MAX_CHART_RADIUS: f64 = 50.0;
+
+
+// The geometry of the unit sphere. Its chart is the gnomonic projection of the
+// hemisphere that faces the viewer, from the center of the sphere onto the plane
+// that touches it in the middle of the image, where the geodesics, the great
+// circles, are straight lines. The image shows the sphere in `projection`.
+pub struct Spherical {
+    pub projection: SphereProjection,
+}
+
+
+// The direction from the center of the sphere through the point of the chart
+fn lift(c: Coord) -> Vector
+{
+    [c.x, c.y, 1.0]
+}
+
+
+// The point of the chart in that direction, on the side of the viewer
+fn chart(v: Vector) -> Option<Coord>
+{
+    let c = coord! {x: v[0] / v[2], y: v[1] / v[2]};
+    (v[2] > 0.0 && c.magnitude() < MAX_CHART_RADIUS).then_some(c)
+}
+
+
+impl Spherical {
+    // The point of the image on the sphere, None where the image shows no sphere
+    fn sphere(&self, p: Coord) -> Option<Vector>
+    {
+        match self.projection {
+            SphereProjection::Orthographic => {
+                let u = (p - coord! {x: 0.5, y: 0.5}) * 2.0;
+                (u.magnitude_squared() < 1.0).then(|| [u.x, u.y, (1.0 - u.magnitude_squared()).sqrt()])
+            }
+            SphereProjection::Equirectangular => {
+                let (longitude, latitude) = ((p.x - 0.5) * 2.0 * PI, (0.5 - p.y) * PI);
+                Some([latitude.cos() * longitude.sin(), -latitude.sin(), latitude.cos() * longitude.cos()])
+            }
+        }
+    }
+}
+
+
+impl Geometry for Spherical {
+    fn to_chart(&self, p: Coord) -> Option<Coord>
+    {
+        chart(self.sphere(p)?)
+    }
+
+    fn to_image(&self, c: Coord) -> Coord
+    {
+        let v = normalize(lift(c));
+        match self.projection {
+            SphereProjection::Orthographic => coord! {x: 0.5 + v[0] / 2.0, y: 0.5 + v[1] / 2.0},
+            SphereProjection::Equirectangular => {
+                let (longitude, latitude) = (v[0].atan2(v[2]), (-v[1]).asin());
+                coord! {x: 0.5 + longitude / (2.0 * PI), y: 0.5 - latitude / PI}
+            }
+        }
+    }
+
+    // The angle between the directions of the points from the center
+    fn distance(&self, a: Coord, b: Coord) -> f64
+    {
+        let (a, b) = (lift(a), lift(b));
+        length(cross(a, b)).atan2(dot(a, b))
+    }
+
+    // At `direction` in the plane that touches the sphere at the point
+    fn start_direction(&self, p: Coord, direction: f64) -> Option<Coord>
+    {
+        let s = self.sphere(p)?;
+        let up = if s[1].abs() < 0.9 { [0.0, 1.0, 0.0] } else { [1.0, 0.0, 0.0] };
+        let e1 = normalize(cross(s, up));
+        let e2 = cross(s, e1);
+        let (sin, cos) = direction.sin_cos();
+        let step = add(s, scale(add(scale(e1, cos), scale(e2, sin)), 1e-6));
+        (chart(step)? - chart(s)?).try_normalize()
+    }
+
+    // The great circle of the wall lies in a plane through the center of the sphere,
+    // the ball is reflected in that plane. A point just before the wall, on the way
+    // the ball came, is reflected, the ball goes on along the reflected line.
+    fn reflection(&self, ball: Coord, tangent: Coord, intersection: Coord) -> Option<Line>
+    {
+        let n = cross(lift(intersection), lift(intersection + tangent));
+        let before = lift(intersection + (ball - intersection).try_normalize()? * 1e-3);
+        let s = 2.0 * dot(before, n) / dot(n, n);
+        let mirrored = chart(sub(before, scale(n, s)))?;
+
+        Some(flight(intersection, (intersection - mirrored).try_normalize()?))
+    }
+}
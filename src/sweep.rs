@@ -0,0 +1,72 @@
+use clap::ValueEnum;
+
+use crate::config::RunConfig;
+
+
+// Parameters that can be varied over a series of runs
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum SweepParam {
+    Edges,
+    ArenaSize,
+    Eccentricity,
+    Rotation,
+}
+
+
+impl SweepParam {
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            SweepParam::Edges => "edges",
+            SweepParam::ArenaSize => "arena-size",
+            SweepParam::Eccentricity => "eccentricity",
+            SweepParam::Rotation => "rotation",
+        }
+    }
+
+    // All values from `from` to `to`, both included. Integer parameters default to
+    // every integer in between, others need an explicit number of steps.
+    pub fn values(&self, from: f64, to: f64, steps: Option<usize>) -> Result<Vec<f64>, String>
+    {
+        let is_integer = matches!(self, SweepParam::Edges);
+
+        let steps = match steps {
+            Some(steps) => steps,
+            None if is_integer => (to - from).abs().round() as usize + 1,
+            None => return Err(format!("sweeping {} needs --steps", self.name()))
+        };
+        if steps == 0 {
+            return Err("a sweep needs at least one step".to_string());
+        }
+
+        let mut values: Vec<f64> = (0..steps)
+            .map(|i| if steps == 1 { from } else { from + (to - from) * i as f64 / (steps - 1) as f64 })
+            .collect();
+
+        if is_integer {
+            values.iter_mut().for_each(|v| *v = v.round());
+            values.dedup();
+        }
+
+        Ok(values)
+    }
+
+    pub fn apply(&self, config: &mut RunConfig, value: f64)
+    {
+        match self {
+            SweepParam::Edges => config.arena.edges = value as usize,
+            SweepParam::ArenaSize => config.arena.size = value,
+            SweepParam::Eccentricity => config.arena.eccentricity = value,
+            SweepParam::Rotation => config.arena.transform.rotation = value,
+        }
+    }
+
+    // The value as it should appear in a file name
+    pub fn format(&self, value: f64) -> String
+    {
+        match self {
+            SweepParam::Edges => format!("{}", value as usize),
+            SweepParam::ArenaSize | SweepParam::Eccentricity | SweepParam::Rotation => format!("{:.4}", value),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::f64::consts::PI;
+use std::ops::Div;
+
+use cgmath::num_traits::Zero;
+use geo::{Coord, coord, Vector2DOps};
+use simple_canvas::Canvas;
+
+use crate::config::{ArenaConfig, ArenaKind, RunConfig};
+use crate::parametric::Curve;
+use crate::simulation::{angle, pixel_index};
+
+// Circles are folded as if they were polygons with this many edges
+const CIRCLE_ORDER: usize = 360;
+
+
+// The rotations, and possibly reflections, that map an arena onto itself
+#[derive(Debug, Clone, Copy)]
+pub struct Symmetry {
+    pub center: Coord,
+    pub order: usize,       // the arena is the same after a rotation by 1 / order of a turn
+    pub mirror: bool,       // and mirrored in the axis
+    pub axis: f64,          // angle of the mirror axis, or of an edge of the wedge without one
+}
+
+
+// The symmetry of a generated arena. Scatterers have to be in the center, and
+// obstacles, openings, regions and moving walls, that are not generated with the
+// arena, are not allowed.
+pub fn arena_symmetry(config: &ArenaConfig) -> Result<Symmetry, String>
+{
+    let t = &config.transform;
+    if t.anisotropy[0] != t.anisotropy[1] {
+        return Err("a stretched arena can not be folded, use the same anisotropy along x and y".to_string());
+    }
+    if !config.obstacles.is_empty() || !config.openings.is_empty() || !config.regions.is_empty() || !config.moving_walls.is_empty() {
+        return Err("an arena with obstacles, openings, regions or moving walls can not be folded".to_string());
+    }
+
+    let center = coord! {x: config.center[0] + t.offset[0], y: config.center[1] + t.offset[1]};
+    if config.scatterers.iter().any(|s| (coord! {x: s.center[0], y: s.center[1]} - center).magnitude() > 1e-9) {
+        return Err("only scatterers in the center of the arena keep its symmetry".to_string());
+    }
+
+    let (order, mirror, axis) = match config.kind {
+        ArenaKind::Polygon if config.morph.is_some() => return Err("a morphed polygon can not be folded".to_string()),
+        ArenaKind::Polygon | ArenaKind::Star | ArenaKind::Koch | ArenaKind::Cesaro => (config.edges, true, 0.0),
+        ArenaKind::Circle => (CIRCLE_ORDER, true, 0.0),
+        ArenaKind::Ellipse | ArenaKind::Stadium => (2, true, 0.0),
+        ArenaKind::Mushroom => (1, true, PI / 2.0),
+        ArenaKind::Lens if config.disc_radii[0] == config.disc_radii[1] => (2, true, 0.0),
+        ArenaKind::Lens | ArenaKind::Lune => (1, true, 0.0),
+        ArenaKind::Parametric => {
+            // Curves are scaled to fit by their extent, odd roses are off center then
+            let order = match &config.curve {
+                Some(Curve::Superellipse { .. }) => 2,
+                Some(Curve::Rose { petals, .. }) if petals.is_multiple_of(2) => *petals,
+                _ => return Err("only superellipses and roses with an even number of petals can be folded".to_string()),
+            };
+            if !config.curve_resolution.is_multiple_of(order) {
+                return Err(format!("the curve resolution has to be a multiple of {} to fold", order));
+            }
+            (order, true, 0.0)
+        }
+        _ => return Err(format!("arena kind {:?} has no symmetry to fold", config.kind)),
+    };
+
+    Ok(Symmetry { center, order, mirror, axis: axis + t.rotation.to_radians() })
+}
+
+
+impl Symmetry {
+    // Map a point onto its copy in the fundamental wedge, that starts at the axis
+    pub fn fold(&self, point: Coord) -> Coord
+    {
+        let d = point - self.center;
+        let wedge = 2.0 * PI / self.order as f64;
+
+        let mut a = (d.y.atan2(d.x) - self.axis).rem_euclid(wedge);
+        if self.mirror && a > wedge / 2.0 {
+            a = wedge - a;
+        }
+        self.center + angle(self.axis + a) * d.magnitude()
+    }
+}
+
+
+// The full image of a canvas that was accumulated folded into one wedge. Every
+// pixel gets the value of the wedge pixel it folds onto, divided by the number of
+// pixels that fold onto it, so the total stays the same.
+pub fn unfold<T: Copy + Zero + Div<f64, Output = T>>(canvas: &Canvas<T>, symmetry: &Symmetry) -> Canvas<T>
+{
+    let sources: Vec<usize> = (0..canvas.width * canvas.height)
+        .map(|i| {
+            let pixel = coord! {x: (i % canvas.width) as f64 / canvas.width as f64,
+                                y: (i / canvas.width) as f64 / canvas.height as f64};
+            pixel_index(canvas, symmetry.fold(pixel))
+        })
+        .collect();
+
+    let mut coverage = vec![0usize; canvas.data.len()];
+    for source in &sources {
+        coverage[*source] += 1;
+    }
+
+    let mut unfolded = Canvas::new(canvas.width, canvas.height, T::zero());
+    for (target, source) in unfolded.data.iter_mut().zip(&sources) {
+        *target = canvas.data[*source] / coverage[*source] as f64;
+    }
+    unfolded
+}
+
+
+// The canvas as it should be shown, unfolded when the run was folded
+pub fn image_canvas<T: Copy + Zero + Div<f64, Output = T>>(canvas: &Canvas<T>, config: &RunConfig) -> Canvas<T>
+{
+    match (config.fold_symmetry, arena_symmetry(&config.arena)) {
+        (true, Ok(symmetry)) => unfold(canvas, &symmetry),
+        _ => canvas.clone(),
+    }
+}
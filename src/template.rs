@@ -0,0 +1,84 @@
+use chrono::prelude::*;
+use clap::ValueEnum;
+
+use crate::config::RunConfig;
+
+
+// Fill in the {placeholders} of a template. Use {{ and }} for literal braces.
+pub fn render(template: &str, vars: &[(&str, String)]) -> Result<String, String>
+{
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unclosed placeholder in template '{}'", template))
+                    }
+                }
+                match vars.iter().find(|(var, _)| *var == name) {
+                    Some((_, value)) => result.push_str(value),
+                    None => {
+                        let names: Vec<&str> = vars.iter().map(|(var, _)| *var).collect();
+                        return Err(format!("unknown placeholder {{{}}} in template '{}', available are: {}",
+                                           name, template, names.join(", ")));
+                    }
+                }
+            }
+            '}' => return Err(format!("unmatched }} in template '{}'", template)),
+            c => result.push(c)
+        }
+    }
+
+    Ok(result)
+}
+
+
+// File name, without extension, for the output of a run. `name` is the name of a
+// run in a batch, and `sweep` the parameter value in a sweep, both can be empty.
+// They are appended when the template does not use them, to keep names unique.
+pub fn output_stem(config: &RunConfig, name: &str, sweep: &str) -> Result<String, String>
+{
+    let mut template = config.output.name.clone();
+    if !name.is_empty() && !template.contains("{name}") {
+        template.push_str("-{name}");
+    }
+    if !sweep.is_empty() && !template.contains("{sweep}") {
+        template.push_str("-{sweep}");
+    }
+
+    render(&template, &run_vars(config, name, sweep))
+}
+
+
+// The placeholders every output file name template can use
+pub fn run_vars(config: &RunConfig, name: &str, sweep: &str) -> Vec<(&'static str, String)>
+{
+    vec![
+        ("name", name.to_string()),
+        ("sweep", sweep.to_string()),
+        ("date", Local::now().to_string()),
+        ("arena", config.arena.kind.to_possible_value().unwrap().get_name().to_string()),
+        ("edges", config.arena.edges.to_string()),
+        ("size", config.arena.size.to_string()),
+        ("sims", config.sims.to_string()),
+        ("duration", config.duration.map(|d| humantime::format_duration(d).to_string()).unwrap_or_default()),
+        ("shader", config.shader.clone()),
+        ("seed", config.seed.map(|s| s.to_string()).unwrap_or_default()),
+        ("width", config.width.to_string()),
+        ("height", config.height.to_string()),
+    ]
+}
@@ -0,0 +1,44 @@
+// Vectors in space, for the geometries that need a third dimension
+pub type Vector = [f64; 3];
+
+
+pub fn add(a: Vector, b: Vector) -> Vector
+{
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+
+pub fn sub(a: Vector, b: Vector) -> Vector
+{
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+
+pub fn scale(a: Vector, s: f64) -> Vector
+{
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+
+pub fn dot(a: Vector, b: Vector) -> f64
+{
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+
+pub fn cross(a: Vector, b: Vector) -> Vector
+{
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+
+pub fn length(a: Vector) -> f64
+{
+    dot(a, a).sqrt()
+}
+
+
+pub fn normalize(a: Vector) -> Vector
+{
+    scale(a, 1.0 / length(a))
+}